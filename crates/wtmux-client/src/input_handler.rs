@@ -1,10 +1,15 @@
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use wtmux_config::keybindings::{Key, KeyBinding, KeyTable, Modifiers};
+use crossterm::event::{KeyCode, KeyEvent, KeyEventState, KeyModifiers};
+use std::collections::HashSet;
+use wtmux_config::keybindings::{
+    is_keypad_char, Key, KeyBinding, KeyEventKind, KeyTable, Modifiers,
+};
 
 /// The result of processing a key event.
 pub enum KeyAction {
     /// Send raw bytes to the server (PTY input).
     SendBytes(Vec<u8>),
+    /// Send a key to the active pane.
+    SendKey(KeyBinding, KeyEventKind),
     /// Execute a command string.
     Command(String),
     /// Detach from the session.
@@ -19,6 +24,9 @@ pub struct InputHandler {
     key_table: KeyTable,
     command_buffer: String,
     in_command_prompt: bool,
+    /// Keys whose press went to the prefix, a binding or the command
+    /// prompt, so that their release is not forwarded either.
+    consumed: HashSet<KeyCode>,
 }
 
 enum InputState {
@@ -33,19 +41,40 @@ impl InputHandler {
             key_table: KeyTable::default_tmux_bindings(),
             command_buffer: String::new(),
             in_command_prompt: false,
+            consumed: HashSet::new(),
         }
     }
 
     pub fn handle_key(&mut self, event: KeyEvent) -> KeyAction {
-        // Command prompt mode
-        if self.in_command_prompt {
-            return self.handle_command_prompt_key(event);
+        // Key releases only reach applications that asked for them with the
+        // kitty keyboard protocol; they never drive prompts or bindings.
+        if event.kind == crossterm::event::KeyEventKind::Release {
+            if self.consumed.remove(&release_code(event.code)) {
+                return KeyAction::None;
+            }
+            return match self.state {
+                InputState::Normal if !self.in_command_prompt => forward_key(event),
+                _ => KeyAction::None,
+            };
         }
 
-        match self.state {
-            InputState::Normal => self.handle_normal(event),
-            InputState::PrefixReceived => self.handle_prefix(event),
+        let action = if self.in_command_prompt {
+            // Command prompt mode
+            self.handle_command_prompt_key(event)
+        } else {
+            match self.state {
+                InputState::Normal => self.handle_normal(event),
+                InputState::PrefixReceived => self.handle_prefix(event),
+            }
+        };
+        // A later press that is forwarded owns the release again, in case
+        // the terminal did not report the earlier one.
+        if matches!(action, KeyAction::SendKey(..)) {
+            self.consumed.remove(&release_code(event.code));
+        } else {
+            self.consumed.insert(release_code(event.code));
         }
+        action
     }
 
     fn handle_normal(&mut self, event: KeyEvent) -> KeyAction {
@@ -56,8 +85,7 @@ impl InputHandler {
             return KeyAction::None;
         }
 
-        // Convert key event to bytes
-        key_event_to_bytes(event)
+        forward_key(event)
     }
 
     fn handle_prefix(&mut self, event: KeyEvent) -> KeyAction {
@@ -84,7 +112,7 @@ impl InputHandler {
         }
 
         // If no binding matched, send the key as regular input
-        key_event_to_bytes(event)
+        forward_key(event)
     }
 
     fn handle_command_prompt_key(&mut self, event: KeyEvent) -> KeyAction {
@@ -134,16 +162,20 @@ fn crossterm_to_binding(event: KeyEvent) -> Option<KeyBinding> {
     let modifiers = Modifiers {
         ctrl: event.modifiers.contains(KeyModifiers::CONTROL),
         alt: event.modifiers.contains(KeyModifiers::ALT),
-        shift: event.modifiers.contains(KeyModifiers::SHIFT),
+        shift: event.modifiers.contains(KeyModifiers::SHIFT) || event.code == KeyCode::BackTab,
     };
 
+    let keypad = event.state.contains(KeyEventState::KEYPAD);
     let key = match event.code {
+        KeyCode::Char(c) if keypad && is_keypad_char(c) => Key::Keypad(c),
+        KeyCode::Enter if keypad => Key::KeypadEnter,
         KeyCode::Char(c) => Key::Char(c),
         KeyCode::F(n) => Key::F(n),
         KeyCode::Enter => Key::Enter,
         KeyCode::Esc => Key::Escape,
         KeyCode::Backspace => Key::Backspace,
         KeyCode::Tab => Key::Tab,
+        KeyCode::BackTab => Key::Tab,
         KeyCode::Up => Key::Up,
         KeyCode::Down => Key::Down,
         KeyCode::Left => Key::Left,
@@ -160,64 +192,25 @@ fn crossterm_to_binding(event: KeyEvent) -> Option<KeyBinding> {
     Some(KeyBinding { key, modifiers })
 }
 
-/// Convert a crossterm KeyEvent to raw terminal bytes.
-fn key_event_to_bytes(event: KeyEvent) -> KeyAction {
-    let bytes = match event.code {
-        KeyCode::Char(c) => {
-            if event.modifiers.contains(KeyModifiers::CONTROL) {
-                // Ctrl+A = 0x01, Ctrl+B = 0x02, etc.
-                if c.is_ascii_lowercase() {
-                    vec![c as u8 - b'a' + 1]
-                } else if c.is_ascii_uppercase() {
-                    vec![c as u8 - b'A' + 1]
-                } else {
-                    let mut buf = [0u8; 4];
-                    let s = c.encode_utf8(&mut buf);
-                    s.as_bytes().to_vec()
-                }
-            } else if event.modifiers.contains(KeyModifiers::ALT) {
-                let mut bytes = vec![0x1b]; // ESC prefix for Alt
-                let mut buf = [0u8; 4];
-                let s = c.encode_utf8(&mut buf);
-                bytes.extend_from_slice(s.as_bytes());
-                bytes
-            } else {
-                let mut buf = [0u8; 4];
-                let s = c.encode_utf8(&mut buf);
-                s.as_bytes().to_vec()
-            }
-        }
-        KeyCode::Enter => vec![0x0D],
-        KeyCode::Backspace => vec![0x7F],
-        KeyCode::Tab => vec![0x09],
-        KeyCode::Esc => vec![0x1B],
-        KeyCode::Up => b"\x1b[A".to_vec(),
-        KeyCode::Down => b"\x1b[B".to_vec(),
-        KeyCode::Right => b"\x1b[C".to_vec(),
-        KeyCode::Left => b"\x1b[D".to_vec(),
-        KeyCode::Home => b"\x1b[H".to_vec(),
-        KeyCode::End => b"\x1b[F".to_vec(),
-        KeyCode::PageUp => b"\x1b[5~".to_vec(),
-        KeyCode::PageDown => b"\x1b[6~".to_vec(),
-        KeyCode::Insert => b"\x1b[2~".to_vec(),
-        KeyCode::Delete => b"\x1b[3~".to_vec(),
-        KeyCode::F(n) => match n {
-            1 => b"\x1bOP".to_vec(),
-            2 => b"\x1bOQ".to_vec(),
-            3 => b"\x1bOR".to_vec(),
-            4 => b"\x1bOS".to_vec(),
-            5 => b"\x1b[15~".to_vec(),
-            6 => b"\x1b[17~".to_vec(),
-            7 => b"\x1b[18~".to_vec(),
-            8 => b"\x1b[19~".to_vec(),
-            9 => b"\x1b[20~".to_vec(),
-            10 => b"\x1b[21~".to_vec(),
-            11 => b"\x1b[23~".to_vec(),
-            12 => b"\x1b[24~".to_vec(),
-            _ => return KeyAction::None,
-        },
-        _ => return KeyAction::None,
-    };
+/// The key a press is matched to its release by. Shift may be let go
+/// first, so letters are compared without case.
+fn release_code(code: KeyCode) -> KeyCode {
+    match code {
+        KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+        KeyCode::BackTab => KeyCode::Tab,
+        code => code,
+    }
+}
 
-    KeyAction::SendBytes(bytes)
+/// Forward a key to the active pane; the server encodes it.
+fn forward_key(event: KeyEvent) -> KeyAction {
+    let kind = match event.kind {
+        crossterm::event::KeyEventKind::Press => KeyEventKind::Press,
+        crossterm::event::KeyEventKind::Repeat => KeyEventKind::Repeat,
+        crossterm::event::KeyEventKind::Release => KeyEventKind::Release,
+    };
+    match crossterm_to_binding(event) {
+        Some(binding) => KeyAction::SendKey(binding, kind),
+        None => KeyAction::None,
+    }
 }
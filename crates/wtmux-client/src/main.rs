@@ -10,7 +10,7 @@ use tokio::io::AsyncReadExt;
 use tracing::{debug, error, info};
 use tracing_subscriber::EnvFilter;
 use wtmux_common::ipc::{connect_client, recv_message, send_message};
-use wtmux_common::protocol::SessionTarget;
+use wtmux_common::protocol::{ClientFeatures, SessionTarget};
 use wtmux_common::{pipe_name, ClientMessage, ServerMessage};
 
 use input_handler::InputHandler;
@@ -97,6 +97,7 @@ async fn main() -> Result<()> {
                     command,
                     cols,
                     rows,
+                    features: detect_features(),
                 },
             )
             .await?;
@@ -132,6 +133,7 @@ async fn main() -> Result<()> {
                     session: session_target,
                     cols,
                     rows,
+                    features: detect_features(),
                 },
             )
             .await?;
@@ -236,6 +238,7 @@ async fn run_interactive(
         crossterm::event::DisableMouseCapture,
         terminal::Clear(ClearType::All),
         cursor::MoveTo(0, 0),
+        cursor::SetCursorStyle::DefaultUserShape,
         cursor::Show
     )?;
     println!("[detached]");
@@ -247,7 +250,7 @@ async fn interactive_loop(
     pipe: &mut tokio::net::windows::named_pipe::NamedPipeClient,
     input_handler: &mut InputHandler,
 ) -> Result<()> {
-    use crossterm::event::{self, Event, KeyEventKind, MouseEventKind as CMouseEventKind};
+    use crossterm::event::{self, Event, MouseEventKind as CMouseEventKind};
     use wtmux_common::protocol::MouseEventKind;
 
     let mut stdout = io::stdout();
@@ -256,12 +259,20 @@ async fn interactive_loop(
         // Poll for terminal events with a short timeout
         if event::poll(std::time::Duration::from_millis(10))? {
             match event::read()? {
-                Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
+                Event::Key(key_event) => {
                     // Process through input handler (handles prefix key, bindings)
                     match input_handler.handle_key(key_event) {
                         input_handler::KeyAction::SendBytes(bytes) => {
                             send_message(pipe, &ClientMessage::Input(bytes)).await?;
                         }
+                        input_handler::KeyAction::SendKey(binding, kind) => {
+                            let msg = ClientMessage::Key {
+                                key: binding.key,
+                                modifiers: binding.modifiers,
+                                kind,
+                            };
+                            send_message(pipe, &msg).await?;
+                        }
                         input_handler::KeyAction::Command(cmd) => {
                             send_message(pipe, &ClientMessage::Command(cmd)).await?;
                         }
@@ -276,6 +287,12 @@ async fn interactive_loop(
                         CMouseEventKind::Down(crossterm::event::MouseButton::Left) => {
                             Some(MouseEventKind::Click)
                         }
+                        CMouseEventKind::Up(crossterm::event::MouseButton::Left) => {
+                            Some(MouseEventKind::Release)
+                        }
+                        CMouseEventKind::Drag(crossterm::event::MouseButton::Left) => {
+                            Some(MouseEventKind::Drag)
+                        }
                         CMouseEventKind::ScrollUp => Some(MouseEventKind::ScrollUp),
                         CMouseEventKind::ScrollDown => Some(MouseEventKind::ScrollDown),
                         _ => None,
@@ -295,7 +312,7 @@ async fn interactive_loop(
                 Event::Resize(cols, rows) => {
                     send_message(pipe, &ClientMessage::Resize { cols, rows }).await?;
                 }
-                _ => {} // Ignore key release/repeat, focus events
+                _ => {} // Ignore focus events
             }
         }
 
@@ -386,6 +403,19 @@ async fn ensure_server_and_connect(
     anyhow::bail!("Server failed to start within timeout");
 }
 
+/// What the terminal the client runs in can show, judged from the
+/// environment it sets.
+fn detect_features() -> ClientFeatures {
+    let term = std::env::var("TERM").unwrap_or_default().to_lowercase();
+    let program = std::env::var("TERM_PROGRAM").unwrap_or_default().to_lowercase();
+    let known = ["kitty", "wezterm", "foot", "ghostty", "alacritty", "vte"];
+    ClientFeatures {
+        // Windows Terminal draws curly underlines and underline colors.
+        styled_underlines: std::env::var_os("WT_SESSION").is_some()
+            || known.iter().any(|name| term.contains(name) || program.contains(name)),
+    }
+}
+
 /// Start the server as a detached background process.
 async fn start_server() -> Result<()> {
     let exe_path = std::env::current_exe()?;
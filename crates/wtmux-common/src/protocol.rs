@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use wtmux_config::keybindings::{Key, KeyEventKind, Modifiers};
 
 use crate::{SessionId, WindowId};
 
@@ -11,6 +12,7 @@ pub enum ClientMessage {
         command: Option<String>,
         cols: u16,
         rows: u16,
+        features: ClientFeatures,
     },
 
     /// Attach to an existing session.
@@ -18,6 +20,7 @@ pub enum ClientMessage {
         session: SessionTarget,
         cols: u16,
         rows: u16,
+        features: ClientFeatures,
     },
 
     /// Detach from the current session.
@@ -26,6 +29,14 @@ pub enum ClientMessage {
     /// Send keyboard input to the active pane.
     Input(Vec<u8>),
 
+    /// A key for the active pane, encoded by the server for the keyboard
+    /// protocol its application asked for.
+    Key {
+        key: Key,
+        modifiers: Modifiers,
+        kind: KeyEventKind,
+    },
+
     /// Resize the client terminal.
     Resize { cols: u16, rows: u16 },
 
@@ -104,6 +115,10 @@ pub enum ClientMessage {
 pub enum MouseEventKind {
     /// Left click.
     Click,
+    /// Left button released.
+    Release,
+    /// Motion with the left button held down.
+    Drag,
     /// Scroll up.
     ScrollUp,
     /// Scroll down.
@@ -147,6 +162,14 @@ pub enum ServerMessage {
     Notification(String),
 }
 
+/// What the client's terminal can show, so that frames only use sequences
+/// it understands.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ClientFeatures {
+    /// Underline styles (`4:x`) and underline colors (SGR 58).
+    pub styled_underlines: bool,
+}
+
 /// How to target a session (by name or ID).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SessionTarget {
@@ -189,11 +212,23 @@ pub enum CopyModeAction {
     Bottom,
     StartOfLine,
     EndOfLine,
+    /// Jump to the previous shell prompt recorded with OSC 133.
+    PreviousPrompt,
+    /// Jump to the next shell prompt recorded with OSC 133.
+    NextPrompt,
     StartSelection,
     CopySelection,
+    /// Copy the URI of the hyperlink under the cursor.
+    CopyHyperlink,
     CancelSelection,
+    /// Search forward for a regular expression.
     SearchForward(String),
+    /// Search backward for a regular expression.
     SearchBackward(String),
+    /// Search forward for plain text.
+    SearchForwardText(String),
+    /// Search backward for plain text.
+    SearchBackwardText(String),
     SearchNext,
     SearchPrev,
     Exit,
@@ -206,5 +241,7 @@ pub struct WindowInfo {
     pub index: usize,
     pub name: String,
     pub active: bool,
+    /// A pane rang the bell since the window was last current.
+    pub bell: bool,
     pub pane_count: usize,
 }
@@ -28,6 +28,10 @@ pub enum Key {
     Insert,
     Delete,
     Space,
+    /// A digit or operator key on the numeric keypad (`0`-`9`, `.`, `+`,
+    /// `-`, `*`, `/`, `=`).
+    Keypad(char),
+    KeypadEnter,
 }
 
 /// Key modifiers.
@@ -38,6 +42,14 @@ pub struct Modifiers {
     pub shift: bool,
 }
 
+/// Whether a key was pressed, auto-repeated or released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyEventKind {
+    Press,
+    Repeat,
+    Release,
+}
+
 /// A table of key bindings mapping keys to command strings.
 pub struct KeyTable {
     /// The prefix key (default: Ctrl-B).
@@ -133,6 +145,7 @@ impl KeyTable {
 
         // Other
         table.bind(Key::Char('t'), Modifiers::default(), "clock-mode");
+        table.bind(Key::Char('r'), Modifiers::default(), "refresh-client");
         table.bind(Key::Char('?'), Modifiers::default(), "list-keys");
         table.bind(Key::Char('o'), Modifiers::default(), "select-pane -t :.+");
         table.bind(Key::Char(';'), Modifiers::default(), "last-pane");
@@ -194,6 +207,14 @@ pub fn parse_key(s: &str) -> Option<KeyBinding> {
         "Space" | "space" => Key::Space,
         "Backspace" | "BSpace" | "bspace" => Key::Backspace,
         "Tab" | "tab" => Key::Tab,
+        "BTab" | "btab" => {
+            modifiers.shift = true;
+            Key::Tab
+        }
+        "KPEnter" => Key::KeypadEnter,
+        s if s.len() == 3 && s.starts_with("KP") && s[2..].chars().all(is_keypad_char) => {
+            Key::Keypad(s.chars().nth(2).unwrap())
+        }
         "Up" | "up" => Key::Up,
         "Down" | "down" => Key::Down,
         "Left" | "left" => Key::Left,
@@ -213,3 +234,8 @@ pub fn parse_key(s: &str) -> Option<KeyBinding> {
 
     Some(KeyBinding { key, modifiers })
 }
+
+/// Whether `c` names a key on the numeric keypad.
+pub fn is_keypad_char(c: char) -> bool {
+    c.is_ascii_digit() || matches!(c, '.' | '+' | '-' | '*' | '/' | '=')
+}
@@ -5,4 +5,4 @@ pub mod parser;
 
 pub use config::Config;
 pub use keybindings::{KeyBinding, KeyTable};
-pub use options::Options;
+pub use options::{AllowPassthrough, BellAction, Options, SetClipboard, VisualBell};
@@ -1,3 +1,137 @@
+/// What to do with clipboard text set by applications with OSC 52.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetClipboard {
+    /// Ignore the request.
+    Off,
+    /// Store the text in a paste buffer.
+    Internal,
+    /// Store it in a paste buffer and pass it on to the client terminals.
+    External,
+}
+
+impl SetClipboard {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "off" => Ok(SetClipboard::Off),
+            "internal" => Ok(SetClipboard::Internal),
+            "external" | "on" => Ok(SetClipboard::External),
+            _ => Err(format!("Invalid set-clipboard value: {}", value)),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SetClipboard::Off => "off",
+            SetClipboard::Internal => "internal",
+            SetClipboard::External => "external",
+        }
+    }
+}
+
+/// Whether applications may pass sequences through to the client
+/// terminals with `DCS tmux; ... ST`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllowPassthrough {
+    Off,
+    /// Only from panes the client is showing.
+    On,
+    /// From any pane in the client's session, even if it is not visible.
+    All,
+}
+
+impl AllowPassthrough {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "off" => Ok(AllowPassthrough::Off),
+            "on" => Ok(AllowPassthrough::On),
+            "all" => Ok(AllowPassthrough::All),
+            _ => Err(format!("Invalid allow-passthrough value: {}", value)),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            AllowPassthrough::Off => "off",
+            AllowPassthrough::On => "on",
+            AllowPassthrough::All => "all",
+        }
+    }
+}
+
+/// Which bells are alerted to the clients attached to a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BellAction {
+    /// Bells in any window.
+    Any,
+    None,
+    /// Only bells in the current window.
+    Current,
+    /// Only bells in windows other than the current one.
+    Other,
+}
+
+impl BellAction {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "any" => Ok(BellAction::Any),
+            "none" => Ok(BellAction::None),
+            "current" => Ok(BellAction::Current),
+            "other" => Ok(BellAction::Other),
+            _ => Err(format!("Invalid bell-action value: {}", value)),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            BellAction::Any => "any",
+            BellAction::None => "none",
+            BellAction::Current => "current",
+            BellAction::Other => "other",
+        }
+    }
+
+    /// Whether a bell in a window is alerted, given whether that window is
+    /// the current one.
+    pub fn alerts(self, current: bool) -> bool {
+        match self {
+            BellAction::Any => true,
+            BellAction::None => false,
+            BellAction::Current => current,
+            BellAction::Other => !current,
+        }
+    }
+}
+
+/// How an alerted bell is shown to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisualBell {
+    /// Ring the client terminal's bell.
+    Off,
+    /// Show a message instead.
+    On,
+    /// Ring the bell and show a message.
+    Both,
+}
+
+impl VisualBell {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "off" => Ok(VisualBell::Off),
+            "on" => Ok(VisualBell::On),
+            "both" => Ok(VisualBell::Both),
+            _ => Err(format!("Invalid visual-bell value: {}", value)),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            VisualBell::Off => "off",
+            VisualBell::On => "on",
+            VisualBell::Both => "both",
+        }
+    }
+}
+
 /// Terminal multiplexer options with 3-tier inheritance.
 #[derive(Debug, Clone)]
 pub struct Options {
@@ -13,16 +147,29 @@ pub struct Options {
     pub base_index: usize,
     pub renumber_windows: bool,
     pub automatic_rename: bool,
+    pub bell_action: BellAction,
+    pub visual_bell: VisualBell,
 
     // Terminal
     pub default_shell: String,
     pub default_terminal: String,
+    /// Whether underline styles (`4:x`) and underline colors (SGR 58) are
+    /// sent to the clients whose terminals show them.
+    pub styled_underlines: bool,
     pub escape_time: u64,
     pub history_limit: usize,
+    pub set_clipboard: SetClipboard,
+    /// Whether applications may read the paste buffer with OSC 52.
+    pub get_clipboard: bool,
+    pub allow_passthrough: AllowPassthrough,
 
     // Mouse
     pub mouse: bool,
 
+    // Copy mode
+    /// Whether copy mode searches go on from the other end of the history.
+    pub wrap_search: bool,
+
     // Prefix
     pub prefix: String,
 
@@ -49,14 +196,22 @@ impl Default for Options {
             base_index: 0,
             renumber_windows: false,
             automatic_rename: true,
+            bell_action: BellAction::Any,
+            visual_bell: VisualBell::Off,
 
             default_shell,
             default_terminal: "xterm-256color".to_string(),
+            styled_underlines: true,
             escape_time: 500,
             history_limit: 2000,
+            set_clipboard: SetClipboard::External,
+            get_clipboard: false,
+            allow_passthrough: AllowPassthrough::Off,
 
             mouse: false,
 
+            wrap_search: true,
+
             prefix: "C-b".to_string(),
 
             display_time: 750,
@@ -91,11 +246,18 @@ impl Options {
             "base-index" => self.base_index = value.parse().map_err(|e| format!("{}", e))?,
             "renumber-windows" => self.renumber_windows = parse_bool(value)?,
             "automatic-rename" => self.automatic_rename = parse_bool(value)?,
+            "bell-action" => self.bell_action = BellAction::parse(value)?,
+            "visual-bell" => self.visual_bell = VisualBell::parse(value)?,
             "default-shell" | "default-command" => self.default_shell = unquote(value),
             "default-terminal" => self.default_terminal = unquote(value),
+            "styled-underlines" => self.styled_underlines = parse_bool(value)?,
             "escape-time" => self.escape_time = value.parse().map_err(|e| format!("{}", e))?,
             "history-limit" => self.history_limit = value.parse().map_err(|e| format!("{}", e))?,
+            "set-clipboard" => self.set_clipboard = SetClipboard::parse(value)?,
+            "get-clipboard" => self.get_clipboard = parse_bool(value)?,
+            "allow-passthrough" => self.allow_passthrough = AllowPassthrough::parse(value)?,
             "mouse" => self.mouse = parse_bool(value)?,
+            "wrap-search" => self.wrap_search = parse_bool(value)?,
             "prefix" => self.prefix = value.to_string(),
             "display-time" => self.display_time = value.parse().map_err(|e| format!("{}", e))?,
             "display-panes-time" => {
@@ -116,11 +278,20 @@ impl Options {
             "status-right" => Some(self.status_right.clone()),
             "status-interval" => Some(self.status_interval.to_string()),
             "base-index" => Some(self.base_index.to_string()),
+            "bell-action" => Some(self.bell_action.as_str().to_string()),
+            "visual-bell" => Some(self.visual_bell.as_str().to_string()),
             "default-shell" => Some(self.default_shell.clone()),
             "default-terminal" => Some(self.default_terminal.clone()),
+            "styled-underlines" => {
+                Some(if self.styled_underlines { "on" } else { "off" }.to_string())
+            }
             "escape-time" => Some(self.escape_time.to_string()),
             "history-limit" => Some(self.history_limit.to_string()),
+            "set-clipboard" => Some(self.set_clipboard.as_str().to_string()),
+            "get-clipboard" => Some(if self.get_clipboard { "on" } else { "off" }.to_string()),
+            "allow-passthrough" => Some(self.allow_passthrough.as_str().to_string()),
             "mouse" => Some(if self.mouse { "on" } else { "off" }.to_string()),
+            "wrap-search" => Some(if self.wrap_search { "on" } else { "off" }.to_string()),
             "prefix" => Some(self.prefix.clone()),
             _ => None,
         }
@@ -104,8 +104,9 @@ impl ConPty {
         Ok((server, client))
     }
 
-    /// Spawn a new process in a ConPTY pseudo-console.
-    pub fn spawn(command: &str, cols: u16, rows: u16) -> Result<Self> {
+    /// Spawn a new process in a ConPTY pseudo-console. The process starts in
+    /// `cwd` if given, otherwise in the current directory.
+    pub fn spawn(command: &str, cols: u16, rows: u16, cwd: Option<&str>) -> Result<Self> {
         unsafe {
             // Create overlapped pipe pairs for ConPTY I/O.
             let (pty_input_read, pty_input_write) = Self::create_overlapped_pipe(true)?;
@@ -179,6 +180,8 @@ impl ConPty {
                 .chain(std::iter::once(0))
                 .collect();
             let mut cmd_wide = cmd_wide;
+            let cwd_wide: Option<Vec<u16>> =
+                cwd.map(|dir| dir.encode_utf16().chain(std::iter::once(0)).collect());
 
             let success = CreateProcessW(
                 ptr::null(),
@@ -188,7 +191,7 @@ impl ConPty {
                 0, // bInheritHandles = FALSE
                 EXTENDED_STARTUPINFO_PRESENT,
                 ptr::null(),
-                ptr::null(),
+                cwd_wide.as_ref().map_or(ptr::null(), |dir| dir.as_ptr()),
                 &si.StartupInfo,
                 &mut pi,
             );
@@ -3,6 +3,7 @@ use tracing::debug;
 use wtmux_common::protocol::Direction;
 
 use crate::server::ServerState;
+use crate::session::Session;
 
 /// Parse and execute a tmux-style command string.
 pub fn execute_command(state: &mut ServerState, command: &str) -> Result<Option<String>> {
@@ -17,8 +18,15 @@ pub fn execute_command(state: &mut ServerState, command: &str) -> Result<Option<
         "split-window" => {
             let horizontal = parts.contains(&"-h");
             let shell = state.config.options.default_shell.clone();
+            let history_limit = state.config.options.history_limit;
             if let Some(session) = state.active_session_mut() {
-                session.active_window_mut().split_pane(&shell, horizontal)?;
+                let cwd = start_directory(&parts, session);
+                session.active_window_mut().split_pane(
+                    &shell,
+                    horizontal,
+                    history_limit,
+                    cwd.as_deref(),
+                )?;
             }
             Ok(None)
         }
@@ -26,10 +34,45 @@ pub fn execute_command(state: &mut ServerState, command: &str) -> Result<Option<
         "new-window" => {
             let name = find_flag_value(&parts, "-n");
             let shell = state.config.options.default_shell.clone();
+            let history_limit = state.config.options.history_limit;
             if let Some(session) = state.active_session_mut() {
+                let cwd = start_directory(&parts, session);
                 let cols = session.active_window().area_width();
                 let rows = session.active_window().area_height();
-                session.new_window(name, &shell, cols, rows)?;
+                session.new_window(name, &shell, cols, rows, history_limit, cwd.as_deref())?;
+            }
+            Ok(None)
+        }
+
+        "respawn-pane" => {
+            // respawn-pane [-k] [-c start-directory] [shell-command]
+            let kill = parts.contains(&"-k");
+            let mut command_parts = Vec::new();
+            let mut args = parts[1..].iter();
+            while let Some(&arg) = args.next() {
+                match arg {
+                    "-k" => {}
+                    "-c" => {
+                        args.next();
+                    }
+                    _ => command_parts.push(arg),
+                }
+            }
+            let shell = if command_parts.is_empty() {
+                state.config.options.default_shell.clone()
+            } else {
+                command_parts.join(" ")
+            };
+            if let Some(session) = state.active_session_mut() {
+                let cwd = start_directory(&parts, session);
+                let window = session.active_window_mut();
+                let pane_id = window.active_pane;
+                if let Some(pane) = window.panes.get_mut(&pane_id) {
+                    if !pane.exited && !kill {
+                        return Ok(Some("Pane is still active (use -k)".to_string()));
+                    }
+                    pane.respawn(&shell, cwd.as_deref())?;
+                }
             }
             Ok(None)
         }
@@ -163,10 +206,30 @@ pub fn execute_command(state: &mut ServerState, command: &str) -> Result<Option<
 
         "detach-client" => Ok(Some("__detach__".to_string())),
 
+        "refresh-client" => Ok(Some("__refresh__".to_string())),
+
         "copy-mode" => Ok(Some("__copy_mode__".to_string())),
 
         "paste-buffer" => Ok(Some("__paste__".to_string())),
 
+        "copy-command-output" => {
+            // Copy the output of the last finished command (OSC 133 marks).
+            let output = state.active_session().and_then(|session| {
+                let window = session.active_window();
+                window
+                    .panes
+                    .get(&window.active_pane)
+                    .and_then(|pane| pane.terminal.state.last_command_output())
+            });
+            match output {
+                Some(text) => {
+                    state.paste_buffer.push(text);
+                    Ok(None)
+                }
+                None => Ok(Some("No finished command output".to_string())),
+            }
+        }
+
         "command-prompt" => Ok(Some("__command_prompt__".to_string())),
 
         "list-keys" => {
@@ -247,13 +310,35 @@ pub fn execute_command(state: &mut ServerState, command: &str) -> Result<Option<
 
         "display-message" => {
             let msg = parts[1..].join(" ");
-            Ok(Some(msg))
+            match state.active_session() {
+                Some(session) => Ok(Some(expand_pane_formats(&msg, session))),
+                None => Ok(Some(msg)),
+            }
         }
 
         _ => Ok(Some(format!("Unknown command: {}", parts[0]))),
     }
 }
 
+/// Directory for a new process: the `-c` flag if given (formats such as
+/// `#{pane_current_path}` are expanded), otherwise the active pane's path
+/// when it is a local directory (see [`Session::default_start_dir`]).
+fn start_directory(parts: &[&str], session: &Session) -> Option<String> {
+    match find_flag_value(parts, "-c") {
+        Some(dir) => {
+            let dir = expand_pane_formats(dir.trim_matches(|c| c == '"' || c == '\''), session);
+            (!dir.is_empty()).then_some(dir)
+        }
+        None => session.default_start_dir(),
+    }
+}
+
+/// Expand pane format variables in `text` for the session's active pane.
+fn expand_pane_formats(text: &str, session: &Session) -> String {
+    let path = session.active_pane_path().unwrap_or_default();
+    text.replace("#{pane_current_path}", &path)
+}
+
 fn find_flag_value<'a>(parts: &'a [&'a str], flag: &str) -> Option<String> {
     parts
         .iter()
@@ -1,4 +1,5 @@
 use wtmux_common::protocol::CopyModeAction;
+use wtmux_terminal::search::{Point, SearchQuery};
 use wtmux_terminal::Terminal;
 
 /// Copy mode state for a pane.
@@ -6,10 +7,14 @@ pub struct CopyMode {
     pub active: bool,
     pub cursor_x: u16,
     pub cursor_y: u16,
+    /// How many lines the view is scrolled back into history.
     pub scroll_offset: usize,
-    pub selection_start: Option<(u16, u16)>,
-    pub selection_end: Option<(u16, u16)>,
+    /// Selection endpoints as (col, absolute line) in the history + screen view.
+    pub selection_start: Option<(u16, usize)>,
+    pub selection_end: Option<(u16, usize)>,
     pub search_query: String,
+    /// Whether `search_query` is a regular expression or plain text.
+    pub search_regex: bool,
     pub search_direction_forward: bool,
 }
 
@@ -23,25 +28,30 @@ impl CopyMode {
             selection_start: None,
             selection_end: None,
             search_query: String::new(),
+            search_regex: true,
             search_direction_forward: true,
         }
     }
 
     /// Handle a copy mode action. Returns Some(text) if text was copied.
+    /// With `wrap_search`, searches go on from the other end of the history
+    /// when they reach one.
     pub fn handle_action(
         &mut self,
         action: &CopyModeAction,
         terminal: &Terminal,
+        wrap_search: bool,
     ) -> Option<String> {
         let cols = terminal.state.grid.cols;
         let rows = terminal.state.grid.rows;
+        let history = terminal.state.history_len();
 
         match action {
             CopyModeAction::Up => {
                 if self.cursor_y > 0 {
                     self.cursor_y -= 1;
                 } else {
-                    self.scroll_offset += 1;
+                    self.scroll_up(1, terminal);
                 }
             }
             CopyModeAction::Down => {
@@ -62,20 +72,20 @@ impl CopyMode {
                 }
             }
             CopyModeAction::PageUp => {
-                self.scroll_offset += rows as usize;
+                self.scroll_up(rows as usize, terminal);
             }
             CopyModeAction::PageDown => {
                 self.scroll_offset = self.scroll_offset.saturating_sub(rows as usize);
             }
             CopyModeAction::HalfPageUp => {
-                self.scroll_offset += (rows / 2) as usize;
+                self.scroll_up((rows / 2) as usize, terminal);
             }
             CopyModeAction::HalfPageDown => {
                 self.scroll_offset = self.scroll_offset.saturating_sub((rows / 2) as usize);
             }
             CopyModeAction::Top => {
                 self.cursor_y = 0;
-                // scroll_offset to maximum
+                self.scroll_offset = history;
             }
             CopyModeAction::Bottom => {
                 self.cursor_y = rows - 1;
@@ -87,38 +97,64 @@ impl CopyMode {
             CopyModeAction::EndOfLine => {
                 self.cursor_x = cols - 1;
             }
+            CopyModeAction::PreviousPrompt => {
+                let line = self.cursor_line(terminal);
+                if let Some(prompt) = terminal.state.previous_prompt(line) {
+                    self.jump_to_line(prompt, terminal);
+                }
+            }
+            CopyModeAction::NextPrompt => {
+                let line = self.cursor_line(terminal);
+                if let Some(prompt) = terminal.state.next_prompt(line) {
+                    self.jump_to_line(prompt, terminal);
+                }
+            }
             CopyModeAction::StartSelection => {
-                self.selection_start = Some((self.cursor_x, self.cursor_y));
+                self.selection_start = Some((self.cursor_x, self.cursor_line(terminal)));
                 self.selection_end = None;
             }
             CopyModeAction::CopySelection => {
                 if let Some(start) = self.selection_start {
-                    self.selection_end = Some((self.cursor_x, self.cursor_y));
-                    let text = self.extract_selection(terminal, start, (self.cursor_x, self.cursor_y));
+                    let end = (self.cursor_x, self.cursor_line(terminal));
+                    self.selection_end = Some(end);
+                    let text = self.extract_selection(terminal, start, end);
                     self.active = false;
                     return Some(text);
                 }
             }
+            CopyModeAction::CopyHyperlink => {
+                let line = self.cursor_line(terminal);
+                if let Some(uri) = terminal.hyperlink_at(self.cursor_x, line) {
+                    self.active = false;
+                    return Some(uri.to_string());
+                }
+            }
             CopyModeAction::CancelSelection => {
                 self.selection_start = None;
                 self.selection_end = None;
             }
             CopyModeAction::SearchForward(query) => {
-                self.search_query = query.clone();
-                self.search_direction_forward = true;
-                self.do_search(terminal);
+                self.start_search(query, true, true);
+                self.do_search(terminal, wrap_search);
             }
             CopyModeAction::SearchBackward(query) => {
-                self.search_query = query.clone();
-                self.search_direction_forward = false;
-                self.do_search(terminal);
+                self.start_search(query, true, false);
+                self.do_search(terminal, wrap_search);
+            }
+            CopyModeAction::SearchForwardText(query) => {
+                self.start_search(query, false, true);
+                self.do_search(terminal, wrap_search);
+            }
+            CopyModeAction::SearchBackwardText(query) => {
+                self.start_search(query, false, false);
+                self.do_search(terminal, wrap_search);
             }
             CopyModeAction::SearchNext => {
-                self.do_search(terminal);
+                self.do_search(terminal, wrap_search);
             }
             CopyModeAction::SearchPrev => {
                 self.search_direction_forward = !self.search_direction_forward;
-                self.do_search(terminal);
+                self.do_search(terminal, wrap_search);
                 self.search_direction_forward = !self.search_direction_forward;
             }
             CopyModeAction::Exit => {
@@ -129,11 +165,39 @@ impl CopyMode {
         None
     }
 
+    /// Scroll the view back into history, clamped to the available lines.
+    pub fn scroll_up(&mut self, lines: usize, terminal: &Terminal) {
+        self.scroll_offset = (self.scroll_offset + lines).min(terminal.state.history_len());
+    }
+
+    /// Absolute line (in the history + screen view) under the cursor.
+    fn cursor_line(&self, terminal: &Terminal) -> usize {
+        let history = terminal.state.history_len();
+        history - self.scroll_offset.min(history) + self.cursor_y as usize
+    }
+
+    /// Move the cursor to the start of an absolute line, scrolling the view
+    /// only if the line is not already visible.
+    fn jump_to_line(&mut self, line: usize, terminal: &Terminal) {
+        let history = terminal.state.history_len();
+        let rows = terminal.state.grid.rows as usize;
+        // The history may have shrunk since the view was scrolled.
+        self.scroll_offset = self.scroll_offset.min(history);
+        let top = history - self.scroll_offset;
+        if line < top || line >= top + rows {
+            // Put the line at the top of the view when possible.
+            self.scroll_offset = history.saturating_sub(line);
+        }
+        let top = history - self.scroll_offset;
+        self.cursor_y = (line - top) as u16;
+        self.cursor_x = 0;
+    }
+
     fn extract_selection(
         &self,
         terminal: &Terminal,
-        start: (u16, u16),
-        end: (u16, u16),
+        start: (u16, usize),
+        end: (u16, usize),
     ) -> String {
         let mut text = String::new();
         let cols = terminal.state.grid.cols;
@@ -147,19 +211,24 @@ impl CopyMode {
         };
 
         for row in start_row..=end_row {
-            if row >= terminal.state.grid.rows {
-                break;
-            }
-            let col_start = if row == start_row { start_col } else { 0 };
+            let line = match terminal.state.line(row) {
+                Some(line) => line,
+                None => break,
+            };
+            let mut col_start = if row == start_row { start_col } else { 0 };
             let col_end = if row == end_row { end_col } else { cols - 1 };
+            // Starting on the right half of a wide character still copies it.
+            if col_start > 0 && line.get(col_start as usize).is_some_and(|c| c.width == 0) {
+                col_start -= 1;
+            }
 
             for col in col_start..=col_end {
-                if col >= cols {
-                    break;
-                }
-                let cell = terminal.state.grid.cell(col, row);
+                let cell = match line.get(col as usize) {
+                    Some(cell) => cell,
+                    None => break,
+                };
                 if cell.width > 0 {
-                    text.push(cell.ch);
+                    cell.push_grapheme(&mut text);
                 }
             }
 
@@ -174,35 +243,60 @@ impl CopyMode {
         text
     }
 
-    fn do_search(&mut self, terminal: &Terminal) {
+    fn start_search(&mut self, query: &str, regex: bool, forward: bool) {
+        self.search_query = query.to_string();
+        self.search_regex = regex;
+        self.search_direction_forward = forward;
+    }
+
+    /// Move the cursor to the start of the next match of the search, in the
+    /// history as well as on the screen. An invalid regular expression
+    /// matches nothing.
+    fn do_search(&mut self, terminal: &Terminal, wrap: bool) {
         if self.search_query.is_empty() {
             return;
         }
+        let Ok(query) = SearchQuery::new(&self.search_query, self.search_regex) else {
+            return;
+        };
 
-        if let Some((col, row)) = terminal.state.grid.search(
-            &self.search_query,
-            self.cursor_x,
-            self.cursor_y,
-            self.search_direction_forward,
-        ) {
-            self.cursor_x = col;
-            self.cursor_y = row;
+        let from = Point {
+            line: self.cursor_line(terminal),
+            col: self.cursor_x,
+        };
+        let forward = self.search_direction_forward;
+        if let Some(found) = query.find(&terminal.state, from, forward, wrap) {
+            self.jump_to_line(found.start.line, terminal);
+            self.cursor_x = found.start.col;
         }
     }
 
-    /// Render copy mode indicator.
-    pub fn render_indicator(&self) -> Vec<u8> {
-        let mut output = Vec::new();
-        // Show copy mode indicator in top-right
-        let indicator = if self.selection_start.is_some() {
+    /// Copy mode indicator, shown in the top-left corner.
+    pub fn indicator(&self) -> &'static str {
+        if self.selection_start.is_some() {
             "[Copy mode - selecting]"
         } else {
             "[Copy mode]"
-        };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jump_after_history_is_cleared() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_bytes(b"a\r\nb\r\nc\r\nd\r\n\x1b]133;A\x07$ ");
+        assert_eq!(terminal.state.history_len(), 2);
+        let mut copy = CopyMode::new(0, 0);
+        copy.scroll_up(2, &terminal);
 
-        output.extend_from_slice(
-            format!("\x1b[1;1H\x1b[43;30m{}\x1b[0m", indicator).as_bytes(),
-        );
-        output
+        // CSI 3 J drops the history the view was scrolled into.
+        terminal.process_bytes(b"\x1b[3J");
+        copy.handle_action(&CopyModeAction::NextPrompt, &terminal, false);
+        assert_eq!(copy.scroll_offset, 0);
+        assert_eq!(copy.cursor_y, 2);
     }
 }
@@ -3,6 +3,7 @@ mod copymode;
 mod pane;
 mod pastebuffer;
 mod renderer;
+mod screen;
 mod server;
 mod session;
 mod window;
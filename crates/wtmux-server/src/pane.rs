@@ -1,8 +1,14 @@
+use std::time::{Duration, Instant};
+
 use anyhow::Result;
 use wtmux_common::PaneId;
 use wtmux_pty::ConPty;
 use wtmux_terminal::Terminal;
 
+/// How long a synchronized update (mode 2026) may hold back rendering
+/// before the pane is drawn anyway.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// A pane is a single terminal within a window.
 pub struct Pane {
     pub id: PaneId,
@@ -12,14 +18,26 @@ pub struct Pane {
     pub cols: u16,
     pub rows: u16,
     pub exited: bool,
+    /// Directory the process was started in, if not the server's.
+    pub start_dir: Option<String>,
+    /// The application rang the bell and the server has not alerted it yet.
+    pub bell: bool,
+    /// When the application began the synchronized update in progress.
+    sync_started: Option<Instant>,
 }
 
 impl Pane {
-    /// Create a new pane by spawning a process.
-    pub fn new(command: &str, cols: u16, rows: u16) -> Result<Self> {
+    /// Create a new pane by spawning a process, in `cwd` if given.
+    pub fn new(
+        command: &str,
+        cols: u16,
+        rows: u16,
+        history_limit: usize,
+        cwd: Option<&str>,
+    ) -> Result<Self> {
         let id = PaneId::new();
-        let pty = ConPty::spawn(command, cols, rows)?;
-        let terminal = Terminal::new(cols, rows);
+        let pty = ConPty::spawn(command, cols, rows, cwd)?;
+        let terminal = Terminal::with_history_limit(cols, rows, history_limit);
 
         Ok(Pane {
             id,
@@ -29,9 +47,33 @@ impl Pane {
             cols,
             rows,
             exited: false,
+            start_dir: cwd.map(str::to_string),
+            bell: false,
+            sync_started: None,
         })
     }
 
+    /// Replace the pane's process with a new one. The screen and history
+    /// are kept.
+    pub fn respawn(&mut self, command: &str, cwd: Option<&str>) -> Result<()> {
+        self.pty = ConPty::spawn(command, self.cols, self.rows, cwd)?;
+        self.title = command.to_string();
+        self.exited = false;
+        self.start_dir = cwd.map(str::to_string);
+        self.terminal.state.current_dir = None;
+        Ok(())
+    }
+
+    /// The pane's working directory (`pane_current_path`): the last one the
+    /// shell reported with OSC 7, or the one it was started in.
+    pub fn current_path(&self) -> Option<&str> {
+        self.terminal
+            .state
+            .current_dir
+            .as_deref()
+            .or(self.start_dir.as_deref())
+    }
+
     /// Resize this pane.
     pub fn resize(&mut self, cols: u16, rows: u16) -> Result<()> {
         if cols != self.cols || rows != self.rows {
@@ -48,27 +90,49 @@ impl Pane {
         self.pty.write(data).await
     }
 
-    /// Read output from the PTY and process it through the VT parser.
-    pub async fn read_output(&mut self) -> Result<Option<Vec<u8>>> {
+    /// Feed PTY output through the VT parser, up to the end of the first
+    /// passthrough sequence in it, and write any replies it queued (cursor
+    /// reports, device attributes) back to the PTY. Returns how many bytes
+    /// were processed.
+    pub async fn process_output(&mut self, data: &[u8]) -> Result<usize> {
+        let processed = self.terminal.process_until_passthrough(data);
+        self.bell |= self.terminal.state.take_bell();
+        if self.terminal.modes().synchronized_output {
+            self.sync_started.get_or_insert_with(Instant::now);
+        } else {
+            self.sync_started = None;
+        }
+        let replies = self.terminal.take_outgoing();
+        if !replies.is_empty() {
+            self.pty.write(&replies).await?;
+        }
+        if let Some(title) = self.get_title_update() {
+            self.title = title;
+        }
+        Ok(processed)
+    }
+
+    /// Whether the application is in the middle of a synchronized update,
+    /// so the pane should not be drawn yet. Gives up after a timeout in
+    /// case the application never ends the update.
+    pub fn holds_output(&self) -> bool {
+        self.sync_started
+            .is_some_and(|started| started.elapsed() < SYNC_TIMEOUT)
+    }
+
+    /// The output the PTY has ready, up to about `limit` bytes, without
+    /// waiting for more.
+    pub async fn read_ready(&mut self, limit: usize) -> Vec<u8> {
+        let mut output = Vec::new();
         let mut buf = vec![0u8; 4096];
-        match self.pty.read(&mut buf).await {
-            Ok(0) => {
-                self.exited = true;
-                Ok(None)
-            }
-            Ok(n) => {
-                buf.truncate(n);
-                self.terminal.process_bytes(&buf);
-                if let Some(title) = self.get_title_update() {
-                    self.title = title;
-                }
-                Ok(Some(buf))
-            }
-            Err(e) => {
-                self.exited = true;
-                Err(e)
+        while !self.exited && output.len() < limit {
+            match tokio::time::timeout(Duration::ZERO, self.pty.read(&mut buf)).await {
+                Ok(Ok(n)) if n > 0 => output.extend_from_slice(&buf[..n]),
+                Ok(_) => self.exited = true,
+                Err(_) => break,
             }
         }
+        output
     }
 
     fn get_title_update(&self) -> Option<String> {
@@ -1,11 +1,132 @@
 use std::collections::HashMap;
-use wtmux_common::PaneId;
+use wtmux_common::{PaneId, WindowId};
 use wtmux_layout::geometry::Rect;
-use wtmux_terminal::cell::Color;
+use wtmux_terminal::cell::{Cell, Color};
+use wtmux_terminal::hyperlink::HyperlinkTable;
+use wtmux_terminal::parser::CursorStyle;
 use wtmux_terminal::statusbar::{StatusBar, StatusBarContext, WindowStatus};
 
+use crate::pane::Pane;
+use crate::screen::Screen;
 use crate::session::Session;
 
+/// A composed frame: the whole screen, and the cursor of the active pane
+/// unless it is hidden while the pane holds back its output.
+pub struct Frame {
+    pub screen: Screen,
+    pub cursor: Option<FrameCursor>,
+}
+
+/// Where the cursor is on the screen and how it is drawn.
+#[derive(Clone, Copy, PartialEq)]
+pub struct FrameCursor {
+    pub col: u16,
+    pub row: u16,
+    pub style: CursorStyle,
+    pub visible: bool,
+}
+
+/// What a client's terminal shows, so that each frame only sends the cells
+/// that changed. Pane rows are also only composed again when their grid
+/// reports damage since they were last composed.
+#[derive(Default)]
+pub struct DrawnFrame {
+    /// The last frame sent, or None if the next one clears the screen and
+    /// draws everything.
+    shown: Option<Frame>,
+    /// Pane contents and borders as last composed, with the layout they
+    /// were composed for.
+    panes: Option<(FrameLayout, Screen)>,
+    /// Damage epoch each pane was last composed at.
+    epochs: HashMap<PaneId, u64>,
+    /// Hyperlinks of the composed cells, swept as the panes' links change.
+    links: HyperlinkTable,
+    /// Whether the client's terminal gets underline styles and colors.
+    styled_underlines: bool,
+}
+
+#[derive(PartialEq)]
+struct FrameLayout {
+    window: WindowId,
+    active_pane: PaneId,
+    panes: Vec<(PaneId, Rect)>,
+    cols: u16,
+    rows: u16,
+    scroll_offset: usize,
+}
+
+impl DrawnFrame {
+    /// Make the next frame clear the screen and draw everything, for when
+    /// the client's terminal may not show what was sent.
+    pub fn invalidate(&mut self) {
+        *self = DrawnFrame::default();
+    }
+
+    /// Draw underline styles and colors for a terminal that shows them, or
+    /// plain underlines. Changing it composes the panes again.
+    pub fn set_styled_underlines(&mut self, styled_underlines: bool) {
+        if self.styled_underlines != styled_underlines {
+            self.styled_underlines = styled_underlines;
+            self.panes = None;
+        }
+    }
+
+    /// Free the links no cell of the composed panes or of the shown frame
+    /// refers to any more.
+    fn sweep_links(&mut self) {
+        let panes = self.panes.iter().flat_map(|(_, layer)| layer.hyperlinks());
+        let shown = self.shown.iter().flat_map(|frame| frame.screen.hyperlinks());
+        self.links.sweep(panes.chain(shown));
+    }
+
+    /// The bytes that change the client's screen from the last frame to
+    /// `frame`. Nothing is sent if the two are the same.
+    pub fn update(&mut self, frame: Frame) -> Vec<u8> {
+        let mut output = Vec::new();
+        let old = self.shown.take().filter(|old| {
+            old.screen.cols == frame.screen.cols && old.screen.rows == frame.screen.rows
+        });
+        let cells = match &old {
+            Some(old) => frame.screen.diff(&old.screen, &self.links),
+            None => {
+                let blank = Screen::new(frame.screen.cols, frame.screen.rows);
+                frame.screen.diff(&blank, &self.links)
+            }
+        };
+        if cells.is_empty() && old.as_ref().is_some_and(|old| old.cursor == frame.cursor) {
+            self.shown = Some(frame);
+            return output;
+        }
+
+        // Hide cursor during render
+        output.extend_from_slice(b"\x1b[?25l");
+        if old.is_none() {
+            output.extend_from_slice(b"\x1b[0m\x1b[H\x1b[2J");
+        }
+        output.extend_from_slice(&cells);
+
+        // Restore cursor to active pane position, with the shape the pane's
+        // application chose. It stays hidden if the application hid it.
+        if let Some(cursor) = frame.cursor {
+            output.extend_from_slice(
+                format!(
+                    "\x1b[{};{}H\x1b[{} q",
+                    cursor.row + 1,
+                    cursor.col + 1,
+                    cursor.style.param()
+                )
+                .as_bytes(),
+            );
+            if cursor.visible {
+                output.extend_from_slice(b"\x1b[?25h");
+            }
+        }
+
+        self.shown = Some(frame);
+        output
+    }
+}
+
 /// Compose pane grids, borders, and status bar into a final screen buffer.
 pub struct Renderer {
     pub cols: u16,
@@ -27,107 +148,131 @@ impl Renderer {
         self.rows = rows;
     }
 
-    /// Render the entire screen for a session.
-    pub fn render(&self, session: &Session) -> Vec<u8> {
-        let mut output = Vec::with_capacity((self.cols as usize * self.rows as usize) * 4);
+    /// Compose the screen for a session, for a client whose screen shows
+    /// `drawn`. `scroll_offset` is how far the active pane is scrolled back
+    /// into its history (copy mode).
+    pub fn compose(
+        &self,
+        session: &mut Session,
+        scroll_offset: usize,
+        drawn: &mut DrawnFrame,
+    ) -> Frame {
+        let window = session.active_window_mut();
+        let mut panes: Vec<_> = window.pane_geometries().into_iter().collect();
+        panes.sort_by_key(|(pane_id, _)| pane_id.0);
+        let layout = FrameLayout {
+            window: window.id,
+            active_pane: window.active_pane,
+            panes,
+            cols: self.cols,
+            rows: self.rows,
+            scroll_offset,
+        };
 
-        // Hide cursor during render
-        output.extend_from_slice(b"\x1b[?25l");
+        if drawn.links.needs_sweep() {
+            drawn.sweep_links();
+        }
 
-        let window = session.active_window();
-        let geometries = window.pane_geometries();
-
-        // Render each pane
-        for (pane_id, rect) in &geometries {
-            if let Some(pane) = window.panes.get(pane_id) {
-                let pane_output = pane.terminal.render_region(
-                    0,
-                    0,
-                    rect.width,
-                    rect.height,
-                    rect.x,
-                    rect.y,
-                );
-                output.extend_from_slice(&pane_output);
+        // Keep the composed panes while the layout stays the same, and
+        // compose only the rows that changed since.
+        let (mut layer, previous) = match drawn.panes.take() {
+            Some((old_layout, layer)) if old_layout == layout => (layer, None),
+            old => {
+                drawn.epochs.clear();
+                let mut layer = Screen::new(self.cols, self.rows);
+                self.draw_borders(&mut layer, &layout);
+                (layer, old.map(|(_, layer)| layer))
             }
-        }
+        };
 
-        // Render pane borders if more than one pane and not zoomed
-        if window.panes.len() > 1 && window.zoomed_pane.is_none() {
-            let border_output =
-                self.render_borders(&geometries, window.active_pane);
-            output.extend_from_slice(&border_output);
+        for (pane_id, rect) in &layout.panes {
+            let Some(pane) = window.panes.get_mut(pane_id) else {
+                continue;
+            };
+            // Leave a half-drawn frame off the screen; the client keeps
+            // showing the previous one until the update ends.
+            if pane.holds_output() {
+                if let Some(previous) = &previous {
+                    layer.copy_rect(previous, *rect);
+                }
+                continue;
+            }
+            let history = pane.terminal.state.history_len();
+            let offset = if *pane_id == layout.active_pane {
+                scroll_offset.min(history)
+            } else {
+                0
+            };
+            // A pane scrolled into its history moves with every new line.
+            let rows = match drawn.epochs.get(pane_id) {
+                Some(&since) if offset == 0 => pane.terminal.damaged_rows(since),
+                _ => (0..rect.height).collect(),
+            };
+            let styled = drawn.styled_underlines;
+            for row in rows.into_iter().filter(|&row| row < rect.height) {
+                let line = history - offset + row as usize;
+                compose_pane_row(&mut layer, pane, line, *rect, row, &mut drawn.links, styled);
+            }
+            drawn.epochs.insert(*pane_id, pane.terminal.begin_damage_epoch());
         }
 
-        // Render status bar at the bottom
-        let status_output = self.render_status_bar(session);
-        output.extend_from_slice(&status_output);
-
-        // Restore cursor to active pane position
-        if let Some(pane) = window.panes.get(&window.active_pane) {
-            if let Some(rect) = geometries.get(&window.active_pane) {
+        // The cursor stays hidden while the active pane holds back a frame.
+        let cursor = window
+            .panes
+            .get(&layout.active_pane)
+            .filter(|pane| !pane.holds_output())
+            .zip(layout.panes.iter().find(|(pane_id, _)| *pane_id == layout.active_pane))
+            .map(|(pane, (_, rect))| {
                 let (cx, cy) = pane.terminal.cursor_pos();
-                output.extend_from_slice(
-                    format!(
-                        "\x1b[{};{}H",
-                        rect.y + cy + 1,
-                        rect.x + cx + 1
-                    )
-                    .as_bytes(),
-                );
-            }
-        }
+                FrameCursor {
+                    col: rect.x + cx,
+                    row: rect.y + cy,
+                    style: pane.terminal.cursor_style(),
+                    visible: pane.terminal.cursor_visible(),
+                }
+            });
 
-        // Show cursor
-        output.extend_from_slice(b"\x1b[?25h");
+        let mut screen = layer.clone();
+        drawn.panes = Some((layout, layer));
+        self.draw_status_bar(&mut screen, session);
 
-        output
+        Frame { screen, cursor }
     }
 
-    fn render_borders(
-        &self,
-        geometries: &HashMap<PaneId, Rect>,
-        active_pane: PaneId,
-    ) -> Vec<u8> {
-        let mut output = Vec::new();
+    /// Draw the borders between panes, green around the active pane.
+    fn draw_borders(&self, layer: &mut Screen, layout: &FrameLayout) {
+        if layout.panes.len() < 2 {
+            return;
+        }
 
-        // Draw borders between panes using box-drawing characters
-        for (&pane_id, rect) in geometries {
-            let is_active = pane_id == active_pane;
-            let color = if is_active {
-                "\x1b[32m" // Green for active
+        // The active pane goes last so that its color wins where borders
+        // meet.
+        let mut panes: Vec<_> = layout.panes.iter().collect();
+        panes.sort_by_key(|(pane_id, _)| *pane_id == layout.active_pane);
+        for (pane_id, rect) in panes {
+            let color = if *pane_id == layout.active_pane {
+                Color::Indexed(2)
             } else {
-                "\x1b[37m" // White for inactive
+                Color::Indexed(7)
             };
 
             // Right border (if there's space)
             if rect.right() < self.cols {
-                output.extend_from_slice(color.as_bytes());
                 for row in rect.y..rect.bottom() {
-                    output.extend_from_slice(
-                        format!("\x1b[{};{}H│", row + 1, rect.right() + 1).as_bytes(),
-                    );
+                    layer.set(rect.right(), row, Cell::new('│').with_fg(color));
                 }
             }
 
             // Bottom border (if there's space and not at the status bar line)
             if rect.bottom() < self.rows.saturating_sub(1) {
-                output.extend_from_slice(color.as_bytes());
                 for col in rect.x..rect.right() {
-                    output.extend_from_slice(
-                        format!("\x1b[{};{}H─", rect.bottom() + 1, col + 1).as_bytes(),
-                    );
+                    layer.set(col, rect.bottom(), Cell::new('─').with_fg(color));
                 }
             }
         }
-
-        output.extend_from_slice(b"\x1b[0m");
-        output
     }
 
-    fn render_status_bar(&self, session: &Session) -> Vec<u8> {
-        let mut output = Vec::new();
-
+    fn draw_status_bar(&self, screen: &mut Screen, session: &Session) {
         let ctx = StatusBarContext {
             session_name: session.name.clone(),
             windows: session
@@ -137,61 +282,121 @@ impl Renderer {
                     index: w.index,
                     name: w.name.clone(),
                     active: w.active,
+                    bell: w.bell,
                 })
                 .collect(),
             cols: self.cols,
         };
 
-        let cells = self.status_bar.render(&ctx);
-
-        // Move to status bar position (last row)
-        output.extend_from_slice(
-            format!("\x1b[{};1H", self.rows).as_bytes(),
-        );
-
-        // Render cells
-        let mut prev_fg = Color::Default;
-        let mut prev_bg = Color::Default;
-
-        for cell in &cells {
-            let need_sgr = cell.fg != prev_fg || cell.bg != prev_bg;
-            if need_sgr {
-                output.extend_from_slice(b"\x1b[0");
-                write_status_color(&mut output, cell.fg, true);
-                write_status_color(&mut output, cell.bg, false);
-                output.push(b'm');
-                prev_fg = cell.fg;
-                prev_bg = cell.bg;
-            }
-
-            let mut buf = [0u8; 4];
-            let s = cell.ch.encode_utf8(&mut buf);
-            output.extend_from_slice(s.as_bytes());
+        let row = self.rows.saturating_sub(1);
+        for (col, cell) in self.status_bar.render(&ctx).into_iter().enumerate() {
+            screen.set(col as u16, row, cell);
         }
-
-        output.extend_from_slice(b"\x1b[0m");
-        output
     }
 }
 
-fn write_status_color(output: &mut Vec<u8>, color: Color, is_fg: bool) {
-    match color {
-        Color::Default => {}
-        Color::Indexed(n) if n < 8 => {
-            let base = if is_fg { 30 } else { 40 };
-            output.extend_from_slice(format!(";{}", base + n as u32).as_bytes());
-        }
-        Color::Indexed(n) if n < 16 => {
-            let base = if is_fg { 90 } else { 100 };
-            output.extend_from_slice(format!(";{}", base + n as u32 - 8).as_bytes());
+/// Compose row `row` of a pane from line `line` of its combined history +
+/// screen view (see [`wtmux_terminal::parser::TerminalState::line`]).
+fn compose_pane_row(
+    layer: &mut Screen,
+    pane: &Pane,
+    line: usize,
+    rect: Rect,
+    row: u16,
+    links: &mut HyperlinkTable,
+    styled_underlines: bool,
+) {
+    let terminal = &pane.terminal;
+    let cells = terminal.state.line(line);
+    let blank = Cell::default();
+    for col in 0..rect.width {
+        let cell = cells.as_deref().and_then(|cells| cells.get(col as usize)).unwrap_or(&blank);
+        let mut drawn = terminal.drawn_cell(cell, styled_underlines);
+        // A wide character cut by either edge of the pane is drawn as a
+        // blank so that it does not spill into a neighbor.
+        if (cell.width == 0 && col == 0) || (cell.width == 2 && col + 1 == rect.width) {
+            drawn.ch = ' ';
+            drawn.combining = None;
+            drawn.width = 1;
         }
-        Color::Indexed(n) => {
-            let prefix = if is_fg { "38" } else { "48" };
-            output.extend_from_slice(format!(";{};5;{}", prefix, n).as_bytes());
+        drawn.hyperlink = terminal
+            .state
+            .hyperlinks
+            .get(cell.hyperlink)
+            .map_or(0, |link| links.intern(link.clone()));
+        layer.set(rect.x + col, rect.y + row, drawn);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wtmux_terminal::hyperlink::Hyperlink;
+
+    fn frame(text: &str, cursor: (u16, u16)) -> Frame {
+        let mut screen = Screen::new(10, 2);
+        for (col, ch) in text.chars().enumerate() {
+            screen.set(col as u16, 0, Cell::new(ch));
         }
-        Color::Rgb(r, g, b) => {
-            let prefix = if is_fg { "38" } else { "48" };
-            output.extend_from_slice(format!(";{};2;{};{};{}", prefix, r, g, b).as_bytes());
+        Frame {
+            screen,
+            cursor: Some(FrameCursor {
+                col: cursor.0,
+                row: cursor.1,
+                style: CursorStyle::Default,
+                visible: true,
+            }),
         }
     }
+
+    #[test]
+    fn test_update_sends_only_changes() {
+        let mut drawn = DrawnFrame::default();
+        let first = drawn.update(frame("ab", (2, 0)));
+        assert!(first.starts_with(b"\x1b[?25l\x1b[0m\x1b[H\x1b[2J"));
+
+        assert_eq!(drawn.update(frame("ab", (2, 0))), b"");
+        assert_eq!(
+            drawn.update(frame("ac", (2, 0))),
+            b"\x1b[?25l\x1b[1;2Hc\x1b[1;3H\x1b[0 q\x1b[?25h"
+        );
+        // Moving the cursor alone still updates the client.
+        assert_eq!(
+            drawn.update(frame("ac", (0, 1))),
+            b"\x1b[?25l\x1b[2;1H\x1b[0 q\x1b[?25h"
+        );
+    }
+
+    #[test]
+    fn test_invalidate_clears_and_redraws() {
+        let mut drawn = DrawnFrame::default();
+        drawn.update(frame("ab", (2, 0)));
+        drawn.invalidate();
+        let output = drawn.update(frame("ab", (2, 0)));
+        let mut expected = b"\x1b[?25l\x1b[0m\x1b[H\x1b[2J\x1b[1;1Hab".to_vec();
+        expected.extend_from_slice(b"\x1b[1;3H\x1b[0 q\x1b[?25h");
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_sweep_keeps_only_shown_links() {
+        let mut drawn = DrawnFrame::default();
+        let link = |n| Hyperlink {
+            id: None,
+            uri: format!("https://example.com/{}", n),
+        };
+        let ids: Vec<_> = (0..300).map(|n| drawn.links.intern(link(n))).collect();
+        let mut shown = frame("ab", (2, 0));
+        let mut cell = Cell::new('c');
+        cell.hyperlink = ids[7];
+        shown.screen.set(2, 0, cell);
+        drawn.update(shown);
+
+        assert!(drawn.links.needs_sweep());
+        drawn.sweep_links();
+        assert_eq!(drawn.links.len(), 1);
+        assert_eq!(drawn.links.get(ids[7]), Some(&link(7)));
+        // Freed ids are handed out again.
+        assert!(drawn.links.intern(link(300)) <= 300);
+    }
 }
@@ -0,0 +1,274 @@
+use wtmux_layout::geometry::Rect;
+use wtmux_terminal::cell::{Attrs, Cell, Color};
+use wtmux_terminal::hyperlink::{self, HyperlinkTable};
+use wtmux_terminal::terminal::write_sgr;
+
+/// A client screen's worth of cells, composed from panes, borders, the
+/// status bar and overlays. Colors are final, and hyperlink ids refer to the
+/// link table of the client the screen is drawn for.
+#[derive(Clone)]
+pub struct Screen {
+    pub cols: u16,
+    pub rows: u16,
+    cells: Vec<Cell>,
+}
+
+impl Screen {
+    /// A blank screen.
+    pub fn new(cols: u16, rows: u16) -> Self {
+        Screen {
+            cols,
+            rows,
+            cells: vec![Cell::default(); cols as usize * rows as usize],
+        }
+    }
+
+    pub fn cell(&self, col: u16, row: u16) -> &Cell {
+        &self.cells[row as usize * self.cols as usize + col as usize]
+    }
+
+    /// Set a cell. Positions off the screen are ignored.
+    pub fn set(&mut self, col: u16, row: u16, cell: Cell) {
+        if col < self.cols && row < self.rows {
+            self.cells[row as usize * self.cols as usize + col as usize] = cell;
+        }
+    }
+
+    /// Hyperlink ids of every cell, for sweeping the link table.
+    pub fn hyperlinks(&self) -> impl Iterator<Item = u32> + '_ {
+        self.cells.iter().map(|cell| cell.hyperlink)
+    }
+
+    /// Copy the cells inside `rect` from a screen of the same size.
+    pub fn copy_rect(&mut self, other: &Screen, rect: Rect) {
+        if other.cols != self.cols || other.rows != self.rows {
+            return;
+        }
+        let left = rect.x.min(self.cols) as usize;
+        let right = rect.right().min(self.cols) as usize;
+        for row in rect.y..rect.bottom().min(self.rows) {
+            let start = row as usize * self.cols as usize;
+            self.cells[start + left..start + right]
+                .clone_from_slice(&other.cells[start + left..start + right]);
+        }
+    }
+
+    /// Draw `text` in black on yellow from (col, row), for messages and mode
+    /// indicators. With `fill`, the rest of the row is painted too.
+    pub fn put_overlay(&mut self, col: u16, row: u16, text: &str, fill: bool) {
+        let style = |ch| Cell::new(ch).with_fg(Color::Indexed(0)).with_bg(Color::Indexed(3));
+        let mut col = col;
+        for ch in text.chars() {
+            self.set(col, row, style(ch));
+            col = col.saturating_add(1);
+        }
+        while fill && col < self.cols {
+            self.set(col, row, style(' '));
+            col += 1;
+        }
+    }
+
+    /// The bytes that change a terminal showing `old` into showing this
+    /// screen. Only cells that differ are drawn, with a cursor move when
+    /// they are not next to the last one drawn and an SGR sequence when the
+    /// style changes. `old` must be the same size.
+    pub fn diff(&self, old: &Screen, links: &HyperlinkTable) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut pen = Pen::default();
+
+        for row in 0..self.rows {
+            let mut col = 0;
+            while col < self.cols {
+                let cell = self.cell(col, row);
+                // A wide character is drawn as a whole when either of its
+                // halves changed.
+                let span = (cell.width.max(1) as u16).min(self.cols - col);
+                let changed = (col..col + span).any(|c| self.cell(c, row) != old.cell(c, row));
+                if changed {
+                    pen.move_to(&mut output, col, row);
+                    pen.set_style(&mut output, cell.fg, cell.bg, cell.attrs);
+                    pen.set_link(&mut output, cell.hyperlink, links);
+                    if cell.width == 0 {
+                        // A continuation without its wide character.
+                        output.push(b' ');
+                    } else {
+                        cell.write_grapheme(&mut output);
+                    }
+                    pen.advance(span, self.cols);
+                }
+                col += span;
+            }
+        }
+
+        if pen.link != 0 {
+            output.extend_from_slice(hyperlink::CLOSE_SEQUENCE);
+        }
+        if pen.style != (Color::Default, Color::Default, Attrs::default()) {
+            output.extend_from_slice(b"\x1b[0m");
+        }
+        output
+    }
+}
+
+/// Where the client's cursor is and what it draws with while a diff is
+/// written. An unknown position is None, so the first cell drawn moves
+/// there. Every diff leaves the terminal with the default style and no link
+/// (as does the clear before a first frame), so the next one starts there.
+#[derive(Default)]
+struct Pen {
+    pos: Option<(u16, u16)>,
+    style: (Color, Color, Attrs),
+    link: u32,
+}
+
+impl Pen {
+    fn move_to(&mut self, output: &mut Vec<u8>, col: u16, row: u16) {
+        match self.pos {
+            Some(pos) if pos == (col, row) => {}
+            Some((pos_col, pos_row)) if pos_row == row && pos_col < col => {
+                output.extend_from_slice(format!("\x1b[{}C", col - pos_col).as_bytes());
+            }
+            _ => output.extend_from_slice(format!("\x1b[{};{}H", row + 1, col + 1).as_bytes()),
+        }
+        self.pos = Some((col, row));
+    }
+
+    fn set_style(&mut self, output: &mut Vec<u8>, fg: Color, bg: Color, attrs: Attrs) {
+        if self.style != (fg, bg, attrs) {
+            write_sgr(output, fg, bg, attrs, true);
+            self.style = (fg, bg, attrs);
+        }
+    }
+
+    fn set_link(&mut self, output: &mut Vec<u8>, link: u32, links: &HyperlinkTable) {
+        if self.link == link {
+            return;
+        }
+        match links.get(link) {
+            Some(link) => output.extend_from_slice(&link.open_sequence()),
+            None => output.extend_from_slice(hyperlink::CLOSE_SEQUENCE),
+        }
+        self.link = link;
+    }
+
+    /// Account for `width` columns just drawn. At the right edge the
+    /// terminal's pending wrap makes the position unreliable, so the next
+    /// cell moves there explicitly.
+    fn advance(&mut self, width: u16, cols: u16) {
+        self.pos = self
+            .pos
+            .map(|(col, row)| (col + width, row))
+            .filter(|&(col, _)| col < cols);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wtmux_terminal::hyperlink::Hyperlink;
+
+    fn diff(new: &Screen, old: &Screen) -> String {
+        String::from_utf8(new.diff(old, &HyperlinkTable::new())).unwrap()
+    }
+
+    fn wide(ch: char) -> [Cell; 2] {
+        let cell = Cell {
+            width: 2,
+            ..Cell::new(ch)
+        };
+        let cont = Cell {
+            width: 0,
+            ..Cell::default()
+        };
+        [cell, cont]
+    }
+
+    #[test]
+    fn test_identical_screens_emit_nothing() {
+        let mut screen = Screen::new(10, 3);
+        screen.put_overlay(0, 1, "hello", true);
+        assert_eq!(diff(&screen, &screen.clone()), "");
+    }
+
+    #[test]
+    fn test_changed_cells_emit_move_and_glyph() {
+        let old = Screen::new(10, 3);
+        let mut new = old.clone();
+        new.set(3, 1, Cell::new('x'));
+        assert_eq!(diff(&new, &old), "\x1b[2;4Hx");
+
+        // Further along the same row the cursor moves forward instead.
+        new.set(6, 1, Cell::new('y'));
+        new.set(7, 1, Cell::new('z'));
+        assert_eq!(diff(&new, &old), "\x1b[2;4Hx\x1b[2Cyz");
+    }
+
+    #[test]
+    fn test_wide_character_drawn_whole() {
+        let mut old = Screen::new(10, 2);
+        let [cell, cont] = wide('漢');
+        old.set(2, 0, cell.clone());
+        old.set(3, 0, cont.clone());
+
+        // Only the right half changed: the character is drawn from its left.
+        let mut new = old.clone();
+        new.set(3, 0, cont.clone().with_bg(Color::Indexed(1)));
+        assert_eq!(diff(&new, &old), "\x1b[1;3H漢");
+
+        // Moved one column left, leaving a blank where its right half was.
+        let mut new = Screen::new(10, 2);
+        new.set(1, 0, cell);
+        new.set(2, 0, cont.clone());
+        assert_eq!(diff(&new, &old), "\x1b[1;2H漢 ");
+
+        // A right half without its character is drawn as a blank.
+        let mut new = Screen::new(10, 2);
+        new.set(5, 1, cont);
+        assert_eq!(diff(&new, &Screen::new(10, 2)), "\x1b[2;6H ");
+    }
+
+    #[test]
+    fn test_right_edge_forgets_position() {
+        let old = Screen::new(5, 2);
+        let mut new = old.clone();
+        new.set(4, 0, Cell::new('a'));
+        new.set(0, 1, Cell::new('b'));
+        // The pending wrap after the last column makes the next cell move
+        // explicitly.
+        assert_eq!(diff(&new, &old), "\x1b[1;5Ha\x1b[2;1Hb");
+    }
+
+    #[test]
+    fn test_style_and_link_carry_over() {
+        let old = Screen::new(10, 1);
+        let mut new = old.clone();
+        let red = |ch| Cell::new(ch).with_fg(Color::Indexed(1));
+        new.set(0, 0, red('a'));
+        new.set(1, 0, red('b'));
+        new.set(2, 0, Cell::new('c'));
+        new.set(3, 0, red('d'));
+        // One SGR per change of style, and a reset at the end.
+        assert_eq!(
+            diff(&new, &old),
+            "\x1b[1;1H\x1b[0;31mab\x1b[0mc\x1b[0;31md\x1b[0m"
+        );
+
+        let mut links = HyperlinkTable::new();
+        let link = Hyperlink {
+            id: None,
+            uri: "https://example.com".to_string(),
+        };
+        let id = links.intern(link.clone());
+        let mut new = old.clone();
+        for (col, ch) in "ab".chars().enumerate() {
+            new.set(col as u16, 0, Cell { hyperlink: id, ..Cell::new(ch) });
+        }
+        new.set(2, 0, Cell::new('c'));
+        let mut expected = b"\x1b[1;1H".to_vec();
+        expected.extend_from_slice(&link.open_sequence());
+        expected.extend_from_slice(b"ab");
+        expected.extend_from_slice(hyperlink::CLOSE_SEQUENCE);
+        expected.extend_from_slice(b"c");
+        assert_eq!(new.diff(&old, &links), expected);
+    }
+}
@@ -1,18 +1,36 @@
 use anyhow::Result;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::windows::named_pipe::NamedPipeServer;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::MissedTickBehavior;
 use tracing::{debug, error, info};
 use wtmux_common::ipc::{create_server, create_server_instance, recv_message, send_message};
-use wtmux_common::protocol::{SessionInfo, SessionTarget};
-use wtmux_common::{ClientId, ClientMessage, ServerMessage, SessionId};
-use wtmux_config::Config;
+use wtmux_common::protocol::{ClientFeatures, MouseEventKind, SessionInfo, SessionTarget};
+use wtmux_common::{ClientId, ClientMessage, PaneId, ServerMessage, SessionId};
+use wtmux_config::{AllowPassthrough, Config, SetClipboard, VisualBell};
+use wtmux_terminal::clipboard::{encode_osc52, ClipboardRequest};
+use wtmux_terminal::dcs::Passthrough;
+use wtmux_terminal::input::{self, MouseAction, MouseButton};
+use wtmux_terminal::keyboard;
+use wtmux_terminal::MouseTracking;
 
 use crate::copymode::CopyMode;
+use crate::pane::Pane;
 use crate::pastebuffer::PasteBuffer;
-use crate::renderer::Renderer;
+use crate::renderer::{DrawnFrame, Renderer};
 use crate::session::Session;
+use crate::window::Window;
+
+/// How often every pane's output is read, and each client's screen brought
+/// up to date between its messages (so that a frame held back by a
+/// synchronized update is drawn once the update ends or times out).
+const REFRESH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Most output read from one pane in one pass, so that a flood from one
+/// application doesn't hold up the others.
+const MAX_OUTPUT_PER_PASS: usize = 64 * 1024;
 
 /// Server-wide state accessible by the command executor.
 pub struct ServerState {
@@ -36,7 +54,18 @@ struct ConnectedClient {
     session_id: Option<SessionId>,
     cols: u16,
     rows: u16,
+    /// What the client's terminal can show.
+    features: ClientFeatures,
     copy_mode: Option<CopyMode>,
+    /// Bytes for the client's own terminal (such as OSC 52 clipboard
+    /// updates), sent ahead of its next render.
+    pending_output: Vec<u8>,
+    /// Message shown over the status bar (such as a visual bell) and when
+    /// it appeared.
+    status_message: Option<(String, Instant)>,
+    /// What the client's screen shows, so that frames can skip unchanged
+    /// pane rows.
+    drawn: DrawnFrame,
 }
 
 /// Shared inner state protected by a mutex for concurrent client access.
@@ -70,6 +99,17 @@ impl Server {
     pub async fn run(&mut self) -> Result<()> {
         info!("Server starting, waiting for connections...");
 
+        // Read the panes' output whether or not anyone is looking at them
+        let inner = Arc::clone(&self.inner);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(REFRESH_INTERVAL);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                inner.lock().await.read_pane_output().await;
+            }
+        });
+
         // Create first pipe instance
         let server = create_server(&self.pipe_name)?;
         self.accept_and_serve(server).await
@@ -90,7 +130,11 @@ impl Server {
                         session_id: None,
                         cols: 80,
                         rows: 24,
+                        features: ClientFeatures::default(),
                         copy_mode: None,
+                        pending_output: Vec::new(),
+                        status_message: None,
+                        drawn: DrawnFrame::default(),
                     },
                 );
             }
@@ -113,52 +157,81 @@ impl Server {
 async fn handle_client(
     inner: Arc<Mutex<ServerInner>>,
     client_id: ClientId,
-    mut pipe: NamedPipeServer,
+    pipe: NamedPipeServer,
 ) {
+    // Messages are read by a task of their own, so that the screen can be
+    // refreshed while waiting for the next one.
+    let (mut reader, mut writer) = tokio::io::split(pipe);
+    let (messages_tx, mut messages) = mpsc::channel(16);
+    let reader_task = tokio::spawn(async move {
+        loop {
+            let msg: Result<ClientMessage> = recv_message(&mut reader).await;
+            let failed = msg.is_err();
+            if messages_tx.send(msg).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+    refresh.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
     loop {
-        // Read next message from client (no lock held during I/O)
-        let msg: Result<ClientMessage> = recv_message(&mut pipe).await;
+        tokio::select! {
+            msg = messages.recv() => match msg {
+                Some(Ok(client_msg)) => {
+                    // Lock inner state, process the message
+                    let mut guard = inner.lock().await;
+                    let focused = guard.focused_pane(client_id);
+                    let response = guard.process_message(client_id, client_msg).await;
+                    guard.report_focus_change(client_id, focused).await;
 
-        match msg {
-            Ok(client_msg) => {
-                // Lock inner state, process the message
-                let mut guard = inner.lock().await;
-                let response = guard.process_message(client_id, client_msg).await;
-
-                match response {
-                    Some(ServerMessage::Detached) => {
-                        drop(guard); // release lock before I/O
-                        let _ = send_message(&mut pipe, &ServerMessage::Detached).await;
-                        break;
-                    }
-                    Some(msg) => {
-                        drop(guard);
-                        if let Err(e) = send_message(&mut pipe, &msg).await {
-                            error!("Failed to send message: {}", e);
+                    match response {
+                        Some(ServerMessage::Detached) => {
+                            drop(guard); // release lock before I/O
+                            let _ = send_message(&mut writer, &ServerMessage::Detached).await;
                             break;
                         }
-                    }
-                    None => {
-                        // Send updated screen after state change
-                        let output = guard.render_for_client(client_id);
-                        drop(guard);
-                        if let Some(output) = output {
-                            if let Err(e) =
-                                send_message(&mut pipe, &ServerMessage::Output(output)).await
-                            {
-                                error!("Failed to send output: {}", e);
+                        Some(msg) => {
+                            drop(guard);
+                            if let Err(e) = send_message(&mut writer, &msg).await {
+                                error!("Failed to send message: {}", e);
                                 break;
                             }
                         }
+                        None => {
+                            // Send updated screen after state change
+                            let output = guard.render_for_client(client_id);
+                            drop(guard);
+                            if let Some(output) = output {
+                                if let Err(e) =
+                                    send_message(&mut writer, &ServerMessage::Output(output)).await
+                                {
+                                    error!("Failed to send output: {}", e);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    debug!("Client read error: {}", e);
+                    break;
+                }
+                None => break,
+            },
+            _ = refresh.tick() => {
+                let output = inner.lock().await.render_for_client(client_id);
+                if let Some(output) = output.filter(|output| !output.is_empty()) {
+                    if let Err(e) = send_message(&mut writer, &ServerMessage::Output(output)).await
+                    {
+                        error!("Failed to send output: {}", e);
+                        break;
                     }
                 }
-            }
-            Err(e) => {
-                debug!("Client read error: {}", e);
-                break;
             }
         }
     }
+    reader_task.abort();
 
     // Clean up client on disconnect
     let mut guard = inner.lock().await;
@@ -178,6 +251,7 @@ impl ServerInner {
                 command,
                 cols,
                 rows,
+                features,
             } => {
                 let session_name =
                     name.unwrap_or_else(|| format!("{}", self.state.sessions.len()));
@@ -185,7 +259,9 @@ impl ServerInner {
                     self.state.config.options.default_shell.clone()
                 });
 
-                match Session::new(session_name.clone(), &shell, cols, rows) {
+                let history_limit = self.state.config.options.history_limit;
+
+                match Session::new(session_name.clone(), &shell, cols, rows, history_limit) {
                     Ok(session) => {
                         let session_id = session.id;
                         self.state.sessions.insert(session_id, session);
@@ -194,6 +270,7 @@ impl ServerInner {
                             client.session_id = Some(session_id);
                             client.cols = cols;
                             client.rows = rows;
+                            client.features = features;
                         }
 
                         info!("Session created: {} ({})", session_name, session_id);
@@ -213,6 +290,7 @@ impl ServerInner {
                 session,
                 cols,
                 rows,
+                features,
             } => {
                 let session_id = match &session {
                     SessionTarget::Name(name) => self
@@ -236,6 +314,7 @@ impl ServerInner {
                             client.session_id = Some(id);
                             client.cols = cols;
                             client.rows = rows;
+                            client.features = features;
                         }
 
                         if let Some(session) = self.state.sessions.get_mut(&id) {
@@ -257,45 +336,32 @@ impl ServerInner {
             ClientMessage::Detach => Some(ServerMessage::Detached),
 
             ClientMessage::Input(data) => {
-                if let Some(client) = self.clients.get(&client_id) {
-                    if let Some(session_id) = client.session_id {
-                        if let Some(session) = self.state.sessions.get_mut(&session_id) {
-                            let pane_id = session.active_pane_id();
-                            if let Some(pane) = session
-                                .active_window_mut()
-                                .panes
-                                .get_mut(&pane_id)
-                            {
-                                if let Err(e) = pane.write_input(&data).await {
-                                    error!("PTY write failed: {}", e);
-                                }
-
-                                // Read any available output with a timeout
-                                let mut buf = vec![0u8; 8192];
-                                loop {
-                                    match tokio::time::timeout(
-                                        std::time::Duration::from_millis(50),
-                                        pane.pty.read(&mut buf),
-                                    )
-                                    .await
-                                    {
-                                        Ok(Ok(n)) if n > 0 => {
-                                            pane.terminal.process_bytes(&buf[..n]);
-                                        }
-                                        _ => break,
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+                self.write_to_active_pane(client_id, |pane| {
+                    Some(input::apply_cursor_key_mode(pane.terminal.modes(), &data))
+                })
+                .await;
                 None // Will trigger a render
             }
 
+            ClientMessage::Key {
+                key,
+                modifiers,
+                kind,
+            } => {
+                self.write_to_active_pane(client_id, |pane| {
+                    let flags = pane.terminal.state.keyboard_flags();
+                    keyboard::encode_key(pane.terminal.modes(), flags, &key, modifiers, kind)
+                })
+                .await;
+                None
+            }
+
             ClientMessage::Resize { cols, rows } => {
                 if let Some(client) = self.clients.get_mut(&client_id) {
                     client.cols = cols;
                     client.rows = rows;
+                    // The client's terminal rewraps or clears what it showed.
+                    client.drawn.invalidate();
                     if let Some(session_id) = client.session_id {
                         if let Some(session) = self.state.sessions.get_mut(&session_id) {
                             let _ = session.resize(cols, rows);
@@ -316,12 +382,16 @@ impl ServerInner {
 
             ClientMessage::SplitPane { horizontal } => {
                 let shell = self.state.config.options.default_shell.clone();
+                let history_limit = self.state.config.options.history_limit;
                 if let Some(session_id) = self.get_client_session(client_id) {
                     if let Some(session) = self.state.sessions.get_mut(&session_id) {
-                        match session
-                            .active_window_mut()
-                            .split_pane(&shell, horizontal)
-                        {
+                        let cwd = session.default_start_dir();
+                        match session.active_window_mut().split_pane(
+                            &shell,
+                            horizontal,
+                            history_limit,
+                            cwd.as_deref(),
+                        ) {
                             Ok(_) => {}
                             Err(e) => {
                                 return Some(ServerMessage::Error(format!(
@@ -359,11 +429,19 @@ impl ServerInner {
                 let shell = command.unwrap_or_else(|| {
                     self.state.config.options.default_shell.clone()
                 });
+                let history_limit = self.state.config.options.history_limit;
                 if let Some(session_id) = self.get_client_session(client_id) {
                     if let Some(client) = self.clients.get(&client_id) {
                         if let Some(session) = self.state.sessions.get_mut(&session_id) {
-                            let _ =
-                                session.new_window(name, &shell, client.cols, client.rows);
+                            let cwd = session.default_start_dir();
+                            let _ = session.new_window(
+                                name,
+                                &shell,
+                                client.cols,
+                                client.rows,
+                                history_limit,
+                                cwd.as_deref(),
+                            );
                         }
                     }
                 }
@@ -514,8 +592,9 @@ impl ServerInner {
                                 if let Some(pane) =
                                     session.active_window().panes.get(&pane_id)
                                 {
+                                    let wrap = self.state.config.options.wrap_search;
                                     if let Some(text) =
-                                        copy_mode.handle_action(&action, &pane.terminal)
+                                        copy_mode.handle_action(&action, &pane.terminal, wrap)
                                     {
                                         self.state.paste_buffer.push(text);
                                     }
@@ -539,7 +618,11 @@ impl ServerInner {
                             if let Some(pane) =
                                 session.active_window_mut().panes.get_mut(&pane_id)
                             {
-                                let _ = pane.write_input(text.as_bytes()).await;
+                                let data = input::encode_paste(
+                                    pane.terminal.modes(),
+                                    text.as_bytes(),
+                                );
+                                let _ = pane.write_input(&data).await;
                             }
                         }
                     }
@@ -552,6 +635,11 @@ impl ServerInner {
                     Ok(Some(result)) => {
                         if result == "__detach__" {
                             Some(ServerMessage::Detached)
+                        } else if result == "__refresh__" {
+                            if let Some(client) = self.clients.get_mut(&client_id) {
+                                client.drawn.invalidate();
+                            }
+                            None
                         } else if result.starts_with("__") {
                             // Internal commands handled separately
                             Some(ServerMessage::Notification(result))
@@ -565,16 +653,25 @@ impl ServerInner {
             }
 
             ClientMessage::MouseEvent { kind, col, row } => {
-                use wtmux_common::protocol::MouseEventKind;
-
                 if !self.state.config.options.mouse {
                     return None;
                 }
 
+                let in_copy_mode = self
+                    .clients
+                    .get(&client_id)
+                    .is_some_and(|client| client.copy_mode.is_some());
+
                 if let Some(session_id) = self.get_client_session(client_id) {
                     if let Some(session) = self.state.sessions.get_mut(&session_id) {
                         let window = session.active_window_mut();
 
+                        // Let the application under the pointer handle the
+                        // event if it asked for mouse reports.
+                        if !in_copy_mode && forward_mouse_event(window, kind, col, row).await {
+                            return None;
+                        }
+
                         match kind {
                             MouseEventKind::Click => {
                                 // Find which pane was clicked
@@ -596,11 +693,16 @@ impl ServerInner {
                             MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
                                 // Scroll the active pane's copy mode, or send scroll keys
                                 let pane_id = window.active_pane;
+                                let history = window
+                                    .panes
+                                    .get(&pane_id)
+                                    .map_or(0, |pane| pane.terminal.state.history_len());
                                 if let Some(client) = self.clients.get_mut(&client_id) {
                                     if let Some(ref mut copy_mode) = client.copy_mode {
                                         match kind {
                                             MouseEventKind::ScrollUp => {
-                                                copy_mode.scroll_offset += 3;
+                                                copy_mode.scroll_offset =
+                                                    (copy_mode.scroll_offset + 3).min(history);
                                             }
                                             MouseEventKind::ScrollDown => {
                                                 copy_mode.scroll_offset =
@@ -627,13 +729,14 @@ impl ServerInner {
                                                 };
                                                 let mut cm =
                                                     crate::copymode::CopyMode::new(cx, cy);
-                                                cm.scroll_offset = 3;
+                                                cm.scroll_offset = history.min(3);
                                                 client.copy_mode = Some(cm);
                                             }
                                         }
                                     }
                                 }
                             }
+                            MouseEventKind::Release | MouseEventKind::Drag => {}
                         }
                     }
                 }
@@ -648,19 +751,377 @@ impl ServerInner {
         self.clients.get(&client_id)?.session_id
     }
 
-    fn render_for_client(&self, client_id: ClientId) -> Option<Vec<u8>> {
-        let client = self.clients.get(&client_id)?;
-        let session_id = client.session_id?;
+    /// The pane that has focus for a client.
+    fn focused_pane(&self, client_id: ClientId) -> Option<(SessionId, PaneId)> {
+        let session_id = self.get_client_session(client_id)?;
         let session = self.state.sessions.get(&session_id)?;
+        Some((session_id, session.active_pane_id()))
+    }
+
+    /// Write input to the client's active pane and read what the
+    /// application prints in reply. `encode` produces the bytes for the pane,
+    /// or None if there is nothing to send.
+    async fn write_to_active_pane<F>(&mut self, client_id: ClientId, encode: F)
+    where
+        F: FnOnce(&Pane) -> Option<Vec<u8>>,
+    {
+        let Some((session_id, pane_id)) = self.focused_pane(client_id) else {
+            return;
+        };
+        let Some(pane) = self.pane_mut(session_id, pane_id) else {
+            return;
+        };
+        let Some(data) = encode(pane) else {
+            return;
+        };
+        if let Err(e) = pane.write_input(&data).await {
+            error!("PTY write failed: {}", e);
+        }
+
+        // Read any available output with a timeout
+        let mut buf = vec![0u8; 8192];
+        while let Some(pane) = self.pane_mut(session_id, pane_id) {
+            match tokio::time::timeout(
+                std::time::Duration::from_millis(50),
+                pane.pty.read(&mut buf),
+            )
+            .await
+            {
+                Ok(Ok(n)) if n > 0 => {
+                    self.process_pane_output(session_id, pane_id, &buf[..n]).await;
+                }
+                _ => break,
+            }
+        }
+        self.handle_pane_output(session_id, pane_id).await;
+    }
+
+    fn pane_mut(&mut self, session_id: SessionId, pane_id: PaneId) -> Option<&mut Pane> {
+        self.state.sessions.get_mut(&session_id)?.pane_mut(pane_id)
+    }
+
+    /// Feed output to a pane. Each passthrough sequence in it goes to the
+    /// session's clients right after the frame showing what came before
+    /// it, so that it lands on the contents it was sent over.
+    async fn process_pane_output(
+        &mut self,
+        session_id: SessionId,
+        pane_id: PaneId,
+        mut data: &[u8],
+    ) {
+        while !data.is_empty() {
+            let Some(pane) = self.pane_mut(session_id, pane_id) else {
+                return;
+            };
+            match pane.process_output(data).await {
+                Ok(processed) => data = &data[processed..],
+                Err(e) => {
+                    error!("PTY write failed: {}", e);
+                    return;
+                }
+            }
+            let passthrough = pane.terminal.state.take_passthrough();
+            self.forward_passthrough(session_id, pane_id, passthrough);
+        }
+    }
+
+    /// Read what the application in every pane printed since the last pass,
+    /// so that background windows and sessions nobody is attached to keep up
+    /// and ring their bells.
+    async fn read_pane_output(&mut self) {
+        let panes: Vec<(SessionId, PaneId)> = self
+            .state
+            .sessions
+            .iter()
+            .flat_map(|(&session_id, session)| {
+                session
+                    .windows
+                    .iter()
+                    .flat_map(move |window| window.panes.keys().map(move |&id| (session_id, id)))
+            })
+            .collect();
+        for (session_id, pane_id) in panes {
+            let Some(pane) = self.pane_mut(session_id, pane_id) else {
+                continue;
+            };
+            let output = pane.read_ready(MAX_OUTPUT_PER_PASS).await;
+            if output.is_empty() {
+                continue;
+            }
+            self.process_pane_output(session_id, pane_id, &output).await;
+            self.handle_pane_output(session_id, pane_id).await;
+        }
+    }
+
+    /// Act on what a pane's output asked for: clipboard updates and bells.
+    async fn handle_pane_output(&mut self, session_id: SessionId, pane_id: PaneId) {
+        let Some(pane) = self.pane_mut(session_id, pane_id) else {
+            return;
+        };
+        let clipboard_requests = pane.terminal.state.take_clipboard_requests();
+        self.handle_clipboard_requests(session_id, pane_id, clipboard_requests)
+            .await;
+        self.alert_bells(session_id);
+    }
+
+    /// Flag the windows whose panes rang the bell and alert the session's
+    /// clients as `bell-action` and `visual-bell` say.
+    fn alert_bells(&mut self, session_id: SessionId) {
+        let bell_action = self.state.config.options.bell_action;
+        let visual_bell = self.state.config.options.visual_bell;
+        let Some(session) = self.state.sessions.get_mut(&session_id) else {
+            return;
+        };
+
+        let current_idx = session.active_window_idx;
+        let mut alert = None;
+        for (i, window) in session.windows.iter_mut().enumerate() {
+            let mut rang = false;
+            for pane in window.panes.values_mut() {
+                rang |= std::mem::take(&mut pane.bell);
+            }
+            if !rang {
+                continue;
+            }
+            let current = i == current_idx;
+            window.bell |= !current;
+            if bell_action.alerts(current) {
+                alert = Some(if current {
+                    "Bell in current window".to_string()
+                } else {
+                    format!("Bell in window {}", window.index)
+                });
+            }
+        }
+
+        let Some(message) = alert else {
+            return;
+        };
+        for client in self.clients.values_mut() {
+            if client.session_id != Some(session_id) {
+                continue;
+            }
+            if visual_bell != VisualBell::On {
+                client.pending_output.push(0x07);
+            }
+            if visual_bell != VisualBell::Off {
+                client.status_message = Some((message.clone(), Instant::now()));
+            }
+        }
+    }
+
+    /// Send focus out/in reports if the client's focused pane changed.
+    async fn report_focus_change(
+        &mut self,
+        client_id: ClientId,
+        previous: Option<(SessionId, PaneId)>,
+    ) {
+        let current = self.focused_pane(client_id);
+        if current == previous {
+            return;
+        }
+        for (target, focused) in [(previous, false), (current, true)] {
+            let Some((session_id, pane_id)) = target else {
+                continue;
+            };
+            let Some(pane) = self
+                .state
+                .sessions
+                .get_mut(&session_id)
+                .and_then(|session| session.pane_mut(pane_id))
+            else {
+                continue;
+            };
+            if let Some(report) = input::encode_focus(pane.terminal.modes(), focused) {
+                let _ = pane.write_input(report).await;
+            }
+        }
+    }
+
+    /// Apply OSC 52 requests from a pane according to the `set-clipboard`
+    /// and `get-clipboard` options.
+    async fn handle_clipboard_requests(
+        &mut self,
+        session_id: SessionId,
+        pane_id: PaneId,
+        requests: Vec<ClipboardRequest>,
+    ) {
+        for request in requests {
+            match request {
+                ClipboardRequest::Set { selection, text } => {
+                    let set_clipboard = self.state.config.options.set_clipboard;
+                    if set_clipboard == SetClipboard::Off {
+                        continue;
+                    }
+                    if set_clipboard == SetClipboard::External {
+                        let sequence = encode_osc52(&selection, &text);
+                        for client in self.clients.values_mut() {
+                            if client.session_id == Some(session_id) {
+                                client.pending_output.extend_from_slice(&sequence);
+                            }
+                        }
+                    }
+                    self.state.paste_buffer.push(text);
+                }
+                ClipboardRequest::Query { selection } => {
+                    if !self.state.config.options.get_clipboard {
+                        continue;
+                    }
+                    let text = self.state.paste_buffer.top().unwrap_or("");
+                    let reply = encode_osc52(&selection, text);
+                    if let Some(pane) = self
+                        .state
+                        .sessions
+                        .get_mut(&session_id)
+                        .and_then(|session| session.pane_mut(pane_id))
+                    {
+                        let _ = pane.write_input(&reply).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queue `DCS tmux;` passthrough sequences from a pane for the clients
+    /// showing it, as `allow-passthrough` permits, behind a frame showing
+    /// the pane as it was when they arrived. Each is sent with the cursor
+    /// where it was in the pane, then the cursor is put back.
+    fn forward_passthrough(
+        &mut self,
+        session_id: SessionId,
+        pane_id: PaneId,
+        passthrough: Vec<Passthrough>,
+    ) {
+        let allow = self.state.config.options.allow_passthrough;
+        if allow == AllowPassthrough::Off || passthrough.is_empty() {
+            return;
+        }
+        let Some(session) = self.state.sessions.get(&session_id) else {
+            return;
+        };
+        let rect = session
+            .active_window()
+            .pane_geometries()
+            .get(&pane_id)
+            .copied();
+        if rect.is_none() && allow != AllowPassthrough::All {
+            return;
+        }
+
+        let mut sequence = Vec::new();
+        for item in passthrough {
+            sequence.extend_from_slice(b"\x1b7");
+            if let Some(rect) = rect {
+                sequence.extend_from_slice(
+                    format!("\x1b[{};{}H", rect.y + item.row + 1, rect.x + item.col + 1)
+                        .as_bytes(),
+                );
+            }
+            sequence.extend_from_slice(&item.data);
+            sequence.extend_from_slice(b"\x1b8");
+        }
+        let client_ids: Vec<ClientId> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.session_id == Some(session_id))
+            .map(|(&client_id, _)| client_id)
+            .collect();
+        for client_id in client_ids {
+            let Some(mut output) = self.render_for_client(client_id) else {
+                continue;
+            };
+            output.extend_from_slice(&sequence);
+            if let Some(client) = self.clients.get_mut(&client_id) {
+                client.pending_output = output;
+            }
+        }
+    }
+
+    fn render_for_client(&mut self, client_id: ClientId) -> Option<Vec<u8>> {
+        let client = self.clients.get_mut(&client_id)?;
+        let session_id = client.session_id?;
+        let session = self.state.sessions.get_mut(&session_id)?;
+
+        // The bell flag is cleared once the window is shown.
+        session.active_window_mut().bell = false;
+
+        client.drawn.set_styled_underlines(
+            self.state.config.options.styled_underlines && client.features.styled_underlines,
+        );
 
         let renderer = Renderer::new(client.cols, client.rows);
-        let mut output = renderer.render(session);
+        let scroll_offset = client.copy_mode.as_ref().map_or(0, |cm| cm.scroll_offset);
+        let mut frame = renderer.compose(session, scroll_offset, &mut client.drawn);
 
         // Add copy mode overlay if active
         if let Some(ref copy_mode) = client.copy_mode {
-            output.extend_from_slice(&copy_mode.render_indicator());
+            frame.screen.put_overlay(0, 0, copy_mode.indicator(), false);
+        }
+
+        let display_time = Duration::from_millis(self.state.config.options.display_time);
+        match &client.status_message {
+            Some((text, shown)) if shown.elapsed() < display_time => {
+                let row = client.rows.saturating_sub(1);
+                frame.screen.put_overlay(0, row, text, true);
+            }
+            _ => client.status_message = None,
         }
 
+        let mut output = std::mem::take(&mut client.pending_output);
+        output.extend_from_slice(&client.drawn.update(frame));
+
         Some(output)
     }
 }
+
+/// Forward a mouse event to the pane under the pointer when its application
+/// asked for mouse reports, or turn wheel scrolls into cursor keys on its
+/// alternate screen. Returns true if the event was consumed.
+async fn forward_mouse_event(
+    window: &mut Window,
+    kind: MouseEventKind,
+    col: u16,
+    row: u16,
+) -> bool {
+    let Some((pane_id, rect)) = window.pane_geometries().into_iter().find(|(_, rect)| {
+        col >= rect.x && col < rect.x + rect.width && row >= rect.y && row < rect.y + rect.height
+    }) else {
+        return false;
+    };
+    let Some(pane) = window.panes.get_mut(&pane_id) else {
+        return false;
+    };
+
+    let (button, action) = match kind {
+        MouseEventKind::Click => (MouseButton::Left, MouseAction::Press),
+        MouseEventKind::Release => (MouseButton::Left, MouseAction::Release),
+        MouseEventKind::Drag => (MouseButton::Left, MouseAction::Drag),
+        MouseEventKind::ScrollUp => (MouseButton::WheelUp, MouseAction::Press),
+        MouseEventKind::ScrollDown => (MouseButton::WheelDown, MouseAction::Press),
+    };
+    let modes = *pane.terminal.modes();
+
+    let data = if let Some(report) =
+        input::encode_mouse(&modes, button, action, col - rect.x, row - rect.y)
+    {
+        report
+    } else if modes.mouse_tracking == MouseTracking::Off
+        && modes.alternate_scroll
+        && pane.terminal.state.using_alt_screen
+        && matches!(kind, MouseEventKind::ScrollUp | MouseEventKind::ScrollDown)
+    {
+        input::encode_wheel_as_keys(&modes, matches!(kind, MouseEventKind::ScrollUp), 3)
+    } else {
+        return false;
+    };
+
+    if let Err(e) = pane.write_input(&data).await {
+        error!("PTY write failed: {}", e);
+    }
+    // Clicking still selects the pane, as it does without mouse reporting.
+    if matches!(kind, MouseEventKind::Click) && pane_id != window.active_pane {
+        window.last_active_pane = Some(window.active_pane);
+        window.active_pane = pane_id;
+    }
+    true
+}
@@ -1,4 +1,5 @@
 use anyhow::Result;
+use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 use wtmux_common::{PaneId, SessionId, WindowId};
 use wtmux_layout::geometry::Rect;
@@ -18,10 +19,16 @@ pub struct Session {
 }
 
 impl Session {
-    pub fn new(name: String, command: &str, cols: u16, rows: u16) -> Result<Self> {
+    pub fn new(
+        name: String,
+        command: &str,
+        cols: u16,
+        rows: u16,
+        history_limit: usize,
+    ) -> Result<Self> {
         let id = SessionId::new();
         let area = Rect::new(0, 0, cols, rows.saturating_sub(1)); // Reserve 1 row for status bar
-        let pane = Pane::new(command, area.width, area.height)?;
+        let pane = Pane::new(command, area.width, area.height, history_limit, None)?;
 
         let window = Window::new("cmd".to_string(), 0, pane, area);
 
@@ -56,10 +63,40 @@ impl Session {
         self.active_window().active_pane
     }
 
+    /// Working directory of the active pane, where new panes start by default.
+    pub fn active_pane_path(&self) -> Option<String> {
+        let window = self.active_window();
+        let pane = window.panes.get(&window.active_pane)?;
+        pane.current_path().map(str::to_string)
+    }
+
+    /// Where new panes start when no directory is given: the active pane's
+    /// path if it is a local directory. A shell inside ssh or WSL reports
+    /// paths that new processes cannot start in; those are left out and the
+    /// default directory is used instead.
+    pub fn default_start_dir(&self) -> Option<String> {
+        self.active_pane_path().filter(|path| Path::new(path).is_dir())
+    }
+
+    /// Find a pane in any window of this session.
+    pub fn pane_mut(&mut self, pane_id: PaneId) -> Option<&mut Pane> {
+        self.windows
+            .iter_mut()
+            .find_map(|window| window.panes.get_mut(&pane_id))
+    }
+
     /// Create a new window.
-    pub fn new_window(&mut self, name: Option<String>, command: &str, cols: u16, rows: u16) -> Result<WindowId> {
+    pub fn new_window(
+        &mut self,
+        name: Option<String>,
+        command: &str,
+        cols: u16,
+        rows: u16,
+        history_limit: usize,
+        cwd: Option<&str>,
+    ) -> Result<WindowId> {
         let area = Rect::new(0, 0, cols, rows.saturating_sub(1));
-        let pane = Pane::new(command, area.width, area.height)?;
+        let pane = Pane::new(command, area.width, area.height, history_limit, cwd)?;
         let idx = self.next_window_index;
         self.next_window_index += 1;
 
@@ -155,6 +192,7 @@ impl Session {
                 index: w.index,
                 name: w.name.clone(),
                 active: i == self.active_window_idx,
+                bell: w.bell,
                 pane_count: w.pane_count(),
             })
             .collect()
@@ -18,6 +18,8 @@ pub struct Window {
     pub active_pane: PaneId,
     pub last_active_pane: Option<PaneId>,
     pub zoomed_pane: Option<PaneId>,
+    /// A pane rang the bell while the window was not the current one.
+    pub bell: bool,
     layout_preset: usize,
     area: Rect,
 }
@@ -38,13 +40,20 @@ impl Window {
             active_pane: pane_id,
             last_active_pane: None,
             zoomed_pane: None,
+            bell: false,
             layout_preset: 0,
             area,
         }
     }
 
     /// Split the active pane.
-    pub fn split_pane(&mut self, command: &str, horizontal: bool) -> Result<PaneId> {
+    pub fn split_pane(
+        &mut self,
+        command: &str,
+        horizontal: bool,
+        history_limit: usize,
+        cwd: Option<&str>,
+    ) -> Result<PaneId> {
         // Calculate the active pane's current geometry
         let geos = self.layout.calculate_geometries(self.pane_area());
         let active_geo = geos
@@ -64,7 +73,7 @@ impl Window {
             Orientation::Vertical => (active_geo.width, active_geo.height / 2),
         };
 
-        let new_pane = Pane::new(command, cols.max(1), rows.max(1))?;
+        let new_pane = Pane::new(command, cols.max(1), rows.max(1), history_limit, cwd)?;
         let new_pane_id = new_pane.id;
 
         self.layout.split_pane(
@@ -0,0 +1,124 @@
+//! Memory taken by 10,000 lines of history, kept as rows of full cells, in
+//! a `Grid` of compact cells and packed in a `Scrollback`.
+//!
+//! Run with `cargo bench -p wtmux-terminal --bench history_memory`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wtmux_terminal::cell::{Cell, Color, GridCell};
+use wtmux_terminal::scrollback::Scrollback;
+use wtmux_terminal::{Grid, Row};
+
+/// Passes allocations to the system allocator, counting the bytes in use.
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const LINES: usize = 10_000;
+const COLS: u16 = 120;
+
+/// A directory listing: mostly plain text, a colored column, the rest blank.
+fn listing_line(n: usize) -> Row {
+    let text = format!(
+        "{:>6}  -rw-r--r--  1 user staff  {:>6} Oct 16 12:00 src/module_{}.rs",
+        n,
+        n * 37 % 100_000,
+        n % 97
+    );
+    let mut row = Row::new(COLS);
+    for (col, ch) in text.chars().enumerate() {
+        row[col] = Cell::new(ch);
+    }
+    for cell in &mut row[..6] {
+        cell.fg = Color::Indexed(3);
+    }
+    row
+}
+
+/// A full line of wide characters in changing colors, the worst case for
+/// packing.
+fn colorful_line(n: usize) -> Row {
+    let mut row = Row::new(COLS);
+    for col in (0..COLS as usize).step_by(2) {
+        let shade = ((n + col) % 256) as u8;
+        let cell = Cell {
+            ch: '漢',
+            width: 2,
+            ..Cell::default()
+        }
+        .with_fg(Color::Rgb(shade, 255 - shade, 128));
+        row[col + 1] = Cell {
+            ch: ' ',
+            width: 0,
+            ..cell.clone()
+        };
+        row[col] = cell;
+    }
+    row
+}
+
+/// Bytes still allocated for the value `build` returns.
+fn allocated_by<T>(build: impl FnOnce() -> T) -> (usize, T) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let value = build();
+    (ALLOCATED.load(Ordering::Relaxed) - before, value)
+}
+
+fn compare(name: &str, line: fn(usize) -> Row) {
+    let (cells, rows) = allocated_by(|| (0..LINES).map(line).collect::<Vec<_>>());
+    black_box(&rows);
+    drop(rows);
+
+    let (grid, screen) = allocated_by(|| {
+        Grid::from_rows(COLS, LINES as u16, (0..LINES).map(line).collect())
+    });
+    black_box(&screen);
+    drop(screen);
+
+    let (packed, scrollback) = allocated_by(|| {
+        let mut scrollback = Scrollback::new(LINES);
+        for n in 0..LINES {
+            scrollback.push_line(line(n));
+        }
+        scrollback
+    });
+    black_box(&scrollback);
+
+    let mib = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:<10} full cells {:>7.2} MiB   grid {:>7.2} MiB   packed {:>7.2} MiB   ({:.1}%)",
+        name,
+        mib(cells),
+        mib(grid),
+        mib(packed),
+        packed as f64 * 100.0 / cells as f64
+    );
+}
+
+fn main() {
+    println!(
+        "{} lines of {} columns, {} bytes per full cell, {} per grid cell",
+        LINES,
+        COLS,
+        std::mem::size_of::<Cell>(),
+        std::mem::size_of::<GridCell>()
+    );
+    compare("listing", listing_line);
+    compare("colorful", colorful_line);
+}
@@ -1,7 +1,8 @@
+use crate::style::{Style, StyleTable};
 use serde::{Deserialize, Serialize};
 
 /// Terminal color.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Color {
     Default,
     Indexed(u8),
@@ -14,37 +15,86 @@ impl Default for Color {
     }
 }
 
+/// Underline style, set with SGR 4, 4:x and 21.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UnderlineStyle {
+    #[default]
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+impl UnderlineStyle {
+    /// The style selected by the subparameter of `SGR 4:x`.
+    pub fn from_sgr(style: u16) -> Option<Self> {
+        match style {
+            0 => Some(UnderlineStyle::None),
+            1 => Some(UnderlineStyle::Single),
+            2 => Some(UnderlineStyle::Double),
+            3 => Some(UnderlineStyle::Curly),
+            4 => Some(UnderlineStyle::Dotted),
+            5 => Some(UnderlineStyle::Dashed),
+            _ => None,
+        }
+    }
+}
+
+/// On/off text attributes, one bit each.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AttrFlags(u8);
+
+bitflags::bitflags! {
+    impl AttrFlags: u8 {
+        const BOLD = 1 << 0;
+        const DIM = 1 << 1;
+        const ITALIC = 1 << 2;
+        const BLINK = 1 << 3;
+        const REVERSE = 1 << 4;
+        const HIDDEN = 1 << 5;
+        const STRIKETHROUGH = 1 << 6;
+        const OVERLINE = 1 << 7;
+    }
+}
+
 /// Text attributes (bold, italic, etc.)
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Attrs {
-    pub bold: bool,
-    pub italic: bool,
-    pub underline: bool,
-    pub blink: bool,
-    pub reverse: bool,
-    pub hidden: bool,
-    pub strikethrough: bool,
+    pub flags: AttrFlags,
+    pub underline: UnderlineStyle,
+    /// Underline color (SGR 58); Default draws it in the foreground color.
+    pub underline_color: Color,
 }
 
 /// A single cell in the terminal grid.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Cell {
+    /// First character of the cell's grapheme cluster.
     pub ch: char,
+    /// Characters following `ch` in its grapheme cluster (combining marks,
+    /// variation selectors, emoji modifiers and ZWJ sequences), if any.
+    pub combining: Option<Box<str>>,
     pub fg: Color,
     pub bg: Color,
     pub attrs: Attrs,
     /// Width of this character (1 for normal, 2 for wide/CJK).
     pub width: u8,
+    /// Id in the terminal's hyperlink table, or 0 if the cell is not a link.
+    pub hyperlink: u32,
 }
 
 impl Default for Cell {
     fn default() -> Self {
         Cell {
             ch: ' ',
+            combining: None,
             fg: Color::Default,
             bg: Color::Default,
             attrs: Attrs::default(),
             width: 1,
+            hyperlink: 0,
         }
     }
 }
@@ -72,8 +122,116 @@ impl Cell {
         self
     }
 
+    /// Add a character to the cell's grapheme cluster.
+    pub fn push_combining(&mut self, c: char) {
+        let mut combining = self.combining.take().map(String::from).unwrap_or_default();
+        combining.push(c);
+        self.combining = Some(combining.into_boxed_str());
+    }
+
+    /// Append the cell's whole grapheme cluster to `text`.
+    pub fn push_grapheme(&self, text: &mut String) {
+        text.push(self.ch);
+        if let Some(combining) = &self.combining {
+            text.push_str(combining);
+        }
+    }
+
+    /// Append the cell's whole grapheme cluster to `output` as UTF-8.
+    pub fn write_grapheme(&self, output: &mut Vec<u8>) {
+        let mut buf = [0u8; 4];
+        output.extend_from_slice(self.ch.encode_utf8(&mut buf).as_bytes());
+        if let Some(combining) = &self.combining {
+            output.extend_from_slice(combining.as_bytes());
+        }
+    }
+
     /// Returns true if this cell is just a blank space with default colors.
     pub fn is_empty(&self) -> bool {
-        self.ch == ' ' && self.fg == Color::Default && self.bg == Color::Default && self.attrs == Attrs::default()
+        self.ch == ' ' && self.combining.is_none() && self.fg == Color::Default && self.bg == Color::Default && self.attrs == Attrs::default() && self.hyperlink == 0
+    }
+}
+
+/// A cell as the grid stores it: colors, attributes and link are kept as one
+/// id in the terminal's style table, so that a screen of cells stays small.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GridCell {
+    /// First character of the cell's grapheme cluster.
+    pub ch: char,
+    /// Id in the terminal's style table; 0 is the default style.
+    pub style: u32,
+    /// Width of this character (1 for normal, 2 for wide/CJK, 0 for the
+    /// cell after a wide character).
+    pub width: u8,
+    /// Characters following `ch` in its grapheme cluster, boxed twice so
+    /// that cells without any only pay for a thin pointer.
+    combining: Option<Box<Box<str>>>,
+}
+
+impl Default for GridCell {
+    fn default() -> Self {
+        GridCell::new(' ', 1, 0)
+    }
+}
+
+impl GridCell {
+    pub fn new(ch: char, width: u8, style: u32) -> Self {
+        GridCell {
+            ch,
+            style,
+            width,
+            combining: None,
+        }
+    }
+
+    /// Store `cell`, adding its style to `styles` if it is new.
+    pub fn from_cell(cell: &Cell, styles: &mut StyleTable) -> Self {
+        GridCell {
+            ch: cell.ch,
+            style: styles.intern(Style::of(cell)),
+            width: cell.width,
+            combining: cell.combining.clone().map(Box::new),
+        }
+    }
+
+    /// The full cell, with its style looked up in `styles`.
+    pub fn to_cell(&self, styles: &StyleTable) -> Cell {
+        let mut cell = Cell {
+            ch: self.ch,
+            combining: self.combining.as_deref().cloned(),
+            width: self.width,
+            ..Cell::default()
+        };
+        styles.get(self.style).apply(&mut cell);
+        cell
+    }
+
+    /// Characters following `ch` in the cell's grapheme cluster, if any.
+    pub fn combining(&self) -> Option<&str> {
+        self.combining.as_deref().map(|combining| &**combining)
+    }
+
+    pub fn set_combining(&mut self, combining: Option<Box<str>>) {
+        self.combining = combining.map(Box::new);
+    }
+
+    /// Add a character to the cell's grapheme cluster.
+    pub fn push_combining(&mut self, c: char) {
+        let mut combining = self.combining().map(String::from).unwrap_or_default();
+        combining.push(c);
+        self.set_combining(Some(combining.into_boxed_str()));
+    }
+
+    /// Append the cell's whole grapheme cluster to `text`.
+    pub fn push_grapheme(&self, text: &mut String) {
+        text.push(self.ch);
+        if let Some(combining) = self.combining() {
+            text.push_str(combining);
+        }
+    }
+
+    /// Returns true if this cell is just a blank space in the default style.
+    pub fn is_empty(&self) -> bool {
+        self.ch == ' ' && self.combining.is_none() && self.style == 0
     }
 }
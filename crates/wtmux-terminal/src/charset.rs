@@ -0,0 +1,110 @@
+/// A character set that can be designated into G0-G3.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Charset {
+    #[default]
+    Ascii,
+    /// DEC special graphics (line drawing), designated with `0`.
+    DecSpecialGraphics,
+    /// British national set, designated with `A`.
+    Uk,
+}
+
+impl Charset {
+    /// Charset for the final byte of a designation sequence (`ESC ( 0` etc.).
+    pub fn from_designator(byte: u8) -> Option<Self> {
+        match byte {
+            b'B' => Some(Charset::Ascii),
+            b'0' => Some(Charset::DecSpecialGraphics),
+            b'A' => Some(Charset::Uk),
+            _ => None,
+        }
+    }
+
+    /// Translate a printed character through this set.
+    pub fn map(self, c: char) -> char {
+        match self {
+            Charset::Ascii => c,
+            Charset::Uk => {
+                if c == '#' {
+                    '£'
+                } else {
+                    c
+                }
+            }
+            Charset::DecSpecialGraphics => match c {
+                '_' => '\u{a0}',
+                '`' => '◆',
+                'a' => '▒',
+                'b' => '␉',
+                'c' => '␌',
+                'd' => '␍',
+                'e' => '␊',
+                'f' => '°',
+                'g' => '±',
+                'h' => '␤',
+                'i' => '␋',
+                'j' => '┘',
+                'k' => '┐',
+                'l' => '┌',
+                'm' => '└',
+                'n' => '┼',
+                'o' => '⎺',
+                'p' => '⎻',
+                'q' => '─',
+                'r' => '⎼',
+                's' => '⎽',
+                't' => '├',
+                'u' => '┤',
+                'v' => '┴',
+                'w' => '┬',
+                'x' => '│',
+                'y' => '≤',
+                'z' => '≥',
+                '{' => 'π',
+                '|' => '≠',
+                '}' => '£',
+                '~' => '·',
+                _ => c,
+            },
+        }
+    }
+}
+
+/// G0-G3 designations plus the shift state selecting which one is in use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CharsetState {
+    sets: [Charset; 4],
+    /// Set invoked into GL by SI/SO/LS2/LS3.
+    active: usize,
+    /// Set used for the next character only (SS2/SS3).
+    single_shift: Option<usize>,
+}
+
+impl CharsetState {
+    /// Designate `charset` into G`slot` (0-3).
+    pub fn designate(&mut self, slot: usize, charset: Charset) {
+        if let Some(set) = self.sets.get_mut(slot) {
+            *set = charset;
+        }
+    }
+
+    /// Invoke G`slot` into GL until the next shift (SI, SO, LS2, LS3).
+    pub fn shift(&mut self, slot: usize) {
+        if slot < self.sets.len() {
+            self.active = slot;
+        }
+    }
+
+    /// Use G`slot` for the next printed character only (SS2, SS3).
+    pub fn single_shift(&mut self, slot: usize) {
+        if slot < self.sets.len() {
+            self.single_shift = Some(slot);
+        }
+    }
+
+    /// Translate a printed character, consuming any pending single shift.
+    pub fn translate(&mut self, c: char) -> char {
+        let slot = self.single_shift.take().unwrap_or(self.active);
+        self.sets[slot].map(c)
+    }
+}
@@ -0,0 +1,38 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// A clipboard request made by the application with OSC 52.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardRequest {
+    /// Store `text` in the selections named by `selection` (e.g. `c`, `p`).
+    Set { selection: String, text: String },
+    /// Ask for the contents of the selections named by `selection`.
+    Query { selection: String },
+}
+
+impl ClipboardRequest {
+    /// Parse the selection and data parts of `OSC 52 ; Pc ; Pd`. Returns
+    /// None if the data is not valid base64.
+    pub fn parse(selection: &[u8], data: &[u8]) -> Option<Self> {
+        let selection = if selection.is_empty() {
+            // xterm treats an empty selection as "s 0"
+            "s0".to_string()
+        } else {
+            String::from_utf8_lossy(selection).into_owned()
+        };
+        if data == b"?" {
+            return Some(ClipboardRequest::Query { selection });
+        }
+        let decoded = STANDARD.decode(data).ok()?;
+        Some(ClipboardRequest::Set {
+            selection,
+            text: String::from_utf8_lossy(&decoded).into_owned(),
+        })
+    }
+}
+
+/// Encode an OSC 52 sequence carrying `text` for `selection`, used both to
+/// answer queries and to pass a copy on to the outer terminal.
+pub fn encode_osc52(selection: &str, text: &str) -> Vec<u8> {
+    format!("\x1b]52;{};{}\x1b\\", selection, STANDARD.encode(text)).into_bytes()
+}
@@ -0,0 +1,84 @@
+/// Longest DCS string or passthrough payload kept; anything longer is
+/// dropped rather than buffered without bound.
+const MAX_DCS_LEN: usize = 4 * 1024 * 1024;
+
+/// A sequence the application wrapped in `DCS tmux; ... ST` for the outer
+/// terminal, with the cursor position in the pane when it arrived.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Passthrough {
+    pub data: Vec<u8>,
+    pub col: u16,
+    pub row: u16,
+}
+
+/// A DCS string being collected between `hook` and `unhook`.
+#[derive(Debug)]
+pub(crate) struct DcsString {
+    pub intermediates: Vec<u8>,
+    pub action: char,
+    pub data: Vec<u8>,
+}
+
+impl DcsString {
+    pub fn new(intermediates: &[u8], action: char) -> Self {
+        DcsString {
+            intermediates: intermediates.to_vec(),
+            action,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        if self.data.len() < MAX_DCS_LEN {
+            self.data.push(byte);
+        }
+    }
+
+    /// Whether the string so far is the `tmux;` header of a passthrough
+    /// sequence (the `t` arrives as the DCS final byte).
+    pub fn is_tmux_header(&self) -> bool {
+        self.intermediates.is_empty() && self.action == 't' && self.data == b"mux;"
+    }
+}
+
+/// The payload of a tmux passthrough sequence, in which every ESC meant for
+/// the outer terminal is doubled so that only `ESC \` ends it.
+#[derive(Debug, Default)]
+pub(crate) struct PassthroughCollector {
+    data: Vec<u8>,
+    escape: bool,
+    overflow: bool,
+}
+
+impl PassthroughCollector {
+    /// Add a byte of the payload. Returns true once the closing `ESC \`
+    /// has arrived.
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.escape {
+            self.escape = false;
+            match byte {
+                b'\\' => return true,
+                0x1b => self.append(&[0x1b]),
+                _ => self.append(&[0x1b, byte]),
+            }
+        } else if byte == 0x1b {
+            self.escape = true;
+        } else {
+            self.append(&[byte]);
+        }
+        false
+    }
+
+    /// The unwrapped payload, or None if it was too long to keep.
+    pub fn finish(self) -> Option<Vec<u8>> {
+        (!self.overflow).then_some(self.data)
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        if self.data.len() + bytes.len() > MAX_DCS_LEN {
+            self.overflow = true;
+        } else if !self.overflow {
+            self.data.extend_from_slice(bytes);
+        }
+    }
+}
@@ -1,53 +1,217 @@
-use crate::cell::Cell;
+use crate::cell::{Cell, GridCell};
+use crate::row::{GridRow, Row};
+use crate::style::SharedStyles;
 
-/// A 2D grid of cells representing the visible terminal area.
+/// A 2D grid of cells representing the visible terminal area. Cells are
+/// stored compactly, with their styles kept in a style table the grid shares
+/// with the rest of the terminal (see [`GridCell`]); reading them gives full
+/// cells.
+///
+/// Every row records the damage epoch in which it last changed, so that a
+/// renderer can redraw only the rows changed since its previous frame (see
+/// [`Grid::advance_damage_epoch`] and [`Grid::damaged_rows`]).
 pub struct Grid {
     pub cols: u16,
     pub rows: u16,
-    cells: Vec<Vec<Cell>>,
+    cells: Vec<GridRow>,
+    styles: SharedStyles,
+    /// Epoch in which each row last changed.
+    damage: Vec<u64>,
+    /// Epoch that changes are recorded in.
+    epoch: u64,
 }
 
 impl Grid {
     pub fn new(cols: u16, rows: u16) -> Self {
-        let cells = (0..rows)
-            .map(|_| vec![Cell::default(); cols as usize])
-            .collect();
-        Grid { cols, rows, cells }
+        Self::with_styles(cols, rows, SharedStyles::new())
     }
 
-    /// Get a reference to a cell.
-    pub fn cell(&self, col: u16, row: u16) -> &Cell {
+    /// Create a blank grid whose cells keep their styles in `styles`.
+    pub(crate) fn with_styles(cols: u16, rows: u16, styles: SharedStyles) -> Self {
+        Self::from_grid_rows(cols, rows, Vec::new(), styles)
+    }
+
+    /// Build a grid from existing rows. Rows are padded or truncated to fit.
+    pub fn from_rows(cols: u16, rows: u16, cells: Vec<Row>) -> Self {
+        let styles = SharedStyles::new();
+        let cells = {
+            let mut table = styles.write();
+            cells.iter().map(|row| GridRow::from_row(row, &mut table)).collect()
+        };
+        Self::from_grid_rows(cols, rows, cells, styles)
+    }
+
+    /// Build a grid from rows already stored with `styles`.
+    pub(crate) fn from_grid_rows(
+        cols: u16,
+        rows: u16,
+        mut cells: Vec<GridRow>,
+        styles: SharedStyles,
+    ) -> Self {
+        cells.resize_with(rows as usize, || GridRow::new(cols));
+        for row in &mut cells {
+            row.cells.resize(cols as usize, GridCell::default());
+        }
+        Grid {
+            cols,
+            rows,
+            cells,
+            styles,
+            damage: vec![0; rows as usize],
+            epoch: 0,
+        }
+    }
+
+    /// Start a new damage epoch and return it. Rows changed from now on are
+    /// reported by `damaged_rows` for this epoch.
+    pub fn advance_damage_epoch(&mut self) -> u64 {
+        self.epoch += 1;
+        self.epoch
+    }
+
+    /// Rows changed since the start of epoch `since`, top to bottom.
+    pub fn damaged_rows(&self, since: u64) -> impl Iterator<Item = u16> + '_ {
+        self.damage
+            .iter()
+            .enumerate()
+            .filter(move |(_, &epoch)| epoch >= since)
+            .map(|(row, _)| row as u16)
+    }
+
+    /// Mark every row as changed, e.g. when the colors they are drawn with
+    /// change.
+    pub fn damage_all(&mut self) {
+        self.damage.fill(self.epoch);
+    }
+
+    /// The epoch changes are currently recorded in.
+    pub fn damage_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Take over the damage epoch of the grid this one replaces, with every
+    /// row changed.
+    pub(crate) fn carry_damage_epoch(&mut self, epoch: u64) {
+        self.epoch = self.epoch.max(epoch);
+        self.damage_all();
+    }
+
+    fn damage_row(&mut self, row: u16) {
+        if let Some(epoch) = self.damage.get_mut(row as usize) {
+            *epoch = self.epoch;
+        }
+    }
+
+    fn damage_rows(&mut self, rows: std::ops::Range<u16>) {
+        for row in rows {
+            self.damage_row(row);
+        }
+    }
+
+    /// Consume the grid, returning its rows from top to bottom.
+    pub fn into_rows(self) -> Vec<Row> {
+        let styles = self.styles.read();
+        self.cells.iter().map(|row| row.to_row(&styles)).collect()
+    }
+
+    /// Consume the grid, returning its rows as stored.
+    pub(crate) fn into_grid_rows(self) -> Vec<GridRow> {
+        self.cells
+    }
+
+    /// The style table the grid's cells keep their styles in.
+    pub(crate) fn styles(&self) -> &SharedStyles {
+        &self.styles
+    }
+
+    /// Ids of the styles the grid's cells use, with repeats.
+    pub(crate) fn style_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.cells.iter().flat_map(|row| row.iter().map(|cell| cell.style))
+    }
+
+    /// Get a cell.
+    pub fn cell(&self, col: u16, row: u16) -> Cell {
+        self.grid_cell(col, row).to_cell(&self.styles.read())
+    }
+
+    /// Get a reference to a cell as stored.
+    pub(crate) fn grid_cell(&self, col: u16, row: u16) -> &GridCell {
         &self.cells[row as usize][col as usize]
     }
 
-    /// Get a mutable reference to a cell.
-    pub fn cell_mut(&mut self, col: u16, row: u16) -> &mut Cell {
+    /// Get a mutable reference to a cell as stored.
+    pub(crate) fn cell_mut(&mut self, col: u16, row: u16) -> &mut GridCell {
+        self.damage_row(row);
         &mut self.cells[row as usize][col as usize]
     }
 
     /// Set a cell at the given position.
     pub fn set_cell(&mut self, col: u16, row: u16, cell: Cell) {
+        let cell = GridCell::from_cell(&cell, &mut self.styles.write());
+        self.set_grid_cell(col, row, cell);
+    }
+
+    /// Set a cell at the given position to one stored with the grid's
+    /// style table.
+    pub(crate) fn set_grid_cell(&mut self, col: u16, row: u16, cell: GridCell) {
         if (col as usize) < self.cols as usize && (row as usize) < self.rows as usize {
+            self.damage_row(row);
             self.cells[row as usize][col as usize] = cell;
         }
     }
 
-    /// Get a row as a slice.
-    pub fn row(&self, row: u16) -> &[Cell] {
+    /// Get the cells of a row.
+    pub fn row(&self, row: u16) -> Vec<Cell> {
+        self.line(row).cells
+    }
+
+    /// Get a row together with its line metadata.
+    pub fn line(&self, row: u16) -> Row {
+        self.grid_line(row).to_row(&self.styles.read())
+    }
+
+    /// Get a row as stored, together with its line metadata.
+    pub(crate) fn grid_line(&self, row: u16) -> &GridRow {
         &self.cells[row as usize]
     }
 
-    /// Get a mutable row.
-    pub fn row_mut(&mut self, row: u16) -> &mut Vec<Cell> {
+    /// Get a mutable row as stored, together with its line metadata.
+    pub(crate) fn line_mut(&mut self, row: u16) -> &mut GridRow {
+        self.damage_row(row);
         &mut self.cells[row as usize]
     }
 
-    /// Scroll the grid up by one line (top line is lost, bottom line is blank).
-    pub fn scroll_up(&mut self, top: u16, bottom: u16) {
+    /// Whether the given row soft-wrapped into the next one.
+    pub fn is_wrapped(&self, row: u16) -> bool {
+        self.cells
+            .get(row as usize)
+            .is_some_and(|r| r.wrapped)
+    }
+
+    /// Mark whether the given row soft-wrapped into the next one.
+    pub fn set_wrapped(&mut self, row: u16, wrapped: bool) {
+        if let Some(r) = self.cells.get_mut(row as usize) {
+            r.wrapped = wrapped;
+        }
+    }
+
+    /// Scroll the grid up by one line (bottom line is blank). Returns the
+    /// line that was scrolled off the top of the region.
+    pub fn scroll_up(&mut self, top: u16, bottom: u16) -> Option<Row> {
+        let line = self.scroll_up_line(top, bottom)?;
+        Some(line.to_row(&self.styles.read()))
+    }
+
+    /// Scroll the grid up by one line, returning the line that was scrolled
+    /// off the top of the region as stored.
+    pub(crate) fn scroll_up_line(&mut self, top: u16, bottom: u16) -> Option<GridRow> {
         if top < bottom && bottom <= self.rows {
-            self.cells.remove(top as usize);
-            self.cells
-                .insert(bottom as usize - 1, vec![Cell::default(); self.cols as usize]);
+            let line = self.cells.remove(top as usize);
+            self.cells.insert(bottom as usize - 1, GridRow::new(self.cols));
+            self.damage_rows(top..bottom);
+            Some(line)
+        } else {
+            None
         }
     }
 
@@ -55,59 +219,69 @@ impl Grid {
     pub fn scroll_down(&mut self, top: u16, bottom: u16) {
         if top < bottom && bottom <= self.rows {
             self.cells.remove(bottom as usize - 1);
-            self.cells
-                .insert(top as usize, vec![Cell::default(); self.cols as usize]);
+            self.cells.insert(top as usize, GridRow::new(self.cols));
+            self.damage_rows(top..bottom);
         }
     }
 
     /// Clear a region of the grid.
     pub fn clear_region(&mut self, top: u16, left: u16, bottom: u16, right: u16) {
         for row in top..=bottom.min(self.rows - 1) {
+            self.damage_row(row);
+            self.cells[row as usize].split_wide_at(left as usize);
+            self.cells[row as usize].split_wide_at(right.min(self.cols - 1) as usize);
             for col in left..=right.min(self.cols - 1) {
-                self.cells[row as usize][col as usize] = Cell::default();
+                self.cells[row as usize][col as usize] = GridCell::default();
             }
         }
     }
 
-    /// Clear the entire grid.
+    /// Clear the entire grid, including line metadata.
     pub fn clear(&mut self) {
-        self.clear_region(0, 0, self.rows - 1, self.cols - 1);
+        for row in &mut self.cells {
+            row.clear();
+        }
+        self.damage_all();
     }
 
     /// Clear a single row.
     pub fn clear_row(&mut self, row: u16) {
-        if (row as usize) < self.cells.len() {
-            for cell in &mut self.cells[row as usize] {
-                *cell = Cell::default();
-            }
+        if let Some(r) = self.cells.get_mut(row as usize) {
+            r.clear();
         }
+        self.damage_row(row);
     }
 
-    /// Resize the grid, preserving content where possible.
+    /// Resize the grid by truncating or padding rows, without rewrapping.
     pub fn resize(&mut self, new_cols: u16, new_rows: u16) {
         // Adjust rows
         while self.cells.len() > new_rows as usize {
             self.cells.pop();
         }
         while self.cells.len() < new_rows as usize {
-            self.cells.push(vec![Cell::default(); new_cols as usize]);
+            self.cells.push(GridRow::new(new_cols));
         }
 
         // Adjust columns
         for row in &mut self.cells {
-            row.resize(new_cols as usize, Cell::default());
+            row.cells.resize(new_cols as usize, GridCell::default());
         }
 
         self.cols = new_cols;
         self.rows = new_rows;
+        self.damage.resize(new_rows as usize, 0);
+        self.damage_all();
     }
 
     /// Erase characters from cursor to end of line.
     pub fn erase_to_eol(&mut self, row: u16, col: u16) {
         if (row as usize) < self.cells.len() {
+            self.damage_row(row);
+            self.cells[row as usize].split_wide_at(col as usize);
             for c in col..self.cols {
-                self.cells[row as usize][c as usize] = Cell::default();
+                self.cells[row as usize][c as usize] = GridCell::default();
             }
+            self.cells[row as usize].wrapped = false;
         }
     }
 
@@ -116,118 +290,42 @@ impl Grid {
         if row >= self.rows {
             return String::new();
         }
-        self.cells[row as usize]
-            .iter()
-            .filter(|c| c.width > 0)
-            .map(|c| c.ch)
-            .collect::<String>()
-            .trim_end()
-            .to_string()
-    }
-
-    /// Search for a string in the grid. Returns (col, row) of the first match
-    /// starting from (start_col, start_row) in the given direction.
-    pub fn search(
-        &self,
-        query: &str,
-        start_col: u16,
-        start_row: u16,
-        forward: bool,
-    ) -> Option<(u16, u16)> {
-        if query.is_empty() {
-            return None;
-        }
-
-        let query_lower = query.to_lowercase();
-
-        if forward {
-            // Search forward: from current position to end, then wrap to start
-            for row in start_row..self.rows {
-                let text = self.row_text(row);
-                let text_lower = text.to_lowercase();
-                let search_from = if row == start_row {
-                    (start_col as usize).saturating_add(1).min(text.len())
-                } else {
-                    0
-                };
-                if let Some(pos) = text_lower[search_from..].find(&query_lower) {
-                    return Some(((search_from + pos) as u16, row));
-                }
-            }
-            // Wrap around
-            for row in 0..=start_row.min(self.rows - 1) {
-                let text = self.row_text(row);
-                let text_lower = text.to_lowercase();
-                let limit = if row == start_row {
-                    start_col as usize
-                } else {
-                    text.len()
-                };
-                if let Some(pos) = text_lower[..limit.min(text.len())].find(&query_lower) {
-                    return Some((pos as u16, row));
-                }
-            }
-        } else {
-            // Search backward
-            for row in (0..=start_row.min(self.rows - 1)).rev() {
-                let text = self.row_text(row);
-                let text_lower = text.to_lowercase();
-                let search_until = if row == start_row {
-                    (start_col as usize).min(text.len())
-                } else {
-                    text.len()
-                };
-                if let Some(pos) = text_lower[..search_until].rfind(&query_lower) {
-                    return Some((pos as u16, row));
-                }
-            }
-            // Wrap around
-            for row in (start_row.min(self.rows - 1)..self.rows).rev() {
-                let text = self.row_text(row);
-                let text_lower = text.to_lowercase();
-                let from = if row == start_row {
-                    (start_col as usize).saturating_add(1).min(text.len())
-                } else {
-                    0
-                };
-                if from < text.len() {
-                    if let Some(pos) = text_lower[from..].rfind(&query_lower) {
-                        return Some(((from + pos) as u16, row));
-                    }
-                }
-            }
-        }
-
-        None
+        self.cells[row as usize].text()
     }
 
     /// Erase characters from start of line to cursor.
     pub fn erase_to_bol(&mut self, row: u16, col: u16) {
         if (row as usize) < self.cells.len() {
+            self.damage_row(row);
+            self.cells[row as usize].split_wide_at(col as usize);
             for c in 0..=col.min(self.cols - 1) {
-                self.cells[row as usize][c as usize] = Cell::default();
+                self.cells[row as usize][c as usize] = GridCell::default();
             }
         }
     }
 
     /// Insert blank lines at the given row, pushing content down.
     pub fn insert_lines(&mut self, row: u16, count: u16, bottom: u16) {
+        if count > 0 {
+            self.damage_rows(row..bottom.min(self.rows));
+        }
         for _ in 0..count {
             if row < bottom && bottom <= self.rows {
                 self.cells.remove(bottom as usize - 1);
-                self.cells
-                    .insert(row as usize, vec![Cell::default(); self.cols as usize]);
+                self.cells.insert(row as usize, GridRow::new(self.cols));
             }
         }
     }
 
     /// Delete lines at the given row, pulling content up.
     pub fn delete_lines(&mut self, row: u16, count: u16, bottom: u16) {
+        if count > 0 {
+            self.damage_rows(row..bottom.min(self.rows));
+        }
         for _ in 0..count {
             if row < bottom && bottom <= self.rows {
                 self.cells.remove(row as usize);
-                self.cells
-                    .insert(bottom as usize - 1, vec![Cell::default(); self.cols as usize]);
+                self.cells.insert(bottom as usize - 1, GridRow::new(self.cols));
             }
         }
     }
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+/// A hyperlink opened with OSC 8.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Hyperlink {
+    /// The `id=` parameter, if the application gave one. Cells with the same
+    /// id and URI belong to one link even when they are not adjacent.
+    pub id: Option<String>,
+    pub uri: String,
+}
+
+/// Smallest table that is swept for unused links.
+const MIN_SWEEP_LEN: usize = 256;
+
+/// Hyperlinks referenced by cells. Cells store a small id into this table
+/// instead of the URI itself; id 0 means "no link". Ids of links no cell
+/// refers to any more are freed by [`HyperlinkTable::sweep`] and handed out
+/// again.
+#[derive(Debug, Default)]
+pub struct HyperlinkTable {
+    links: Vec<Hyperlink>,
+    ids: HashMap<Hyperlink, u32>,
+    /// Ids freed by the last sweeps, to be reused first.
+    free: Vec<u32>,
+    /// Links in use after the last sweep.
+    swept_len: usize,
+}
+
+impl HyperlinkTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the id for a link, adding it to the table if it is new.
+    pub fn intern(&mut self, link: Hyperlink) -> u32 {
+        if let Some(&id) = self.ids.get(&link) {
+            return id;
+        }
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.links[id as usize - 1] = link.clone();
+                id
+            }
+            None => {
+                self.links.push(link.clone());
+                self.links.len() as u32
+            }
+        };
+        self.ids.insert(link, id);
+        id
+    }
+
+    /// Look up a link by the id stored in a cell.
+    pub fn get(&self, id: u32) -> Option<&Hyperlink> {
+        id.checked_sub(1)
+            .and_then(|index| self.links.get(index as usize))
+    }
+
+    /// Whether the table has doubled since the last sweep, so that sweeping
+    /// it again is worth going through every cell that may refer to it.
+    pub fn needs_sweep(&self) -> bool {
+        self.ids.len() >= (self.swept_len * 2).max(MIN_SWEEP_LEN)
+    }
+
+    /// Free every link except those with an id in `used`.
+    pub fn sweep(&mut self, used: impl IntoIterator<Item = u32>) {
+        let mut keep = vec![false; self.links.len() + 1];
+        for id in used {
+            if let Some(keep) = keep.get_mut(id as usize) {
+                *keep = true;
+            }
+        }
+        for (index, link) in self.links.iter().enumerate() {
+            let id = index as u32 + 1;
+            if !keep[id as usize] && self.ids.get(link) == Some(&id) {
+                self.ids.remove(link);
+                self.free.push(id);
+            }
+        }
+        self.swept_len = self.ids.len();
+    }
+
+    /// Number of links in use.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+impl Hyperlink {
+    /// Parse the parameter and URI parts of `OSC 8 ; params ; URI`.
+    /// Returns None for an empty URI, which closes the current link.
+    pub fn parse(params: &[u8], uri: &[u8]) -> Option<Self> {
+        if uri.is_empty() {
+            return None;
+        }
+        let uri = String::from_utf8_lossy(uri).into_owned();
+        let id = params
+            .split(|&b| b == b':')
+            .find_map(|param| param.strip_prefix(b"id="))
+            .filter(|id| !id.is_empty())
+            .map(|id| String::from_utf8_lossy(id).into_owned());
+        Some(Hyperlink { id, uri })
+    }
+
+    /// The OSC 8 sequence that opens this link.
+    pub fn open_sequence(&self) -> Vec<u8> {
+        match &self.id {
+            Some(id) => format!("\x1b]8;id={};{}\x1b\\", id, self.uri).into_bytes(),
+            None => format!("\x1b]8;;{}\x1b\\", self.uri).into_bytes(),
+        }
+    }
+}
+
+/// The OSC 8 sequence that closes the current link.
+pub const CLOSE_SEQUENCE: &[u8] = b"\x1b]8;;\x1b\\";
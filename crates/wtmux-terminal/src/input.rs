@@ -0,0 +1,231 @@
+use crate::modes::{MouseEncoding, MouseTracking, TerminalModes};
+
+/// Mouse button in a report sent to the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+}
+
+/// What happened to the mouse button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseAction {
+    Press,
+    Release,
+    /// Motion with the button held down.
+    Drag,
+}
+
+/// Encode a mouse event the way the application asked for. `col` and `row`
+/// are 0-based and relative to the pane. Returns None if the application
+/// did not ask for this event or it cannot be encoded.
+pub fn encode_mouse(
+    modes: &TerminalModes,
+    button: MouseButton,
+    action: MouseAction,
+    col: u16,
+    row: u16,
+) -> Option<Vec<u8>> {
+    let is_wheel = matches!(button, MouseButton::WheelUp | MouseButton::WheelDown);
+    match (modes.mouse_tracking, action) {
+        (MouseTracking::Off, _) => return None,
+        (MouseTracking::Click, MouseAction::Drag) => return None,
+        (_, MouseAction::Release | MouseAction::Drag) if is_wheel => return None,
+        _ => {}
+    }
+
+    let mut code: u16 = match button {
+        MouseButton::Left => 0,
+        MouseButton::Middle => 1,
+        MouseButton::Right => 2,
+        MouseButton::WheelUp => 64,
+        MouseButton::WheelDown => 65,
+    };
+    if action == MouseAction::Drag {
+        code += 32;
+    }
+
+    let x = col as u32 + 1;
+    let y = row as u32 + 1;
+
+    match modes.mouse_encoding {
+        MouseEncoding::Sgr => {
+            let end = if action == MouseAction::Release { 'm' } else { 'M' };
+            Some(format!("\x1b[<{};{};{}{}", code, x, y, end).into_bytes())
+        }
+        MouseEncoding::Urxvt => {
+            if action == MouseAction::Release {
+                code = 3;
+            }
+            Some(format!("\x1b[{};{};{}M", code + 32, x, y).into_bytes())
+        }
+        MouseEncoding::Default => {
+            if action == MouseAction::Release {
+                code = 3;
+            }
+            // Each value is a single byte offset by 32.
+            if x + 32 > 255 || y + 32 > 255 {
+                return None;
+            }
+            Some(vec![
+                0x1b,
+                b'[',
+                b'M',
+                (code + 32) as u8,
+                (x + 32) as u8,
+                (y + 32) as u8,
+            ])
+        }
+    }
+}
+
+/// Rewrite unmodified cursor key sequences (`CSI A` etc.) to their SS3 form
+/// when the application enabled DECCKM.
+pub fn apply_cursor_key_mode(modes: &TerminalModes, data: &[u8]) -> Vec<u8> {
+    if !modes.app_cursor_keys {
+        return data.to_vec();
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] == 0x1b
+            && data.get(i + 1) == Some(&b'[')
+            && matches!(data.get(i + 2), Some(b'A' | b'B' | b'C' | b'D' | b'H' | b'F'))
+        {
+            output.extend_from_slice(&[0x1b, b'O', data[i + 2]]);
+            i += 3;
+        } else {
+            output.push(data[i]);
+            i += 1;
+        }
+    }
+    output
+}
+
+/// Encode pasted text, wrapping it in bracketed paste markers if the
+/// application asked for them.
+pub fn encode_paste(modes: &TerminalModes, text: &[u8]) -> Vec<u8> {
+    if !modes.bracketed_paste {
+        return text.to_vec();
+    }
+
+    const END: &[u8] = b"\x1b[201~";
+    let mut output = Vec::with_capacity(text.len() + 12);
+    output.extend_from_slice(b"\x1b[200~");
+    // Drop any end marker inside the text so it cannot end the paste early.
+    let mut i = 0;
+    while i < text.len() {
+        if text[i..].starts_with(END) {
+            i += END.len();
+        } else {
+            output.push(text[i]);
+            i += 1;
+        }
+    }
+    output.extend_from_slice(END);
+    output
+}
+
+/// Focus in/out report, if the application enabled focus reporting.
+pub fn encode_focus(modes: &TerminalModes, focused: bool) -> Option<&'static [u8]> {
+    if !modes.focus_reporting {
+        return None;
+    }
+    Some(if focused { b"\x1b[I" } else { b"\x1b[O" })
+}
+
+/// Cursor up/down keys standing in for wheel scrolls (alternate scroll mode).
+pub fn encode_wheel_as_keys(modes: &TerminalModes, up: bool, lines: usize) -> Vec<u8> {
+    let key: &[u8] = match (up, modes.app_cursor_keys) {
+        (true, false) => b"\x1b[A",
+        (true, true) => b"\x1bOA",
+        (false, false) => b"\x1b[B",
+        (false, true) => b"\x1bOB",
+    };
+    key.repeat(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modes(tracking: MouseTracking, encoding: MouseEncoding) -> TerminalModes {
+        TerminalModes {
+            mouse_tracking: tracking,
+            mouse_encoding: encoding,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_mouse_off_sends_nothing() {
+        let m = TerminalModes::default();
+        assert_eq!(encode_mouse(&m, MouseButton::Left, MouseAction::Press, 0, 0), None);
+    }
+
+    #[test]
+    fn test_mouse_encodings() {
+        let m = modes(MouseTracking::Click, MouseEncoding::Default);
+        assert_eq!(
+            encode_mouse(&m, MouseButton::Left, MouseAction::Press, 4, 9).unwrap(),
+            b"\x1b[M\x20\x25\x2a"
+        );
+        assert_eq!(
+            encode_mouse(&m, MouseButton::Left, MouseAction::Release, 4, 9).unwrap(),
+            b"\x1b[M\x23\x25\x2a"
+        );
+
+        let m = modes(MouseTracking::Click, MouseEncoding::Sgr);
+        assert_eq!(
+            encode_mouse(&m, MouseButton::Right, MouseAction::Release, 4, 9).unwrap(),
+            b"\x1b[<2;5;10m"
+        );
+        assert_eq!(
+            encode_mouse(&m, MouseButton::WheelDown, MouseAction::Press, 0, 0).unwrap(),
+            b"\x1b[<65;1;1M"
+        );
+
+        let m = modes(MouseTracking::Click, MouseEncoding::Urxvt);
+        assert_eq!(
+            encode_mouse(&m, MouseButton::Left, MouseAction::Press, 4, 9).unwrap(),
+            b"\x1b[32;5;10M"
+        );
+    }
+
+    #[test]
+    fn test_mouse_drag_needs_drag_tracking() {
+        let m = modes(MouseTracking::Click, MouseEncoding::Sgr);
+        assert_eq!(encode_mouse(&m, MouseButton::Left, MouseAction::Drag, 1, 1), None);
+        let m = modes(MouseTracking::Drag, MouseEncoding::Sgr);
+        assert_eq!(
+            encode_mouse(&m, MouseButton::Left, MouseAction::Drag, 1, 1).unwrap(),
+            b"\x1b[<32;2;2M"
+        );
+    }
+
+    #[test]
+    fn test_app_cursor_keys() {
+        let mut m = TerminalModes::default();
+        assert_eq!(apply_cursor_key_mode(&m, b"\x1b[A"), b"\x1b[A");
+        m.app_cursor_keys = true;
+        assert_eq!(apply_cursor_key_mode(&m, b"\x1b[Ax\x1b[D"), b"\x1bOAx\x1bOD");
+        // Modified keys keep their CSI form.
+        assert_eq!(apply_cursor_key_mode(&m, b"\x1b[1;5A"), b"\x1b[1;5A");
+    }
+
+    #[test]
+    fn test_bracketed_paste() {
+        let mut m = TerminalModes::default();
+        assert_eq!(encode_paste(&m, b"ls"), b"ls");
+        m.bracketed_paste = true;
+        assert_eq!(encode_paste(&m, b"ls"), b"\x1b[200~ls\x1b[201~");
+        assert_eq!(
+            encode_paste(&m, b"a\x1b[201~b"),
+            b"\x1b[200~ab\x1b[201~"
+        );
+    }
+}
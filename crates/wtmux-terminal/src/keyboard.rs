@@ -0,0 +1,508 @@
+use wtmux_config::keybindings::{Key, KeyEventKind, Modifiers};
+
+use crate::modes::TerminalModes;
+
+/// Kitty keyboard protocol enhancement flags, pushed by the application
+/// with `CSI > flags u`.
+pub const DISAMBIGUATE_ESCAPE_CODES: u8 = 1;
+pub const REPORT_EVENT_TYPES: u8 = 2;
+pub const REPORT_ALTERNATE_KEYS: u8 = 4;
+pub const REPORT_ALL_KEYS_AS_ESCAPE_CODES: u8 = 8;
+pub const REPORT_ASSOCIATED_TEXT: u8 = 16;
+const ALL_FLAGS: u8 = 31;
+
+/// Deepest the flag stack grows; pushing onto a full stack drops the oldest
+/// entry so that an application that never pops cannot grow it without bound.
+const MAX_STACK_DEPTH: usize = 16;
+
+/// The keyboard enhancement flags of one screen. Each entry was pushed by
+/// an application; the top one is in effect.
+#[derive(Debug, Clone, Default)]
+pub struct KeyboardFlagStack {
+    stack: Vec<u8>,
+}
+
+impl KeyboardFlagStack {
+    /// Flags in effect, 0 when nothing has been pushed.
+    pub fn current(&self) -> u8 {
+        self.stack.last().copied().unwrap_or(0)
+    }
+
+    /// `CSI > flags u`
+    pub fn push(&mut self, flags: u8) {
+        if self.stack.len() == MAX_STACK_DEPTH {
+            self.stack.remove(0);
+        }
+        self.stack.push(flags & ALL_FLAGS);
+    }
+
+    /// `CSI < n u`: popping more entries than there are empties the stack.
+    pub fn pop(&mut self, n: usize) {
+        let keep = self.stack.len().saturating_sub(n);
+        self.stack.truncate(keep);
+    }
+
+    /// `CSI = flags ; mode u`: mode 1 replaces the flags in effect, 2 sets
+    /// the given bits and 3 clears them.
+    pub fn modify(&mut self, flags: u8, mode: u16) {
+        let flags = flags & ALL_FLAGS;
+        let current = self.current();
+        let flags = match mode {
+            1 => flags,
+            2 => current | flags,
+            3 => current & !flags,
+            _ => return,
+        };
+        match self.stack.last_mut() {
+            Some(top) => *top = flags,
+            None => self.stack.push(flags),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear();
+    }
+}
+
+/// Encode a key for the application: in CSI u form when it pushed kitty
+/// keyboard flags, otherwise as the legacy sequence. Returns None for keys
+/// (and releases) the application has no way to receive.
+pub fn encode_key(
+    modes: &TerminalModes,
+    flags: u8,
+    key: &Key,
+    modifiers: Modifiers,
+    kind: KeyEventKind,
+) -> Option<Vec<u8>> {
+    if flags == 0 {
+        return match kind {
+            KeyEventKind::Release => None,
+            _ => encode_legacy(modes, key, modifiers),
+        };
+    }
+
+    // Text and unmodified Enter, Tab and Backspace keep their legacy bytes
+    // unless the application asked for every key as an escape code.
+    let all_keys = flags & REPORT_ALL_KEYS_AS_ESCAPE_CODES != 0;
+    let text = key_text(key, modifiers);
+    let plain = modifiers == Modifiers::default();
+    if !all_keys
+        && (text.is_some() || plain && matches!(key, Key::Enter | Key::Tab | Key::Backspace))
+    {
+        return match kind {
+            KeyEventKind::Release => None,
+            _ => encode_legacy(modes, key, modifiers),
+        };
+    }
+
+    let report_events = flags & REPORT_EVENT_TYPES != 0;
+    let event = match kind {
+        KeyEventKind::Release if !report_events => return None,
+        KeyEventKind::Repeat if report_events => 2,
+        KeyEventKind::Release => 3,
+        _ => 1,
+    };
+    let mods = modifier_param(modifiers);
+    let (number, final_byte) = kitty_key_code(key)?;
+
+    // Unmodified presses of functional keys are sent as in legacy mode.
+    if final_byte != 'u' && mods == 1 && event == 1 {
+        return encode_legacy(modes, key, modifiers);
+    }
+
+    let mut seq = format!("\x1b[{}", number);
+    if let Key::Char(c) = *key {
+        if flags & REPORT_ALTERNATE_KEYS != 0 && c as u32 != number {
+            seq.push_str(&format!(":{}", c as u32));
+        }
+    }
+    let text = text.filter(|_| {
+        all_keys && flags & REPORT_ASSOCIATED_TEXT != 0 && kind != KeyEventKind::Release
+    });
+    if mods != 1 || event != 1 || text.is_some() {
+        seq.push_str(&format!(";{}", mods));
+        if event != 1 {
+            seq.push_str(&format!(":{}", event));
+        }
+    }
+    if let Some(c) = text {
+        seq.push_str(&format!(";{}", c as u32));
+    }
+    seq.push(final_byte);
+    Some(seq.into_bytes())
+}
+
+/// The text a key types, if any: printable keys without Ctrl or Alt.
+fn key_text(key: &Key, modifiers: Modifiers) -> Option<char> {
+    if modifiers.ctrl || modifiers.alt {
+        return None;
+    }
+    match *key {
+        Key::Char(c) => Some(c),
+        Key::Space => Some(' '),
+        _ => None,
+    }
+}
+
+/// Key number and final byte of a key's CSI u form. Printable keys are
+/// reported by their unshifted codepoint.
+fn kitty_key_code(key: &Key) -> Option<(u32, char)> {
+    Some(match *key {
+        Key::Char(c) if c.is_ascii_uppercase() => (c.to_ascii_lowercase() as u32, 'u'),
+        Key::Char(c) => (c as u32, 'u'),
+        Key::Space => (32, 'u'),
+        Key::Enter => (13, 'u'),
+        Key::Tab => (9, 'u'),
+        Key::Backspace => (127, 'u'),
+        Key::Escape => (27, 'u'),
+        Key::Up => (1, 'A'),
+        Key::Down => (1, 'B'),
+        Key::Right => (1, 'C'),
+        Key::Left => (1, 'D'),
+        Key::Home => (1, 'H'),
+        Key::End => (1, 'F'),
+        Key::Insert => (2, '~'),
+        Key::Delete => (3, '~'),
+        Key::PageUp => (5, '~'),
+        Key::PageDown => (6, '~'),
+        Key::F(1) => (1, 'P'),
+        Key::F(2) => (1, 'Q'),
+        Key::F(3) => (13, '~'),
+        Key::F(4) => (1, 'S'),
+        Key::F(n @ 5..=12) => (legacy_function_key_number(n)?, '~'),
+        // F13-F35 and the keypad live in the private use area.
+        Key::F(n @ 13..=35) => (57376 + (n as u32 - 13), 'u'),
+        Key::F(_) => return None,
+        Key::Keypad(c @ '0'..='9') => (57399 + c as u32 - '0' as u32, 'u'),
+        Key::Keypad('.') => (57409, 'u'),
+        Key::Keypad('/') => (57410, 'u'),
+        Key::Keypad('*') => (57411, 'u'),
+        Key::Keypad('-') => (57412, 'u'),
+        Key::Keypad('+') => (57413, 'u'),
+        Key::KeypadEnter => (57414, 'u'),
+        Key::Keypad('=') => (57415, 'u'),
+        Key::Keypad(_) => return None,
+    })
+}
+
+/// The number in the `CSI n ~` sequence of F5-F12.
+fn legacy_function_key_number(n: u8) -> Option<u32> {
+    Some(match n {
+        5 => 15,
+        6 => 17,
+        7 => 18,
+        8 => 19,
+        9 => 20,
+        10 => 21,
+        11 => 23,
+        12 => 24,
+        _ => return None,
+    })
+}
+
+/// The xterm sequence for a key, without keyboard enhancements. Modified
+/// special keys carry the modifiers as a parameter (`CSI 1 ; 5 A`).
+fn encode_legacy(modes: &TerminalModes, key: &Key, modifiers: Modifiers) -> Option<Vec<u8>> {
+    let param = modifier_param(modifiers);
+    // `CSI 1 ; m X` when modified, otherwise `CSI X` or `SS3 X`.
+    let letter = |byte: u8, ss3: bool| {
+        if param > 1 {
+            format!("\x1b[1;{}{}", param, byte as char).into_bytes()
+        } else if ss3 {
+            vec![0x1b, b'O', byte]
+        } else {
+            vec![0x1b, b'[', byte]
+        }
+    };
+    let tilde = |n: u32| {
+        if param > 1 {
+            format!("\x1b[{};{}~", n, param).into_bytes()
+        } else {
+            format!("\x1b[{}~", n).into_bytes()
+        }
+    };
+    // Application keypad sequences carry modifiers inside the SS3 (`SS3 5 p`).
+    let keypad = |byte: u8| {
+        if param > 1 {
+            format!("\x1bO{}{}", param, byte as char).into_bytes()
+        } else {
+            vec![0x1b, b'O', byte]
+        }
+    };
+
+    let bytes = match *key {
+        Key::Char(c) => encode_char(c, modifiers),
+        Key::Space => encode_char(' ', modifiers),
+        Key::Enter => alt_prefixed(modifiers, b"\r"),
+        Key::Tab if modifiers.shift => alt_prefixed(modifiers, b"\x1b[Z"),
+        Key::Tab => alt_prefixed(modifiers, b"\t"),
+        Key::Backspace if modifiers.ctrl => alt_prefixed(modifiers, b"\x08"),
+        Key::Backspace => alt_prefixed(modifiers, b"\x7f"),
+        Key::Escape => alt_prefixed(modifiers, b"\x1b"),
+        Key::Up => letter(b'A', modes.app_cursor_keys),
+        Key::Down => letter(b'B', modes.app_cursor_keys),
+        Key::Right => letter(b'C', modes.app_cursor_keys),
+        Key::Left => letter(b'D', modes.app_cursor_keys),
+        Key::Home => letter(b'H', modes.app_cursor_keys),
+        Key::End => letter(b'F', modes.app_cursor_keys),
+        Key::Insert => tilde(2),
+        Key::Delete => tilde(3),
+        Key::PageUp => tilde(5),
+        Key::PageDown => tilde(6),
+        Key::F(n @ 1..=4) => letter(b'P' + n - 1, true),
+        Key::F(n @ 5..=12) => tilde(legacy_function_key_number(n)?),
+        // xterm sends F13-F24 as shifted F1-F12.
+        Key::F(n @ 13..=24) => {
+            let shifted = Modifiers {
+                shift: true,
+                ..modifiers
+            };
+            return encode_legacy(modes, &Key::F(n - 12), shifted);
+        }
+        Key::F(_) => return None,
+        Key::Keypad(c) => {
+            let byte = keypad_final_byte(c)?;
+            if modes.app_keypad {
+                keypad(byte)
+            } else {
+                encode_char(c, modifiers)
+            }
+        }
+        Key::KeypadEnter if modes.app_keypad => keypad(b'M'),
+        Key::KeypadEnter => alt_prefixed(modifiers, b"\r"),
+    };
+    Some(bytes)
+}
+
+/// The xterm modifier parameter: 1 plus Shift=1, Alt=2, Ctrl=4.
+fn modifier_param(modifiers: Modifiers) -> u8 {
+    1 + modifiers.shift as u8 + 2 * modifiers.alt as u8 + 4 * modifiers.ctrl as u8
+}
+
+/// Prefix `bytes` with ESC when Alt is held (xterm's metaSendsEscape).
+fn alt_prefixed(modifiers: Modifiers, bytes: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(bytes.len() + 1);
+    if modifiers.alt {
+        output.push(0x1b);
+    }
+    output.extend_from_slice(bytes);
+    output
+}
+
+fn encode_char(c: char, modifiers: Modifiers) -> Vec<u8> {
+    match control_code(c).filter(|_| modifiers.ctrl) {
+        Some(code) => alt_prefixed(modifiers, &[code]),
+        None => {
+            let mut buf = [0u8; 4];
+            alt_prefixed(modifiers, c.encode_utf8(&mut buf).as_bytes())
+        }
+    }
+}
+
+/// The C0 control a key sends with Ctrl held: Ctrl+A = 0x01, Ctrl+[ = ESC,
+/// and the digit row as xterm maps it (Ctrl+2 = NUL ... Ctrl+8 = DEL).
+fn control_code(c: char) -> Option<u8> {
+    Some(match c {
+        'a'..='z' => c as u8 - b'a' + 1,
+        'A'..='Z' => c as u8 - b'A' + 1,
+        '@' | ' ' | '2' => 0x00,
+        '[' | '3' => 0x1b,
+        '\\' | '4' => 0x1c,
+        ']' | '5' => 0x1d,
+        '^' | '6' => 0x1e,
+        '_' | '/' | '7' => 0x1f,
+        '?' | '8' => 0x7f,
+        _ => return None,
+    })
+}
+
+/// Final byte of a keypad key's application mode (SS3) sequence.
+fn keypad_final_byte(c: char) -> Option<u8> {
+    Some(match c {
+        '0'..='9' => b'p' + (c as u8 - b'0'),
+        '.' => b'n',
+        '+' => b'k',
+        '-' => b'm',
+        '*' => b'j',
+        '/' => b'o',
+        '=' => b'X',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTRL: Modifiers = Modifiers {
+        ctrl: true,
+        alt: false,
+        shift: false,
+    };
+    const SHIFT: Modifiers = Modifiers {
+        ctrl: false,
+        alt: false,
+        shift: true,
+    };
+    const NONE: Modifiers = Modifiers {
+        ctrl: false,
+        alt: false,
+        shift: false,
+    };
+
+    fn press(flags: u8, key: Key, modifiers: Modifiers) -> Vec<u8> {
+        let modes = TerminalModes::default();
+        encode_key(&modes, flags, &key, modifiers, KeyEventKind::Press).unwrap()
+    }
+
+    #[test]
+    fn test_flag_stack() {
+        let mut stack = KeyboardFlagStack::default();
+        assert_eq!(stack.current(), 0);
+        stack.push(1);
+        stack.push(0xff);
+        assert_eq!(stack.current(), 31);
+        stack.modify(2, 3);
+        assert_eq!(stack.current(), 29);
+        stack.pop(1);
+        assert_eq!(stack.current(), 1);
+        stack.modify(8, 2);
+        assert_eq!(stack.current(), 9);
+        stack.pop(5);
+        assert_eq!(stack.current(), 0);
+        for _ in 0..100 {
+            stack.push(1);
+        }
+        stack.pop(MAX_STACK_DEPTH - 1);
+        assert_eq!(stack.current(), 1);
+    }
+
+    fn mods(spec: &str) -> Modifiers {
+        Modifiers {
+            ctrl: spec.contains('C'),
+            alt: spec.contains('M'),
+            shift: spec.contains('S'),
+        }
+    }
+
+    #[test]
+    fn test_xterm_keys() {
+        // (key, modifiers, expected)
+        let table: &[(Key, &str, &[u8])] = &[
+            (Key::Char('a'), "", b"a"),
+            (Key::Char('A'), "S", b"A"),
+            (Key::Char('a'), "C", b"\x01"),
+            (Key::Char('i'), "C", b"\x09"),
+            (Key::Char('a'), "M", b"\x1ba"),
+            (Key::Char('a'), "CM", b"\x1b\x01"),
+            (Key::Char('['), "C", b"\x1b"),
+            (Key::Char('2'), "C", b"\x00"),
+            (Key::Char('/'), "C", b"\x1f"),
+            (Key::Char('\u{e9}'), "M", "\x1b\u{e9}".as_bytes()),
+            (Key::Space, "C", b"\x00"),
+            (Key::Space, "M", b"\x1b "),
+            (Key::Enter, "", b"\r"),
+            (Key::Enter, "M", b"\x1b\r"),
+            (Key::Tab, "", b"\t"),
+            (Key::Tab, "S", b"\x1b[Z"),
+            (Key::Tab, "MS", b"\x1b\x1b[Z"),
+            (Key::Backspace, "", b"\x7f"),
+            (Key::Backspace, "C", b"\x08"),
+            (Key::Backspace, "M", b"\x1b\x7f"),
+            (Key::Escape, "", b"\x1b"),
+            (Key::Escape, "M", b"\x1b\x1b"),
+            (Key::Up, "", b"\x1b[A"),
+            (Key::Up, "S", b"\x1b[1;2A"),
+            (Key::Down, "M", b"\x1b[1;3B"),
+            (Key::Right, "C", b"\x1b[1;5C"),
+            (Key::Left, "C", b"\x1b[1;5D"),
+            (Key::Left, "CMS", b"\x1b[1;8D"),
+            (Key::Home, "", b"\x1b[H"),
+            (Key::End, "S", b"\x1b[1;2F"),
+            (Key::Insert, "", b"\x1b[2~"),
+            (Key::Delete, "C", b"\x1b[3;5~"),
+            (Key::PageUp, "", b"\x1b[5~"),
+            (Key::PageDown, "M", b"\x1b[6;3~"),
+            (Key::F(1), "", b"\x1bOP"),
+            (Key::F(4), "", b"\x1bOS"),
+            (Key::F(2), "C", b"\x1b[1;5Q"),
+            (Key::F(5), "", b"\x1b[15~"),
+            (Key::F(12), "S", b"\x1b[24;2~"),
+            (Key::F(13), "", b"\x1b[1;2P"),
+            (Key::F(17), "", b"\x1b[15;2~"),
+            (Key::F(24), "C", b"\x1b[24;6~"),
+            (Key::Keypad('5'), "", b"5"),
+            (Key::Keypad('+'), "", b"+"),
+            (Key::KeypadEnter, "", b"\r"),
+        ];
+        for (key, modifiers, expected) in table {
+            let got = press(0, key.clone(), mods(modifiers));
+            assert_eq!(got, *expected, "{:?} {:?}", key, modifiers);
+        }
+        let modes = TerminalModes::default();
+        assert_eq!(encode_key(&modes, 0, &Key::F(25), NONE, KeyEventKind::Press), None);
+        let release = encode_key(&modes, 0, &Key::Char('a'), NONE, KeyEventKind::Release);
+        assert_eq!(release, None);
+    }
+
+    #[test]
+    fn test_xterm_application_modes() {
+        let modes = TerminalModes {
+            app_cursor_keys: true,
+            app_keypad: true,
+            ..Default::default()
+        };
+        // (key, modifiers, expected)
+        let table: &[(Key, &str, &[u8])] = &[
+            (Key::Up, "", b"\x1bOA"),
+            (Key::Home, "", b"\x1bOH"),
+            (Key::Up, "C", b"\x1b[1;5A"),
+            (Key::PageUp, "", b"\x1b[5~"),
+            (Key::Keypad('0'), "", b"\x1bOp"),
+            (Key::Keypad('9'), "", b"\x1bOy"),
+            (Key::Keypad('.'), "", b"\x1bOn"),
+            (Key::Keypad('*'), "", b"\x1bOj"),
+            (Key::Keypad('-'), "", b"\x1bOm"),
+            (Key::Keypad('/'), "", b"\x1bOo"),
+            (Key::Keypad('+'), "S", b"\x1bO2k"),
+            (Key::KeypadEnter, "", b"\x1bOM"),
+            (Key::Char('a'), "", b"a"),
+        ];
+        for (key, modifiers, expected) in table {
+            let got = encode_key(&modes, 0, key, mods(modifiers), KeyEventKind::Press);
+            assert_eq!(got.as_deref(), Some(*expected), "{:?} {:?}", key, modifiers);
+        }
+    }
+
+    #[test]
+    fn test_disambiguate() {
+        let flags = DISAMBIGUATE_ESCAPE_CODES;
+        assert_eq!(press(flags, Key::Char('i'), CTRL), b"\x1b[105;5u");
+        assert_eq!(press(flags, Key::Tab, NONE), b"\x09");
+        assert_eq!(press(flags, Key::Escape, NONE), b"\x1b[27u");
+        assert_eq!(press(flags, Key::Char('A'), SHIFT), b"A");
+        assert_eq!(press(flags, Key::Up, NONE), b"\x1b[A");
+        assert_eq!(press(flags, Key::Up, CTRL), b"\x1b[1;5A");
+        assert_eq!(press(flags, Key::F(3), SHIFT), b"\x1b[13;2~");
+    }
+
+    #[test]
+    fn test_event_types_and_all_keys() {
+        let modes = TerminalModes::default();
+        let flags = DISAMBIGUATE_ESCAPE_CODES | REPORT_EVENT_TYPES;
+        let release = |key: Key| encode_key(&modes, flags, &key, NONE, KeyEventKind::Release);
+        assert_eq!(release(Key::Escape).unwrap(), b"\x1b[27;1:3u");
+        assert_eq!(release(Key::Left).unwrap(), b"\x1b[1;1:3D");
+        assert_eq!(release(Key::Char('a')), None);
+        let repeat = encode_key(&modes, flags, &Key::Char('a'), CTRL, KeyEventKind::Repeat);
+        assert_eq!(repeat.unwrap(), b"\x1b[97;5:2u");
+
+        let flags = REPORT_ALL_KEYS_AS_ESCAPE_CODES | REPORT_ALTERNATE_KEYS;
+        assert_eq!(press(flags, Key::Char('a'), NONE), b"\x1b[97u");
+        assert_eq!(press(flags, Key::Enter, NONE), b"\x1b[13u");
+        assert_eq!(press(flags, Key::Char('A'), SHIFT), b"\x1b[97:65;2u");
+        let flags = REPORT_ALL_KEYS_AS_ESCAPE_CODES | REPORT_ASSOCIATED_TEXT;
+        assert_eq!(press(flags, Key::Char('A'), SHIFT), b"\x1b[97;2;65u");
+        assert_eq!(press(flags, Key::F(13), NONE), b"\x1b[57376u");
+    }
+}
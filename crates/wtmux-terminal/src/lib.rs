@@ -1,10 +1,24 @@
 pub mod cell;
+pub mod charset;
+pub mod clipboard;
+pub mod dcs;
 pub mod grid;
+pub mod hyperlink;
+pub mod input;
+pub mod keyboard;
+pub mod modes;
+pub mod palette;
 pub mod parser;
+mod reflow;
+pub mod row;
 pub mod scrollback;
+pub mod search;
 pub mod statusbar;
+pub mod style;
 pub mod terminal;
 
 pub use cell::{Attrs, Cell, Color};
 pub use grid::Grid;
+pub use modes::{MouseEncoding, MouseTracking, TerminalModes};
+pub use row::{LineMarks, Row};
 pub use terminal::Terminal;
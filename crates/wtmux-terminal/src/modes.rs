@@ -0,0 +1,131 @@
+/// Which mouse events the application asked to receive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MouseTracking {
+    /// No mouse reporting.
+    #[default]
+    Off,
+    /// Button press and release (`?1000`).
+    Click,
+    /// Press, release and motion while a button is held (`?1002`).
+    Drag,
+    /// Press, release and all motion (`?1003`).
+    Any,
+}
+
+/// How mouse reports are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MouseEncoding {
+    /// Legacy X10-style `CSI M Cb Cx Cy` bytes.
+    #[default]
+    Default,
+    /// SGR `CSI < b ; x ; y M/m` (`?1006`).
+    Sgr,
+    /// urxvt `CSI b ; x ; y M` (`?1015`).
+    Urxvt,
+}
+
+/// DEC private modes set by the application running in the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalModes {
+    /// DECCKM (`?1`): cursor keys send SS3 instead of CSI sequences.
+    pub app_cursor_keys: bool,
+    /// DECKPAM (`ESC =`, or DECNKM `?66`): the keypad sends SS3 sequences
+    /// instead of the characters on its keys.
+    pub app_keypad: bool,
+    /// DECOM (`?6`): cursor addressing is relative to the scroll region.
+    pub origin: bool,
+    /// DECAWM (`?7`): printing past the last column wraps to the next line.
+    pub autowrap: bool,
+    /// `?1004`: send `CSI I` / `CSI O` when the pane gains or loses focus.
+    pub focus_reporting: bool,
+    /// `?1007`: on the alternate screen, turn wheel scrolls into cursor keys.
+    pub alternate_scroll: bool,
+    /// `?2004`: wrap pasted text in `CSI 200 ~` / `CSI 201 ~`.
+    pub bracketed_paste: bool,
+    /// `?2026`: the application is drawing a frame that should not be
+    /// shown until it is complete.
+    pub synchronized_output: bool,
+    pub mouse_tracking: MouseTracking,
+    pub mouse_encoding: MouseEncoding,
+}
+
+impl Default for TerminalModes {
+    fn default() -> Self {
+        TerminalModes {
+            app_cursor_keys: false,
+            app_keypad: false,
+            origin: false,
+            autowrap: true,
+            focus_reporting: false,
+            alternate_scroll: false,
+            bracketed_paste: false,
+            synchronized_output: false,
+            mouse_tracking: MouseTracking::Off,
+            mouse_encoding: MouseEncoding::Default,
+        }
+    }
+}
+
+impl TerminalModes {
+    /// Set or reset a DEC private mode by number. Returns false for modes
+    /// that are not tracked here.
+    pub fn set_private(&mut self, mode: u16, enabled: bool) -> bool {
+        match mode {
+            1 => self.app_cursor_keys = enabled,
+            6 => self.origin = enabled,
+            7 => self.autowrap = enabled,
+            66 => self.app_keypad = enabled,
+            1000 | 1002 | 1003 => {
+                let tracking = match mode {
+                    1000 => MouseTracking::Click,
+                    1002 => MouseTracking::Drag,
+                    _ => MouseTracking::Any,
+                };
+                if enabled {
+                    self.mouse_tracking = tracking;
+                } else if self.mouse_tracking == tracking {
+                    self.mouse_tracking = MouseTracking::Off;
+                }
+            }
+            1004 => self.focus_reporting = enabled,
+            1006 | 1015 => {
+                let encoding = if mode == 1006 {
+                    MouseEncoding::Sgr
+                } else {
+                    MouseEncoding::Urxvt
+                };
+                if enabled {
+                    self.mouse_encoding = encoding;
+                } else if self.mouse_encoding == encoding {
+                    self.mouse_encoding = MouseEncoding::Default;
+                }
+            }
+            1007 => self.alternate_scroll = enabled,
+            2004 => self.bracketed_paste = enabled,
+            2026 => self.synchronized_output = enabled,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Current state of a DEC private mode tracked here, or None if the
+    /// mode is not recognized.
+    pub fn get_private(&self, mode: u16) -> Option<bool> {
+        Some(match mode {
+            1 => self.app_cursor_keys,
+            6 => self.origin,
+            7 => self.autowrap,
+            66 => self.app_keypad,
+            1000 => self.mouse_tracking == MouseTracking::Click,
+            1002 => self.mouse_tracking == MouseTracking::Drag,
+            1003 => self.mouse_tracking == MouseTracking::Any,
+            1004 => self.focus_reporting,
+            1006 => self.mouse_encoding == MouseEncoding::Sgr,
+            1007 => self.alternate_scroll,
+            1015 => self.mouse_encoding == MouseEncoding::Urxvt,
+            2004 => self.bracketed_paste,
+            2026 => self.synchronized_output,
+            _ => return None,
+        })
+    }
+}
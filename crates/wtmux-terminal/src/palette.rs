@@ -0,0 +1,194 @@
+use crate::cell::Color;
+
+/// An RGB color value.
+pub type Rgb = (u8, u8, u8);
+
+/// Foreground answered for OSC 10 when the application has not set one.
+const DEFAULT_FG: Rgb = (0xe5, 0xe5, 0xe5);
+/// Background answered for OSC 11 when the application has not set one.
+const DEFAULT_BG: Rgb = (0x00, 0x00, 0x00);
+
+/// The 256-color palette and default colors of a terminal, with the
+/// overrides applications set through OSC 4/10/11/12.
+#[derive(Debug, Clone)]
+pub struct Palette {
+    colors: [Option<Rgb>; 256],
+    pub fg: Option<Rgb>,
+    pub bg: Option<Rgb>,
+    pub cursor: Option<Rgb>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            colors: [None; 256],
+            fg: None,
+            bg: None,
+            cursor: None,
+        }
+    }
+}
+
+impl Palette {
+    /// Current value of a palette entry.
+    pub fn color(&self, index: u8) -> Rgb {
+        self.colors[index as usize].unwrap_or_else(|| default_color(index))
+    }
+
+    /// Override a palette entry.
+    pub fn set_color(&mut self, index: u8, rgb: Rgb) {
+        self.colors[index as usize] = Some(rgb);
+    }
+
+    /// Drop the override of a palette entry.
+    pub fn reset_color(&mut self, index: u8) {
+        self.colors[index as usize] = None;
+    }
+
+    /// Drop all palette entry overrides.
+    pub fn reset_colors(&mut self) {
+        self.colors = [None; 256];
+    }
+
+    pub fn foreground(&self) -> Rgb {
+        self.fg.unwrap_or(DEFAULT_FG)
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.bg.unwrap_or(DEFAULT_BG)
+    }
+
+    pub fn cursor_color(&self) -> Rgb {
+        self.cursor.unwrap_or_else(|| self.foreground())
+    }
+
+    /// The color to draw with: overridden palette entries and default
+    /// colors become explicit RGB, everything else is left alone so the
+    /// client terminal's own palette applies.
+    pub fn resolve(&self, color: Color, is_fg: bool) -> Color {
+        let rgb = match color {
+            Color::Indexed(n) => self.colors[n as usize],
+            Color::Default if is_fg => self.fg,
+            Color::Default => self.bg,
+            Color::Rgb(..) => None,
+        };
+        match rgb {
+            Some((r, g, b)) => Color::Rgb(r, g, b),
+            None => color,
+        }
+    }
+}
+
+/// xterm's default value for a palette entry.
+fn default_color(index: u8) -> Rgb {
+    const BASE: [Rgb; 16] = [
+        (0x00, 0x00, 0x00),
+        (0xcd, 0x00, 0x00),
+        (0x00, 0xcd, 0x00),
+        (0xcd, 0xcd, 0x00),
+        (0x00, 0x00, 0xee),
+        (0xcd, 0x00, 0xcd),
+        (0x00, 0xcd, 0xcd),
+        (0xe5, 0xe5, 0xe5),
+        (0x7f, 0x7f, 0x7f),
+        (0xff, 0x00, 0x00),
+        (0x00, 0xff, 0x00),
+        (0xff, 0xff, 0x00),
+        (0x5c, 0x5c, 0xff),
+        (0xff, 0x00, 0xff),
+        (0x00, 0xff, 0xff),
+        (0xff, 0xff, 0xff),
+    ];
+    match index {
+        0..=15 => BASE[index as usize],
+        16..=231 => {
+            // 6x6x6 color cube
+            let n = index - 16;
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            (level(n / 36), level((n / 6) % 6), level(n % 6))
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// Parse an X11 color spec: `rgb:r/g/b` with 1-4 hex digits per component,
+/// or `#rgb`, `#rrggbb`, `#rrrgggbbb`, `#rrrrggggbbbb`.
+pub fn parse_color_spec(spec: &[u8]) -> Option<Rgb> {
+    let spec = std::str::from_utf8(spec).ok()?;
+    if let Some(rest) = spec.strip_prefix("rgb:") {
+        let mut parts = rest.split('/');
+        let r = scale_hex(parts.next()?)?;
+        let g = scale_hex(parts.next()?)?;
+        let b = scale_hex(parts.next()?)?;
+        return parts.next().is_none().then_some((r, g, b));
+    }
+    let hex = spec.strip_prefix('#')?;
+    if hex.is_empty() || hex.len() % 3 != 0 || hex.len() > 12 || !hex.is_ascii() {
+        return None;
+    }
+    // In the # form the high digits are significant, so each component is
+    // truncated to its first two digits.
+    let n = hex.len() / 3;
+    let component = |i: usize| -> Option<u8> {
+        let digits = &hex[i * n..(i + 1) * n];
+        let value = u16::from_str_radix(digits, 16).ok()?;
+        Some(if n == 1 {
+            (value * 0x11) as u8
+        } else {
+            (value >> ((n - 2) * 4)) as u8
+        })
+    };
+    Some((component(0)?, component(1)?, component(2)?))
+}
+
+/// Scale a 1-4 digit hex component to 8 bits.
+fn scale_hex(digits: &str) -> Option<u8> {
+    if digits.is_empty() || digits.len() > 4 {
+        return None;
+    }
+    let value = u32::from_str_radix(digits, 16).ok()?;
+    let max = (1u32 << (4 * digits.len())) - 1;
+    Some(((value * 255 + max / 2) / max) as u8)
+}
+
+/// Format a color the way xterm reports it: `rgb:rrrr/gggg/bbbb`.
+pub fn format_color((r, g, b): Rgb) -> String {
+    format!(
+        "rgb:{:04x}/{:04x}/{:04x}",
+        r as u16 * 0x101,
+        g as u16 * 0x101,
+        b as u16 * 0x101
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color_spec() {
+        assert_eq!(parse_color_spec(b"rgb:ff/80/00"), Some((0xff, 0x80, 0x00)));
+        assert_eq!(
+            parse_color_spec(b"rgb:ffff/0000/8080"),
+            Some((0xff, 0x00, 0x80))
+        );
+        assert_eq!(parse_color_spec(b"rgb:f/0/8"), Some((0xff, 0x00, 0x88)));
+        assert_eq!(parse_color_spec(b"#1a2b3c"), Some((0x1a, 0x2b, 0x3c)));
+        assert_eq!(parse_color_spec(b"#fff"), Some((0xff, 0xff, 0xff)));
+        assert_eq!(parse_color_spec(b"red"), None);
+        assert_eq!(parse_color_spec(b"rgb:ff/ff"), None);
+    }
+
+    #[test]
+    fn test_default_palette() {
+        let palette = Palette::default();
+        assert_eq!(palette.color(1), (0xcd, 0x00, 0x00));
+        assert_eq!(palette.color(16), (0, 0, 0));
+        assert_eq!(palette.color(231), (0xff, 0xff, 0xff));
+        assert_eq!(palette.color(232), (8, 8, 8));
+        assert_eq!(format_color((0xff, 0x00, 0x80)), "rgb:ffff/0000/8080");
+    }
+}
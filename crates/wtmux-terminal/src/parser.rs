@@ -1,7 +1,19 @@
-use crate::cell::{Attrs, Cell, Color};
+use crate::cell::{AttrFlags, Attrs, Color, GridCell, UnderlineStyle};
+use crate::charset::{Charset, CharsetState};
+use crate::clipboard::ClipboardRequest;
+use crate::dcs::{DcsString, Passthrough, PassthroughCollector};
 use crate::grid::Grid;
+use crate::hyperlink::{Hyperlink, HyperlinkTable};
+use crate::keyboard::KeyboardFlagStack;
+use crate::modes::TerminalModes;
+use crate::palette::{format_color, parse_color_spec, Palette};
+use crate::reflow::reflow;
+use crate::row::{LineMarks, Row};
+use crate::scrollback::Scrollback;
+use crate::style::{SharedStyles, Style};
 use tracing::trace;
-use unicode_width::UnicodeWidthChar;
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 /// Cursor position and attributes for the terminal.
 pub struct Cursor {
@@ -11,6 +23,11 @@ pub struct Cursor {
     pub fg: Color,
     pub bg: Color,
     pub visible: bool,
+    /// Hyperlink applied to printed characters (0 for none).
+    pub hyperlink: u32,
+    /// A character was printed in the last column and the next one wraps
+    /// to a new line (xterm's "last column flag").
+    pub pending_wrap: bool,
 }
 
 impl Default for Cursor {
@@ -22,39 +39,172 @@ impl Default for Cursor {
             fg: Color::Default,
             bg: Color::Default,
             visible: true,
+            hyperlink: 0,
+            pending_wrap: false,
         }
     }
 }
 
+impl Cursor {
+    /// Position to track through a reflow. A pending wrap puts the cursor
+    /// logically just past the last column.
+    fn reflow_position(&self) -> (u16, u16) {
+        (self.col + self.pending_wrap as u16, self.row)
+    }
+}
+
+/// Cursor shape chosen by the application with DECSCUSR (`CSI Ps SP q`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CursorStyle {
+    /// Whatever the client terminal uses by default (Ps 0).
+    #[default]
+    Default,
+    BlinkingBlock,
+    SteadyBlock,
+    BlinkingUnderline,
+    SteadyUnderline,
+    BlinkingBar,
+    SteadyBar,
+}
+
+impl CursorStyle {
+    pub fn from_param(ps: u16) -> Option<Self> {
+        Some(match ps {
+            0 => CursorStyle::Default,
+            1 => CursorStyle::BlinkingBlock,
+            2 => CursorStyle::SteadyBlock,
+            3 => CursorStyle::BlinkingUnderline,
+            4 => CursorStyle::SteadyUnderline,
+            5 => CursorStyle::BlinkingBar,
+            6 => CursorStyle::SteadyBar,
+            _ => return None,
+        })
+    }
+
+    /// The DECSCUSR parameter that selects this style.
+    pub fn param(self) -> u16 {
+        match self {
+            CursorStyle::Default => 0,
+            CursorStyle::BlinkingBlock => 1,
+            CursorStyle::SteadyBlock => 2,
+            CursorStyle::BlinkingUnderline => 3,
+            CursorStyle::SteadyUnderline => 4,
+            CursorStyle::BlinkingBar => 5,
+            CursorStyle::SteadyBar => 6,
+        }
+    }
+}
+
+/// Cursor state saved by DECSC and restored by DECRC.
+#[derive(Debug, Clone, Copy)]
+pub struct SavedCursor {
+    pub col: u16,
+    pub row: u16,
+    pub attrs: Attrs,
+    pub fg: Color,
+    pub bg: Color,
+    pub charsets: CharsetState,
+    pub pending_wrap: bool,
+}
+
+/// Columns between the default tab stops.
+const TAB_WIDTH: usize = 8;
+
+/// Most characters a cell keeps after its base character, as other
+/// terminals cap them, so that a long run of combining marks costs no more
+/// per character than plain text.
+const MAX_COMBINING: usize = 16;
+
+/// Default number of lines kept in a terminal's scrollback history.
+pub const DEFAULT_HISTORY_LIMIT: usize = 2000;
+
 /// Terminal state that implements vte::Perform to process VT sequences.
 pub struct TerminalState {
     pub grid: Grid,
+    /// Lines scrolled off the top of the main screen.
+    pub scrollback: Scrollback,
+    /// Styles of the cells in both screens and the scrollback.
+    pub(crate) styles: SharedStyles,
+    /// Style of the cursor when characters were last printed, and its id.
+    pen: (Style, u32),
     pub cursor: Cursor,
+    /// Cursor shape set with DECSCUSR.
+    pub cursor_style: CursorStyle,
     pub scroll_top: u16,
     pub scroll_bottom: u16,
-    pub saved_cursor: Option<(u16, u16, Attrs, Color, Color)>,
+    pub saved_cursor: Option<SavedCursor>,
     pub title: String,
+    /// Working directory reported by the shell with OSC 7.
+    pub current_dir: Option<String>,
+    /// Links opened with OSC 8, referenced by id from cells.
+    pub hyperlinks: HyperlinkTable,
+    /// Color palette, with overrides set through OSC 4/10/11/12.
+    pub palette: Palette,
     /// Whether the terminal content has changed since last render.
     pub dirty: bool,
     // Alternate screen buffer support
     alt_grid: Option<Grid>,
     alt_cursor: Option<Cursor>,
     pub using_alt_screen: bool,
+    modes: TerminalModes,
+    /// Kitty keyboard flags of the main and alternate screens.
+    keyboard_flags: KeyboardFlagStack,
+    alt_keyboard_flags: KeyboardFlagStack,
+    charsets: CharsetState,
+    /// One flag per column, set where there is a tab stop.
+    tab_stops: Vec<bool>,
+    /// Replies to queries, waiting to be written back to the application.
+    outgoing: Vec<u8>,
+    /// OSC 52 requests waiting to be handled by the server.
+    clipboard_requests: Vec<ClipboardRequest>,
+    /// DCS string being collected.
+    dcs: Option<DcsString>,
+    /// Payload of a `DCS tmux;` sequence being collected. While set, the
+    /// bytes bypass the VT parser (see [`Self::passthrough_byte`]).
+    tmux_passthrough: Option<PassthroughCollector>,
+    /// Passthrough sequences waiting to be forwarded by the server.
+    passthrough: Vec<Passthrough>,
+    /// The application rang the bell since the last [`Self::take_bell`].
+    bell: bool,
 }
 
 impl TerminalState {
     pub fn new(cols: u16, rows: u16) -> Self {
+        Self::with_history_limit(cols, rows, DEFAULT_HISTORY_LIMIT)
+    }
+
+    /// Create a terminal state that keeps at most `history_limit` scrollback lines.
+    pub fn with_history_limit(cols: u16, rows: u16, history_limit: usize) -> Self {
+        let styles = SharedStyles::new();
         TerminalState {
-            grid: Grid::new(cols, rows),
+            grid: Grid::with_styles(cols, rows, styles.clone()),
+            scrollback: Scrollback::with_styles(history_limit, styles.clone()),
+            styles,
+            pen: (Style::default(), 0),
             cursor: Cursor::default(),
+            cursor_style: CursorStyle::default(),
             scroll_top: 0,
             scroll_bottom: rows,
             saved_cursor: None,
             title: String::new(),
+            current_dir: None,
+            hyperlinks: HyperlinkTable::new(),
+            palette: Palette::default(),
             dirty: true,
             alt_grid: None,
             alt_cursor: None,
             using_alt_screen: false,
+            modes: TerminalModes::default(),
+            keyboard_flags: KeyboardFlagStack::default(),
+            alt_keyboard_flags: KeyboardFlagStack::default(),
+            charsets: CharsetState::default(),
+            tab_stops: (0..cols as usize).map(|col| col % TAB_WIDTH == 0).collect(),
+            outgoing: Vec::new(),
+            clipboard_requests: Vec::new(),
+            dcs: None,
+            tmux_passthrough: None,
+            passthrough: Vec::new(),
+            bell: false,
         }
     }
 
@@ -66,131 +216,799 @@ impl TerminalState {
         self.grid.rows
     }
 
+    /// DEC private modes set by the application.
+    pub fn modes(&self) -> &TerminalModes {
+        &self.modes
+    }
+
+    /// Kitty keyboard protocol flags in effect on the current screen.
+    pub fn keyboard_flags(&self) -> u8 {
+        if self.using_alt_screen {
+            self.alt_keyboard_flags.current()
+        } else {
+            self.keyboard_flags.current()
+        }
+    }
+
+    fn keyboard_flags_mut(&mut self) -> &mut KeyboardFlagStack {
+        if self.using_alt_screen {
+            &mut self.alt_keyboard_flags
+        } else {
+            &mut self.keyboard_flags
+        }
+    }
+
+    /// Take the bytes queued in reply to queries (DSR, DA, XTVERSION,
+    /// DECRQM). The caller writes them back to the PTY.
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// Take the OSC 52 clipboard requests made since the last call.
+    pub fn take_clipboard_requests(&mut self) -> Vec<ClipboardRequest> {
+        std::mem::take(&mut self.clipboard_requests)
+    }
+
+    /// Take the `DCS tmux;` passthrough sequences received since the last call.
+    pub fn take_passthrough(&mut self) -> Vec<Passthrough> {
+        std::mem::take(&mut self.passthrough)
+    }
+
+    /// Whether the application rang the bell since the last call.
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell)
+    }
+
+    /// Whether a `DCS tmux;` payload is being collected.
+    pub(crate) fn in_passthrough(&self) -> bool {
+        self.tmux_passthrough.is_some()
+    }
+
+    /// Feed a byte of a `DCS tmux;` payload. Its doubled ESCs would end the
+    /// DCS in the VT parser, so these bytes are collected here instead.
+    /// Returns true once the payload is complete.
+    pub(crate) fn passthrough_byte(&mut self, byte: u8) -> bool {
+        let Some(collector) = self.tmux_passthrough.as_mut() else {
+            return false;
+        };
+        if !collector.push(byte) {
+            return false;
+        }
+        if let Some(data) = self.tmux_passthrough.take().and_then(PassthroughCollector::finish) {
+            self.passthrough.push(Passthrough {
+                data,
+                col: self.cursor.col,
+                row: self.cursor.row,
+            });
+        }
+        true
+    }
+
+    /// Number of lines in the scrollback history.
+    pub fn history_len(&self) -> usize {
+        self.scrollback.len()
+    }
+
+    /// Number of lines in the combined history + visible screen view.
+    pub fn total_lines(&self) -> usize {
+        self.scrollback.len() + self.grid.rows as usize
+    }
+
+    /// Get a line from the combined history + visible screen view.
+    ///
+    /// Index 0 is the oldest history line; `history_len()` is the top row of
+    /// the visible screen. The line is unpacked into a new row.
+    pub fn line(&self, index: usize) -> Option<Row> {
+        let history = self.scrollback.len();
+        if index < history {
+            self.scrollback.get_line(history - 1 - index)
+        } else if index - history < self.grid.rows as usize {
+            Some(self.grid.line((index - history) as u16))
+        } else {
+            None
+        }
+    }
+
+    /// Shell integration marks of a line of the combined history + visible
+    /// screen view, without unpacking history lines.
+    pub fn line_marks(&self, index: usize) -> Option<LineMarks> {
+        let history = self.scrollback.len();
+        if index < history {
+            self.scrollback.get_marks(history - 1 - index)
+        } else if index - history < self.grid.rows as usize {
+            Some(self.grid.grid_line((index - history) as u16).marks)
+        } else {
+            None
+        }
+    }
+
+    /// Whether a line of the combined history + visible screen view
+    /// soft-wrapped into the next one, without unpacking history lines.
+    pub fn line_wrapped(&self, index: usize) -> Option<bool> {
+        let history = self.scrollback.len();
+        if index < history {
+            self.scrollback.get_wrapped(history - 1 - index)
+        } else if index - history < self.grid.rows as usize {
+            Some(self.grid.is_wrapped((index - history) as u16))
+        } else {
+            None
+        }
+    }
+
+    /// The closest line before `line` where a prompt starts (OSC 133 A).
+    pub fn previous_prompt(&self, line: usize) -> Option<usize> {
+        (0..line.min(self.total_lines()))
+            .rev()
+            .find(|&i| self.line_marks(i).is_some_and(|marks| marks.prompt))
+    }
+
+    /// The closest line after `line` where a prompt starts (OSC 133 A).
+    pub fn next_prompt(&self, line: usize) -> Option<usize> {
+        (line + 1..self.total_lines())
+            .find(|&i| self.line_marks(i).is_some_and(|marks| marks.prompt))
+    }
+
+    /// Text printed by the last command that finished: from its OSC 133 C
+    /// mark up to where its D mark arrived. Soft-wrapped lines are joined.
+    /// The C mark is looked for after the command's prompt only, so that a
+    /// command without one does not pick up an older command's output.
+    pub fn last_command_output(&self) -> Option<String> {
+        let end = (0..self.total_lines())
+            .rev()
+            .find(|&i| self.line_marks(i).is_some_and(|marks| marks.finished))?;
+        let finished_col = self.line_marks(end)?.finished_col as usize;
+        let prompt = self.previous_prompt(end).unwrap_or(0);
+        let start = (prompt..=end)
+            .rev()
+            .find(|&i| self.line_marks(i).is_some_and(|marks| marks.output))?;
+
+        let mut text = String::new();
+        // Cells left before D on the line that carries it.
+        let mut left = None;
+        let mut newline = false;
+        let mut i = start;
+        while let Some(row) = self.line(i) {
+            if i == end {
+                left = Some(finished_col);
+            }
+            if left == Some(0) {
+                break;
+            }
+            if newline {
+                text.push('\n');
+            }
+            let take = left.map_or(row.len(), |left: usize| left.min(row.len()));
+            let mut segment = String::new();
+            for cell in row[..take].iter().filter(|c| c.width > 0) {
+                cell.push_grapheme(&mut segment);
+            }
+            let wrapped = row.wrapped && take == row.len();
+            if wrapped {
+                // Spaces before a soft wrap are part of the line.
+                text.push_str(&segment);
+            } else {
+                text.push_str(segment.trim_end());
+            }
+            newline = !wrapped;
+            if let Some(left) = &mut left {
+                *left -= take;
+            }
+            if i >= end && !wrapped {
+                break;
+            }
+            i += 1;
+        }
+        Some(text)
+    }
+
+    /// Resize the terminal. The main screen and its history are rewrapped
+    /// to the new width; the alternate screen is truncated or padded.
     pub fn resize(&mut self, cols: u16, rows: u16) {
-        self.grid.resize(cols, rows);
+        if cols == 0 || rows == 0 {
+            return;
+        }
+
+        if self.using_alt_screen {
+            self.grid.resize(cols, rows);
+            let saved = (self.alt_grid.take(), self.alt_cursor.as_mut());
+            if let (Some(main), Some(main_cursor)) = saved {
+                let (grid, (col, row)) = reflow(
+                    main,
+                    &mut self.scrollback,
+                    main_cursor.reflow_position(),
+                    cols,
+                    rows,
+                );
+                main_cursor.col = col;
+                main_cursor.row = row;
+                main_cursor.pending_wrap = false;
+                self.alt_grid = Some(grid);
+            }
+        } else {
+            let main = std::mem::replace(&mut self.grid, Grid::new(0, 0));
+            let epoch = main.damage_epoch();
+            let (mut grid, (col, row)) = reflow(
+                main,
+                &mut self.scrollback,
+                self.cursor.reflow_position(),
+                cols,
+                rows,
+            );
+            grid.carry_damage_epoch(epoch);
+            self.grid = grid;
+            self.cursor.col = col;
+            self.cursor.row = row;
+        }
+
+        // Keep the stops set so far; new columns get the default stops.
+        let old_cols = self.tab_stops.len();
+        self.tab_stops.truncate(cols as usize);
+        self.tab_stops
+            .extend((old_cols..cols as usize).map(|col| col % TAB_WIDTH == 0));
+
         self.scroll_top = 0;
         self.scroll_bottom = rows;
+        self.cursor.pending_wrap = false;
         if self.cursor.col >= cols {
             self.cursor.col = cols - 1;
         }
         if self.cursor.row >= rows {
             self.cursor.row = rows - 1;
         }
-        if let Some(ref mut alt) = self.alt_grid {
-            alt.resize(cols, rows);
-        }
         self.dirty = true;
     }
 
-    fn advance_cursor(&mut self) {
-        self.cursor.col += 1;
-        if self.cursor.col >= self.grid.cols {
-            self.cursor.col = 0;
-            self.line_feed();
+    /// Move the cursor past a character whose last cell is `last_col`. In
+    /// the last column the cursor stays put; with DECAWM set the next
+    /// character wraps (deferred wrap).
+    fn advance_past(&mut self, last_col: u16) {
+        if last_col + 1 >= self.grid.cols {
+            self.cursor.col = self.grid.cols - 1;
+            self.cursor.pending_wrap = self.modes.autowrap;
+        } else {
+            self.cursor.col = last_col + 1;
         }
     }
 
+    /// Soft-wrap to the start of the next line.
+    fn wrap_line(&mut self) {
+        self.grid.set_wrapped(self.cursor.row, true);
+        self.cursor.col = 0;
+        self.cursor.pending_wrap = false;
+        self.line_feed();
+    }
+
     fn line_feed(&mut self) {
         if self.cursor.row + 1 >= self.scroll_bottom {
-            self.grid.scroll_up(self.scroll_top, self.scroll_bottom);
+            self.scroll_up();
         } else {
             self.cursor.row += 1;
         }
     }
 
+    /// Scroll the scroll region up by one line. Lines leaving a full-screen
+    /// region on the main screen are kept in the scrollback.
+    fn scroll_up(&mut self) {
+        let line = self.grid.scroll_up_line(self.scroll_top, self.scroll_bottom);
+        if let Some(line) = line {
+            if self.scroll_top == 0
+                && self.scroll_bottom == self.grid.rows
+                && !self.using_alt_screen
+            {
+                self.scrollback.push_grid_line(&line);
+            }
+        }
+    }
+
+    /// Style id for characters printed with the cursor's colors,
+    /// attributes and link.
+    fn pen(&mut self) -> u32 {
+        let style = Style {
+            fg: self.cursor.fg,
+            bg: self.cursor.bg,
+            attrs: self.cursor.attrs,
+            hyperlink: self.cursor.hyperlink,
+        };
+        if self.pen.0 != style {
+            if self.styles.read().needs_sweep() {
+                self.sweep_styles();
+            }
+            self.pen = (style, self.styles.write().intern(style));
+        }
+        self.pen.1
+    }
+
+    /// Free the styles no cell uses any more, such as those of lines that
+    /// dropped out of the history.
+    fn sweep_styles(&mut self) {
+        let alt_grid = self.alt_grid.iter().flat_map(Grid::style_ids);
+        let used = self.grid.style_ids().chain(alt_grid).chain(self.scrollback.style_ids());
+        self.styles.write().sweep(used.chain([self.pen.1]));
+    }
+
+    /// Free the links no cell uses any more. Cells refer to links through
+    /// their styles, so unused styles are freed first.
+    fn sweep_hyperlinks(&mut self) {
+        self.sweep_styles();
+        let cursors = [Some(&self.cursor), self.alt_cursor.as_ref()];
+        let cursors = cursors.into_iter().flatten().map(|cursor| cursor.hyperlink);
+        let styles = self.styles.read();
+        let used = styles.iter().map(|style| style.hyperlink).chain(cursors);
+        self.hyperlinks.sweep(used);
+    }
+
     fn enter_alt_screen(&mut self) {
         if !self.using_alt_screen {
             let cols = self.grid.cols;
             let rows = self.grid.rows;
-            self.alt_grid = Some(std::mem::replace(&mut self.grid, Grid::new(cols, rows)));
-            self.alt_cursor = Some(std::mem::replace(&mut self.cursor, Cursor::default()));
+            let mut alt = Grid::with_styles(cols, rows, self.styles.clone());
+            alt.carry_damage_epoch(self.grid.damage_epoch());
+            self.alt_grid = Some(std::mem::replace(&mut self.grid, alt));
+            // Cursor visibility (DECTCEM) is shared by both screens.
+            let cursor = Cursor {
+                visible: self.cursor.visible,
+                ..Cursor::default()
+            };
+            self.alt_cursor = Some(std::mem::replace(&mut self.cursor, cursor));
+            self.alt_keyboard_flags.clear();
             self.using_alt_screen = true;
         }
     }
 
     fn exit_alt_screen(&mut self) {
         if self.using_alt_screen {
-            if let Some(grid) = self.alt_grid.take() {
+            if let Some(mut grid) = self.alt_grid.take() {
+                grid.carry_damage_epoch(self.grid.damage_epoch());
                 self.grid = grid;
             }
             if let Some(cursor) = self.alt_cursor.take() {
-                self.cursor = cursor;
+                self.cursor = Cursor {
+                    visible: self.cursor.visible,
+                    ..cursor
+                };
             }
             self.using_alt_screen = false;
         }
     }
 
-    fn parse_color_from_params(&self, params: &[u16], idx: &mut usize) -> Option<Color> {
-        if *idx >= params.len() {
+    /// Move the cursor forward `n` tab stops, stopping at the last column.
+    fn tab_forward(&mut self, n: u16) {
+        let last = self.grid.cols - 1;
+        for _ in 0..n {
+            let next = (self.cursor.col as usize + 1..last as usize)
+                .find(|&col| self.tab_stops.get(col).copied().unwrap_or(false));
+            self.cursor.col = next.map_or(last, |col| col as u16);
+        }
+    }
+
+    /// Move the cursor back `n` tab stops, stopping at the first column.
+    fn tab_backward(&mut self, n: u16) {
+        for _ in 0..n {
+            let prev = (0..self.cursor.col as usize)
+                .rev()
+                .find(|&col| self.tab_stops.get(col).copied().unwrap_or(false));
+            self.cursor.col = prev.map_or(0, |col| col as u16);
+        }
+    }
+
+    /// DECSC: save the cursor position, attributes, character sets and
+    /// pending wrap.
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            col: self.cursor.col,
+            row: self.cursor.row,
+            attrs: self.cursor.attrs,
+            fg: self.cursor.fg,
+            bg: self.cursor.bg,
+            charsets: self.charsets,
+            pending_wrap: self.cursor.pending_wrap,
+        });
+    }
+
+    /// DECRC: restore the state saved by DECSC.
+    fn restore_cursor(&mut self) {
+        if let Some(saved) = self.saved_cursor {
+            self.cursor.col = saved.col.min(self.grid.cols - 1);
+            self.cursor.row = saved.row.min(self.grid.rows - 1);
+            self.cursor.attrs = saved.attrs;
+            self.cursor.fg = saved.fg;
+            self.cursor.bg = saved.bg;
+            self.charsets = saved.charsets;
+            self.cursor.pending_wrap = saved.pending_wrap && self.cursor.col == self.grid.cols - 1;
+            self.dirty = true;
+        }
+    }
+
+    /// Every cell may be drawn in a different color now.
+    fn palette_changed(&mut self) {
+        self.grid.damage_all();
+        self.dirty = true;
+    }
+
+    /// OSC 4: pairs of palette index and color spec, or `?` to query.
+    fn set_palette_colors(&mut self, args: &[&[u8]], terminator: &str) {
+        for pair in args.chunks_exact(2) {
+            let Some(index) = std::str::from_utf8(pair[0])
+                .ok()
+                .and_then(|s| s.parse::<u8>().ok())
+            else {
+                continue;
+            };
+            if pair[1] == b"?" {
+                let color = format_color(self.palette.color(index));
+                let reply = format!("\x1b]4;{};{}{}", index, color, terminator);
+                self.outgoing.extend_from_slice(reply.as_bytes());
+            } else if let Some(rgb) = parse_color_spec(pair[1]) {
+                self.palette.set_color(index, rgb);
+                self.palette_changed();
+            }
+        }
+    }
+
+    /// OSC 10/11/12: foreground, background and cursor color. Each further
+    /// argument applies to the next color in that order.
+    fn set_dynamic_colors(&mut self, first: u16, args: &[&[u8]], terminator: &str) {
+        for (code, spec) in (first..=12).zip(args) {
+            if *spec == b"?" {
+                let rgb = match code {
+                    10 => self.palette.foreground(),
+                    11 => self.palette.background(),
+                    _ => self.palette.cursor_color(),
+                };
+                let reply = format!("\x1b]{};{}{}", code, format_color(rgb), terminator);
+                self.outgoing.extend_from_slice(reply.as_bytes());
+            } else if let Some(rgb) = parse_color_spec(spec) {
+                match code {
+                    10 => self.palette.fg = Some(rgb),
+                    11 => self.palette.bg = Some(rgb),
+                    _ => self.palette.cursor = Some(rgb),
+                }
+                self.palette_changed();
+            }
+        }
+    }
+
+    /// Queue the reply to a DECRQM query for `mode`.
+    fn report_mode(&mut self, mode: u16, private: bool) {
+        // 1 = set, 2 = reset, 0 = not recognized
+        let state = if private {
+            let value = match mode {
+                25 => Some(self.cursor.visible),
+                1049 => Some(self.using_alt_screen),
+                _ => self.modes.get_private(mode),
+            };
+            match value {
+                Some(true) => 1,
+                Some(false) => 2,
+                None => 0,
+            }
+        } else {
+            0
+        };
+        let prefix = if private { "?" } else { "" };
+        self.outgoing
+            .extend_from_slice(format!("\x1b[{}{};{}$y", prefix, mode, state).as_bytes());
+    }
+
+    /// Position of the cell the last printed character went into: the one
+    /// before the cursor (or under it while a wrap is pending), or the end
+    /// of the previous row after a soft wrap.
+    fn previous_cell(&self) -> Option<(u16, u16)> {
+        let (col, row) = (self.cursor.col, self.cursor.row);
+        if row >= self.grid.rows {
             return None;
         }
-        match params[*idx] {
-            2 => {
-                // RGB: 2;r;g;b
-                *idx += 1;
-                if *idx + 2 < params.len() {
-                    let r = params[*idx] as u8;
-                    let g = params[*idx + 1] as u8;
-                    let b = params[*idx + 2] as u8;
-                    *idx += 3;
-                    Some(Color::Rgb(r, g, b))
-                } else {
-                    None
+        let (mut col, row) = if self.cursor.pending_wrap {
+            (col, row)
+        } else if col > 0 {
+            (col.min(self.grid.cols) - 1, row)
+        } else if row > 0 && self.grid.is_wrapped(row - 1) {
+            (self.grid.cols - 1, row - 1)
+        } else {
+            return None;
+        };
+        if self.grid.grid_cell(col, row).width == 0 && col > 0 {
+            col -= 1;
+        }
+        Some((col, row))
+    }
+
+    /// Add `c` to the grapheme cluster of the previous cell if it continues
+    /// it (UAX #29). The cell is then as wide as the whole cluster, so a
+    /// VS16 emoji presentation widens it and a VS15 narrows it. Past
+    /// `MAX_COMBINING` characters, further ones continuing the cluster are
+    /// dropped.
+    fn extend_grapheme(&mut self, c: char) -> bool {
+        let Some((col, row)) = self.previous_cell() else {
+            return false;
+        };
+        let cell = self.grid.grid_cell(col, row);
+        if !continues_grapheme(cell, c) {
+            return false;
+        }
+        let combining = cell.combining().unwrap_or("");
+        if combining.chars().count() >= MAX_COMBINING {
+            return true;
+        }
+
+        let old_width = cell.width;
+        // Zero-width marks leave the width alone; other characters (emoji
+        // after a ZWJ, variation selectors) can change it.
+        let width = if c.width() == Some(0) && !matches!(c, '\u{FE0E}' | '\u{FE0F}') {
+            old_width
+        } else {
+            let mut cluster = String::new();
+            cell.push_grapheme(&mut cluster);
+            cluster.push(c);
+            UnicodeWidthStr::width(cluster.as_str()).clamp(1, 2) as u8
+        };
+        let fits_wide = col + 1 < self.grid.cols;
+        let cell = self.grid.cell_mut(col, row);
+        cell.push_combining(c);
+
+        if width == 2 && old_width == 1 && fits_wide {
+            cell.width = 2;
+            let cont = GridCell::new(' ', 0, cell.style);
+            self.grid.line_mut(row).split_wide_at(col as usize + 1);
+            self.grid.set_grid_cell(col + 1, row, cont);
+            if self.cursor.row == row && self.cursor.col == col + 1 {
+                self.advance_past(col + 1);
+            }
+        } else if width == 1 && old_width == 2 {
+            cell.width = 1;
+            self.grid.set_grid_cell(col + 1, row, GridCell::default());
+            if self.cursor.row == row && self.cursor.col == col + 2 {
+                self.cursor.col -= 1;
+            } else if self.cursor.row == row && self.cursor.pending_wrap {
+                // The wide character ended in the last column.
+                self.cursor.col = col + 1;
+                self.cursor.pending_wrap = false;
+            }
+        }
+        true
+    }
+
+    /// SGR - Select Graphic Rendition. Parameters may carry colon-separated
+    /// subparameters (`4:3`, `38:2::r:g:b`), so this works on the grouped
+    /// parameters rather than the flattened list.
+    fn select_graphic_rendition(&mut self, params: &vte::Params) {
+        let attrs = &mut self.cursor.attrs;
+        let mut groups = params.iter();
+        if params.is_empty() {
+            *attrs = Attrs::default();
+            self.cursor.fg = Color::Default;
+            self.cursor.bg = Color::Default;
+        }
+        while let Some(param) = groups.next() {
+            let code = param.first().copied().unwrap_or(0);
+            match code {
+                0 => {
+                    *attrs = Attrs::default();
+                    self.cursor.fg = Color::Default;
+                    self.cursor.bg = Color::Default;
+                }
+                1 => attrs.flags.insert(AttrFlags::BOLD),
+                2 => attrs.flags.insert(AttrFlags::DIM),
+                3 => attrs.flags.insert(AttrFlags::ITALIC),
+                4 => {
+                    let style = match param.get(1) {
+                        Some(&style) => UnderlineStyle::from_sgr(style),
+                        None => Some(UnderlineStyle::Single),
+                    };
+                    if let Some(style) = style {
+                        attrs.underline = style;
+                    }
+                }
+                5 => attrs.flags.insert(AttrFlags::BLINK),
+                7 => attrs.flags.insert(AttrFlags::REVERSE),
+                8 => attrs.flags.insert(AttrFlags::HIDDEN),
+                9 => attrs.flags.insert(AttrFlags::STRIKETHROUGH),
+                21 => attrs.underline = UnderlineStyle::Double,
+                22 => attrs.flags.remove(AttrFlags::BOLD | AttrFlags::DIM),
+                23 => attrs.flags.remove(AttrFlags::ITALIC),
+                24 => attrs.underline = UnderlineStyle::None,
+                25 => attrs.flags.remove(AttrFlags::BLINK),
+                27 => attrs.flags.remove(AttrFlags::REVERSE),
+                28 => attrs.flags.remove(AttrFlags::HIDDEN),
+                29 => attrs.flags.remove(AttrFlags::STRIKETHROUGH),
+                30..=37 => self.cursor.fg = Color::Indexed(code as u8 - 30),
+                38 => {
+                    if let Some(color) = sgr_color(param, &mut groups) {
+                        self.cursor.fg = color;
+                    }
+                }
+                39 => self.cursor.fg = Color::Default,
+                40..=47 => self.cursor.bg = Color::Indexed(code as u8 - 40),
+                48 => {
+                    if let Some(color) = sgr_color(param, &mut groups) {
+                        self.cursor.bg = color;
+                    }
+                }
+                49 => self.cursor.bg = Color::Default,
+                53 => attrs.flags.insert(AttrFlags::OVERLINE),
+                55 => attrs.flags.remove(AttrFlags::OVERLINE),
+                58 => {
+                    if let Some(color) = sgr_color(param, &mut groups) {
+                        attrs.underline_color = color;
+                    }
                 }
+                59 => attrs.underline_color = Color::Default,
+                90..=97 => self.cursor.fg = Color::Indexed(code as u8 - 90 + 8),
+                100..=107 => self.cursor.bg = Color::Indexed(code as u8 - 100 + 8),
+                _ => {}
             }
-            5 => {
-                // Indexed: 5;n
-                *idx += 1;
-                if *idx < params.len() {
-                    let n = params[*idx] as u8;
-                    *idx += 1;
-                    Some(Color::Indexed(n))
-                } else {
-                    None
+        }
+        self.dirty = true;
+    }
+}
+
+/// Whether `c` continues the grapheme cluster in `cell`. Only the boundary
+/// between the cluster's last character and `c` is checked; the rest of the
+/// cluster is read when the rules need it (regional indicator pairs, emoji
+/// ZWJ sequences).
+fn continues_grapheme(cell: &GridCell, c: char) -> bool {
+    let combining = cell.combining().unwrap_or("");
+    let last = combining.chars().next_back().unwrap_or(cell.ch);
+    let mut pair = String::new();
+    pair.push(last);
+    pair.push(c);
+    // Offsets count from the start of the cluster.
+    let before = cell.ch.len_utf8() + combining.len() - last.len_utf8();
+    let mut cursor = GraphemeCursor::new(before + last.len_utf8(), before + pair.len(), true);
+    loop {
+        match cursor.is_boundary(&pair, before) {
+            Ok(boundary) => return !boundary,
+            Err(GraphemeIncomplete::PreContext(_)) => {
+                let mut cluster = String::new();
+                cell.push_grapheme(&mut cluster);
+                cursor.provide_context(&cluster[..before], 0);
+            }
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Parse the color of SGR 38/48/58. With subparameters the whole color is
+/// in `param` (`38:5:n`, `38:2::r:g:b` or `38:2:r:g:b`); otherwise it is
+/// taken from the following parameters (`38;5;n`, `38;2;r;g;b`).
+fn sgr_color<'a>(
+    param: &[u16],
+    groups: &mut impl Iterator<Item = &'a [u16]>,
+) -> Option<Color> {
+    if param.len() > 1 {
+        return match param[1] {
+            5 => param.get(2).map(|&n| Color::Indexed(n as u8)),
+            2 => {
+                let rgb = &param[2..];
+                // Skip the color space id when it is present.
+                let rgb = if rgb.len() >= 4 { &rgb[1..] } else { rgb };
+                match rgb {
+                    [r, g, b, ..] => Some(Color::Rgb(*r as u8, *g as u8, *b as u8)),
+                    _ => None,
                 }
             }
             _ => None,
+        };
+    }
+    let mut next = || groups.next().and_then(|p| p.first().copied());
+    match next()? {
+        5 => next().map(|n| Color::Indexed(n as u8)),
+        2 => {
+            let r = next()?;
+            let g = next()?;
+            let b = next()?;
+            Some(Color::Rgb(r as u8, g as u8, b as u8))
+        }
+        _ => None,
+    }
+}
+
+/// Extract the path from a `file://host/path` URI, decoding %XX escapes.
+/// Windows paths (`file://host/C:/dir`) lose the slash before the drive.
+fn parse_file_uri(uri: &[u8]) -> Option<String> {
+    let rest = uri.strip_prefix(b"file://")?;
+    let path = &rest[rest.iter().position(|&b| b == b'/')?..];
+
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let escaped = (path[i] == b'%')
+            .then(|| path.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(path[i]);
+                i += 1;
+            }
         }
     }
+
+    let path = String::from_utf8(decoded).ok()?;
+    let bytes = path.as_bytes();
+    if bytes.len() >= 3
+        && bytes[0] == b'/'
+        && bytes[1].is_ascii_alphabetic()
+        && bytes[2] == b':'
+    {
+        return Some(path[1..].to_string());
+    }
+    Some(path)
 }
 
 impl vte::Perform for TerminalState {
     fn print(&mut self, c: char) {
+        let c = self.charsets.translate(c);
+        if !c.is_ascii() && self.extend_grapheme(c) {
+            self.dirty = true;
+            return;
+        }
         let width = c.width().unwrap_or(1) as u8;
-        let cell = Cell {
-            ch: c,
-            fg: self.cursor.fg,
-            bg: self.cursor.bg,
-            attrs: self.cursor.attrs,
-            width,
-        };
+        if width == 0 {
+            // A combining character with no cell to attach to.
+            return;
+        }
+        if self.cursor.pending_wrap {
+            if self.modes.autowrap {
+                self.wrap_line();
+            } else {
+                self.cursor.pending_wrap = false;
+            }
+        }
+        if width == 2
+            && self.cursor.col + 1 >= self.grid.cols
+            && self.grid.cols > 1
+            && self.cursor.row < self.grid.rows
+        {
+            if self.modes.autowrap {
+                // A wide character does not fit in the last column: pad it
+                // and continue on the next line, as xterm does.
+                let col = self.cursor.col.min(self.grid.cols - 1);
+                let line = self.grid.line_mut(self.cursor.row);
+                line.split_wide_at(col as usize);
+                line[col as usize] = GridCell::default();
+                self.wrap_line();
+            } else {
+                self.cursor.col = self.grid.cols - 2;
+            }
+        }
+        let style = self.pen();
+        let cell = GridCell::new(c, width, style);
 
         if self.cursor.col < self.grid.cols && self.cursor.row < self.grid.rows {
+            // Overwriting half of a wide character blanks its other half.
+            let line = self.grid.line_mut(self.cursor.row);
+            line.split_wide_at(self.cursor.col as usize);
+            if width == 2 {
+                line.split_wide_at(self.cursor.col as usize + 1);
+            }
             self.grid
-                .set_cell(self.cursor.col, self.cursor.row, cell);
+                .set_grid_cell(self.cursor.col, self.cursor.row, cell);
             // For wide characters, mark the next cell as a continuation.
             if width == 2 && self.cursor.col + 1 < self.grid.cols {
-                let cont = Cell {
-                    ch: ' ',
-                    fg: self.cursor.fg,
-                    bg: self.cursor.bg,
-                    attrs: self.cursor.attrs,
-                    width: 0, // continuation cell
-                };
+                let cont = GridCell::new(' ', 0, style); // continuation cell
                 self.grid
-                    .set_cell(self.cursor.col + 1, self.cursor.row, cont);
-                self.cursor.col += 1;
+                    .set_grid_cell(self.cursor.col + 1, self.cursor.row, cont);
             }
         }
 
-        self.advance_cursor();
+        self.advance_past(self.cursor.col + width as u16 - 1);
         self.dirty = true;
     }
 
     fn execute(&mut self, byte: u8) {
+        // Every control that moves the cursor cancels a pending wrap.
+        if matches!(byte, 0x08..=0x0D) {
+            self.cursor.pending_wrap = false;
+        }
         match byte {
             // BEL
-            0x07 => {}
+            0x07 => self.bell = true,
             // BS (backspace)
             0x08 => {
                 if self.cursor.col > 0 {
@@ -198,10 +1016,7 @@ impl vte::Perform for TerminalState {
                 }
             }
             // HT (tab)
-            0x09 => {
-                let next_tab = ((self.cursor.col / 8) + 1) * 8;
-                self.cursor.col = next_tab.min(self.grid.cols - 1);
-            }
+            0x09 => self.tab_forward(1),
             // LF, VT, FF
             0x0A | 0x0B | 0x0C => {
                 self.line_feed();
@@ -211,27 +1026,130 @@ impl vte::Perform for TerminalState {
             0x0D => {
                 self.cursor.col = 0;
             }
+            // SO - invoke G1
+            0x0E => self.charsets.shift(1),
+            // SI - invoke G0
+            0x0F => self.charsets.shift(0),
             _ => {
                 trace!("Unhandled execute byte: 0x{:02x}", byte);
             }
         }
     }
 
-    fn hook(&mut self, _params: &vte::Params, _intermediates: &[u8], _ignore: bool, _action: char) {}
-    fn put(&mut self, _byte: u8) {}
-    fn unhook(&mut self) {}
+    fn hook(&mut self, _params: &vte::Params, intermediates: &[u8], _ignore: bool, action: char) {
+        self.dcs = Some(DcsString::new(intermediates, action));
+    }
+
+    fn put(&mut self, byte: u8) {
+        if let Some(dcs) = self.dcs.as_mut() {
+            dcs.push(byte);
+            if dcs.is_tmux_header() {
+                self.dcs = None;
+                self.tmux_passthrough = Some(PassthroughCollector::default());
+            }
+        }
+    }
+
+    fn unhook(&mut self) {
+        if let Some(dcs) = self.dcs.take() {
+            trace!(
+                "Unhandled DCS: {:?} {} ({} bytes)",
+                dcs.intermediates,
+                dcs.action,
+                dcs.data.len()
+            );
+        }
+    }
 
-    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
-        if params.len() >= 2 {
-            match params[0] {
-                // Set window title
-                b"0" | b"2" => {
-                    if let Ok(title) = std::str::from_utf8(params[1]) {
-                        self.title = title.to_string();
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        // Replies end the same way as the query did.
+        let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+        match params.first().copied().unwrap_or_default() {
+            // Set window title
+            b"0" | b"2" if params.len() >= 2 => {
+                if let Ok(title) = std::str::from_utf8(params[1]) {
+                    self.title = title.to_string();
+                }
+            }
+            // Working directory: OSC 7 ; file://host/path
+            b"7" if params.len() >= 2 => {
+                let uri = params[1..].join(&b';');
+                match parse_file_uri(&uri) {
+                    Some(path) => self.current_dir = Some(path),
+                    None => trace!("Invalid OSC 7 URI"),
+                }
+            }
+            // Shell integration: OSC 133 ; A|B|C|D [; exit status]
+            b"133" if params.len() >= 2 && !self.using_alt_screen => {
+                let marks = &mut self.grid.line_mut(self.cursor.row).marks;
+                match params[1] {
+                    b"A" => marks.prompt = true,
+                    b"B" => marks.command = true,
+                    b"C" => marks.output = true,
+                    b"D" => {
+                        marks.finished = true;
+                        marks.finished_col =
+                            (self.cursor.col + self.cursor.pending_wrap as u16) as u32;
+                        marks.exit_status = params
+                            .get(2)
+                            .and_then(|status| std::str::from_utf8(status).ok())
+                            .and_then(|status| status.parse().ok());
                     }
+                    _ => trace!("Unhandled OSC 133: {:?}", params[1]),
                 }
-                _ => {}
             }
+            // Palette colors: OSC 4 ; index ; spec ...
+            b"4" => self.set_palette_colors(&params[1..], terminator),
+            // Default foreground, background and cursor colors
+            b"10" => self.set_dynamic_colors(10, &params[1..], terminator),
+            b"11" => self.set_dynamic_colors(11, &params[1..], terminator),
+            b"12" => self.set_dynamic_colors(12, &params[1..], terminator),
+            // Reset palette colors: all, or the listed indices
+            b"104" => {
+                if params.len() < 2 || params[1].is_empty() {
+                    self.palette.reset_colors();
+                } else {
+                    for index in &params[1..] {
+                        if let Some(index) = std::str::from_utf8(index)
+                            .ok()
+                            .and_then(|s| s.parse::<u8>().ok())
+                        {
+                            self.palette.reset_color(index);
+                        }
+                    }
+                }
+                self.palette_changed();
+            }
+            b"110" => {
+                self.palette.fg = None;
+                self.palette_changed();
+            }
+            b"111" => {
+                self.palette.bg = None;
+                self.palette_changed();
+            }
+            b"112" => self.palette.cursor = None,
+            // Clipboard: OSC 52 ; selection ; base64 data or "?"
+            b"52" if params.len() >= 3 => {
+                match ClipboardRequest::parse(params[1], params[2]) {
+                    Some(request) => self.clipboard_requests.push(request),
+                    None => trace!("Invalid OSC 52 data"),
+                }
+            }
+            // Hyperlink: OSC 8 ; params ; URI (the URI may contain ';')
+            b"8" if params.len() >= 3 => {
+                let uri = params[2..].join(&b';');
+                self.cursor.hyperlink = match Hyperlink::parse(params[1], &uri) {
+                    Some(link) => {
+                        if self.hyperlinks.needs_sweep() {
+                            self.sweep_hyperlinks();
+                        }
+                        self.hyperlinks.intern(link)
+                    }
+                    None => 0,
+                };
+            }
+            _ => {}
         }
     }
 
@@ -242,11 +1160,23 @@ impl vte::Perform for TerminalState {
         _ignore: bool,
         action: char,
     ) {
+        let raw_params = params;
         let params: Vec<u16> = params.iter().flat_map(|p| p.iter().copied()).collect();
         let p = |idx: usize, default: u16| -> u16 {
             params.get(idx).copied().filter(|&v| v != 0).unwrap_or(default)
         };
 
+        // Cursor movement and editing cancel a pending wrap; SGR, mode
+        // changes and reports leave it alone.
+        if intermediates.is_empty()
+            && matches!(
+                action,
+                'A'..='M' | 'P' | 'S' | 'T' | 'X' | 'Z' | '@' | '`' | 'a' | 'd' | 'e' | 'f' | 'r'
+            )
+        {
+            self.cursor.pending_wrap = false;
+        }
+
         match action {
             // CUU - Cursor Up
             'A' => {
@@ -296,7 +1226,13 @@ impl vte::Perform for TerminalState {
             'H' | 'f' => {
                 let row = p(0, 1).saturating_sub(1);
                 let col = p(1, 1).saturating_sub(1);
-                self.cursor.row = row.min(self.grid.rows - 1);
+                if self.modes.origin {
+                    // Rows are relative to, and confined to, the scroll region.
+                    let bottom = self.scroll_bottom.max(self.scroll_top + 1) - 1;
+                    self.cursor.row = (self.scroll_top + row).min(bottom);
+                } else {
+                    self.cursor.row = row.min(self.grid.rows - 1);
+                }
                 self.cursor.col = col.min(self.grid.cols - 1);
                 self.dirty = true;
             }
@@ -317,10 +1253,14 @@ impl vte::Perform for TerminalState {
                             self.grid.clear_row(row);
                         }
                     }
-                    2 | 3 => {
+                    2 => {
                         // Clear entire screen
                         self.grid.clear();
                     }
+                    3 => {
+                        // Clear scrollback (xterm "erase saved lines")
+                        self.scrollback.clear();
+                    }
                     _ => {}
                 }
                 self.dirty = true;
@@ -351,16 +1291,20 @@ impl vte::Perform for TerminalState {
             }
             // DCH - Delete Characters
             'P' => {
-                let n = p(0, 1) as usize;
                 let row = self.cursor.row;
                 let col = self.cursor.col as usize;
                 let cols = self.grid.cols as usize;
-                let row_cells = self.grid.row_mut(row);
-                for i in col..(cols - n).max(col) {
-                    row_cells[i] = row_cells[i + n].clone();
+                let n = (p(0, 1) as usize).min(cols.saturating_sub(col));
+                let line = self.grid.line_mut(row);
+                // Wide characters cut by either end of the deleted span
+                // lose both halves.
+                line.split_wide_at(col);
+                line.split_wide_at(col + n);
+                for i in col..cols - n {
+                    line[i] = line[i + n].clone();
                 }
-                for i in (cols - n)..cols {
-                    row_cells[i] = Cell::default();
+                for i in (cols - n).max(col)..cols {
+                    line[i] = GridCell::default();
                 }
                 self.dirty = true;
             }
@@ -368,7 +1312,7 @@ impl vte::Perform for TerminalState {
             'S' => {
                 let n = p(0, 1);
                 for _ in 0..n {
-                    self.grid.scroll_up(self.scroll_top, self.scroll_bottom);
+                    self.scroll_up();
                 }
                 self.dirty = true;
             }
@@ -386,90 +1330,37 @@ impl vte::Perform for TerminalState {
                 let row = self.cursor.row;
                 let col = self.cursor.col as usize;
                 let cols = self.grid.cols as usize;
-                let row_cells = self.grid.row_mut(row);
+                let line = self.grid.line_mut(row);
+                line.split_wide_at(col);
                 for i in (col + n..cols).rev() {
-                    row_cells[i] = row_cells[i - n].clone();
+                    line[i] = line[i - n].clone();
                 }
                 for i in col..((col + n).min(cols)) {
-                    row_cells[i] = Cell::default();
+                    line[i] = GridCell::default();
+                }
+                // A wide character pushed into the last column loses the
+                // half that fell off the edge.
+                if line.last().is_some_and(|cell| cell.width == 2) {
+                    line.split_wide_at(cols - 1);
                 }
                 self.dirty = true;
             }
             // ECH - Erase Characters
             'X' => {
                 let n = p(0, 1);
-                for i in 0..n {
-                    let col = self.cursor.col + i;
-                    if col < self.grid.cols {
-                        self.grid.set_cell(col, self.cursor.row, Cell::default());
-                    }
+                let end = self.cursor.col.saturating_add(n).min(self.grid.cols);
+                if self.cursor.col < end && self.cursor.row < self.grid.rows {
+                    let line = self.grid.line_mut(self.cursor.row);
+                    line.split_wide_at(self.cursor.col as usize);
+                    line.split_wide_at(end as usize - 1);
                 }
-                self.dirty = true;
-            }
-            // SGR - Select Graphic Rendition
-            'm' => {
-                if params.is_empty() {
-                    self.cursor.attrs = Attrs::default();
-                    self.cursor.fg = Color::Default;
-                    self.cursor.bg = Color::Default;
-                } else {
-                    let mut i = 0;
-                    while i < params.len() {
-                        match params[i] {
-                            0 => {
-                                self.cursor.attrs = Attrs::default();
-                                self.cursor.fg = Color::Default;
-                                self.cursor.bg = Color::Default;
-                            }
-                            1 => self.cursor.attrs.bold = true,
-                            3 => self.cursor.attrs.italic = true,
-                            4 => self.cursor.attrs.underline = true,
-                            5 => self.cursor.attrs.blink = true,
-                            7 => self.cursor.attrs.reverse = true,
-                            8 => self.cursor.attrs.hidden = true,
-                            9 => self.cursor.attrs.strikethrough = true,
-                            22 => self.cursor.attrs.bold = false,
-                            23 => self.cursor.attrs.italic = false,
-                            24 => self.cursor.attrs.underline = false,
-                            25 => self.cursor.attrs.blink = false,
-                            27 => self.cursor.attrs.reverse = false,
-                            28 => self.cursor.attrs.hidden = false,
-                            29 => self.cursor.attrs.strikethrough = false,
-                            30..=37 => {
-                                self.cursor.fg = Color::Indexed(params[i] as u8 - 30);
-                            }
-                            38 => {
-                                i += 1;
-                                if let Some(color) = self.parse_color_from_params(&params, &mut i) {
-                                    self.cursor.fg = color;
-                                }
-                                continue;
-                            }
-                            39 => self.cursor.fg = Color::Default,
-                            40..=47 => {
-                                self.cursor.bg = Color::Indexed(params[i] as u8 - 40);
-                            }
-                            48 => {
-                                i += 1;
-                                if let Some(color) = self.parse_color_from_params(&params, &mut i) {
-                                    self.cursor.bg = color;
-                                }
-                                continue;
-                            }
-                            49 => self.cursor.bg = Color::Default,
-                            90..=97 => {
-                                self.cursor.fg = Color::Indexed(params[i] as u8 - 90 + 8);
-                            }
-                            100..=107 => {
-                                self.cursor.bg = Color::Indexed(params[i] as u8 - 100 + 8);
-                            }
-                            _ => {}
-                        }
-                        i += 1;
-                    }
+                for col in self.cursor.col..end {
+                    self.grid.set_grid_cell(col, self.cursor.row, GridCell::default());
                 }
                 self.dirty = true;
             }
+            // SGR - Select Graphic Rendition
+            'm' if intermediates.is_empty() => self.select_graphic_rendition(raw_params),
             // DECSTBM - Set Scrolling Region
             'r' => {
                 let top = p(0, 1).saturating_sub(1);
@@ -477,37 +1368,40 @@ impl vte::Perform for TerminalState {
                 self.scroll_top = top;
                 self.scroll_bottom = bottom;
                 self.cursor.col = 0;
-                self.cursor.row = 0;
+                self.cursor.row = if self.modes.origin { top } else { 0 };
                 self.dirty = true;
             }
             // DECSC - Save Cursor
-            's' => {
-                self.saved_cursor = Some((
-                    self.cursor.col,
-                    self.cursor.row,
-                    self.cursor.attrs,
-                    self.cursor.fg,
-                    self.cursor.bg,
-                ));
-            }
+            's' => self.save_cursor(),
             // DECRC - Restore Cursor
-            'u' => {
-                if let Some((col, row, attrs, fg, bg)) = self.saved_cursor {
-                    self.cursor.col = col;
-                    self.cursor.row = row;
-                    self.cursor.attrs = attrs;
-                    self.cursor.fg = fg;
-                    self.cursor.bg = bg;
+            'u' if intermediates.is_empty() => self.restore_cursor(),
+            // Kitty keyboard protocol: push, pop, modify and query flags
+            'u' => match intermediates {
+                b">" => self.keyboard_flags_mut().push(p(0, 0) as u8),
+                b"<" => self.keyboard_flags_mut().pop(p(0, 1) as usize),
+                b"=" => {
+                    let flags = params.first().copied().unwrap_or(0) as u8;
+                    self.keyboard_flags_mut().modify(flags, p(1, 1));
                 }
-                self.dirty = true;
-            }
-            // Hide/Show cursor
+                b"?" => {
+                    let report = format!("\x1b[?{}u", self.keyboard_flags());
+                    self.outgoing.extend_from_slice(report.as_bytes());
+                }
+                _ => trace!("Unhandled CSI u: {:?} {:?}", params, intermediates),
+            },
+            // DECSET/DECRST - DEC private modes
             'h' | 'l' => {
                 if intermediates == b"?" {
                     let mode_set = action == 'h';
                     for &param in &params {
                         match param {
                             25 => self.cursor.visible = mode_set,
+                            // DECOM also homes the cursor
+                            6 => {
+                                self.modes.origin = mode_set;
+                                self.cursor.col = 0;
+                                self.cursor.row = if mode_set { self.scroll_top } else { 0 };
+                            }
                             // Alt screen buffer
                             1049 => {
                                 if mode_set {
@@ -516,15 +1410,80 @@ impl vte::Perform for TerminalState {
                                     self.exit_alt_screen();
                                 }
                             }
-                            _ => {}
+                            _ => {
+                                if !self.modes.set_private(param, mode_set) {
+                                    trace!("Unhandled DEC private mode: {}", param);
+                                }
+                            }
                         }
                     }
                     self.dirty = true;
                 }
             }
-            // Device Status Report
-            'n' => {
-                // We handle DSR responses in the server
+            // CHT - Cursor Forward Tabulation
+            'I' => {
+                self.tab_forward(p(0, 1));
+                self.dirty = true;
+            }
+            // CBT - Cursor Backward Tabulation
+            'Z' => {
+                self.tab_backward(p(0, 1));
+                self.dirty = true;
+            }
+            // TBC - Tab Clear
+            'g' => match p(0, 0) {
+                0 => {
+                    if let Some(stop) = self.tab_stops.get_mut(self.cursor.col as usize) {
+                        *stop = false;
+                    }
+                }
+                3 => self.tab_stops.fill(false),
+                _ => {}
+            },
+            // DSR - Device Status Report
+            'n' => match (intermediates, p(0, 0)) {
+                // Operating status: OK
+                (b"", 5) => self.outgoing.extend_from_slice(b"\x1b[0n"),
+                // Cursor position report
+                (b"" | b"?", 6) => {
+                    let row = if self.modes.origin {
+                        self.cursor.row.saturating_sub(self.scroll_top)
+                    } else {
+                        self.cursor.row
+                    };
+                    let prefix = if intermediates == b"?" { "?" } else { "" };
+                    let report =
+                        format!("\x1b[{}{};{}R", prefix, row + 1, self.cursor.col + 1);
+                    self.outgoing.extend_from_slice(report.as_bytes());
+                }
+                _ => trace!("Unhandled DSR: {:?} {:?}", params, intermediates),
+            },
+            // DA - Device Attributes
+            'c' if p(0, 0) == 0 => match intermediates {
+                // Primary: VT220 with ANSI color
+                b"" => self.outgoing.extend_from_slice(b"\x1b[?62;22c"),
+                // Secondary: terminal type, version, ROM cartridge
+                b">" => self.outgoing.extend_from_slice(b"\x1b[>84;0;0c"),
+                _ => {}
+            },
+            // DECSCUSR - Set Cursor Style
+            'q' if intermediates == b" " => match CursorStyle::from_param(p(0, 0)) {
+                Some(style) => {
+                    self.cursor_style = style;
+                    self.dirty = true;
+                }
+                None => trace!("Unhandled cursor style: {:?}", params),
+            },
+            // XTVERSION - report name and version
+            'q' if intermediates == b">" && p(0, 0) == 0 => {
+                let report = format!("\x1bP>|wtmux {}\x1b\\", env!("CARGO_PKG_VERSION"));
+                self.outgoing.extend_from_slice(report.as_bytes());
+            }
+            // DECRQM - Request Mode
+            'p' if intermediates == b"$" || intermediates == b"?$" => {
+                if let Some(&mode) = params.first() {
+                    self.report_mode(mode, intermediates == b"?$");
+                }
             }
             _ => {
                 trace!("Unhandled CSI: {:?} {} {:?}", params, action, intermediates);
@@ -533,28 +1492,34 @@ impl vte::Perform for TerminalState {
     }
 
     fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        if intermediates.is_empty() && matches!(byte, b'D' | b'E' | b'M') {
+            self.cursor.pending_wrap = false;
+        }
         match (intermediates, byte) {
-            // DECSC - Save Cursor
-            (_, b'7') => {
-                self.saved_cursor = Some((
-                    self.cursor.col,
-                    self.cursor.row,
-                    self.cursor.attrs,
-                    self.cursor.fg,
-                    self.cursor.bg,
-                ));
+            // SCS - designate G0-G3
+            ([slot @ (b'(' | b')' | b'*' | b'+')], _) => {
+                if let Some(charset) = Charset::from_designator(byte) {
+                    self.charsets.designate((slot - b'(') as usize, charset);
+                } else {
+                    trace!("Unhandled charset: {:?} 0x{:02x}", intermediates, byte);
+                }
             }
+            // DECSC - Save Cursor
+            (_, b'7') => self.save_cursor(),
             // DECRC - Restore Cursor
-            (_, b'8') => {
-                if let Some((col, row, attrs, fg, bg)) = self.saved_cursor {
-                    self.cursor.col = col;
-                    self.cursor.row = row;
-                    self.cursor.attrs = attrs;
-                    self.cursor.fg = fg;
-                    self.cursor.bg = bg;
-                    self.dirty = true;
+            (_, b'8') => self.restore_cursor(),
+            // HTS - Horizontal Tab Set
+            (_, b'H') => {
+                if let Some(stop) = self.tab_stops.get_mut(self.cursor.col as usize) {
+                    *stop = true;
                 }
             }
+            // SS2/SS3 - single shift G2/G3
+            (_, b'N') => self.charsets.single_shift(2),
+            (_, b'O') => self.charsets.single_shift(3),
+            // LS2/LS3 - invoke G2/G3
+            (_, b'n') => self.charsets.shift(2),
+            (_, b'o') => self.charsets.shift(3),
             // RI - Reverse Index
             (_, b'M') => {
                 if self.cursor.row == self.scroll_top {
@@ -575,6 +1540,9 @@ impl vte::Perform for TerminalState {
                 self.line_feed();
                 self.dirty = true;
             }
+            // DECKPAM/DECKPNM - application/numeric keypad
+            ([], b'=') => self.modes.app_keypad = true,
+            ([], b'>') => self.modes.app_keypad = false,
             _ => {
                 trace!("Unhandled ESC: {:?} 0x{:02x}", intermediates, byte);
             }
@@ -0,0 +1,392 @@
+use crate::cell::GridCell;
+use crate::grid::Grid;
+use crate::row::{GridRow, LineMarks};
+use crate::scrollback::{PackedRow, Scrollback};
+
+/// A line being reflowed: still packed as it came out of the history, or
+/// in grid cells.
+enum Line {
+    Packed(PackedRow),
+    Cells(GridRow),
+}
+
+impl Line {
+    fn wrapped(&self) -> bool {
+        match self {
+            Line::Packed(row) => row.wrapped,
+            Line::Cells(row) => row.wrapped,
+        }
+    }
+
+    fn content_len(&self) -> usize {
+        match self {
+            Line::Packed(row) => row.content_len(),
+            Line::Cells(row) => row.content_len(),
+        }
+    }
+
+    fn starts_wide(&self) -> bool {
+        match self {
+            Line::Packed(row) => row.starts_wide(),
+            Line::Cells(row) => row.first().is_some_and(|c| c.width == 2),
+        }
+    }
+
+    fn marks_mut(&mut self) -> &mut LineMarks {
+        match self {
+            Line::Packed(row) => &mut row.marks,
+            Line::Cells(row) => &mut row.marks,
+        }
+    }
+
+    fn into_cells(self) -> GridRow {
+        match self {
+            Line::Packed(row) => row.unpack(),
+            Line::Cells(row) => row,
+        }
+    }
+
+    fn into_packed(self) -> PackedRow {
+        match self {
+            Line::Packed(row) => row,
+            Line::Cells(row) => PackedRow::pack(&row),
+        }
+    }
+}
+
+/// Rewrap the main screen and its history to a new size.
+///
+/// Soft-wrapped rows are joined back into logical lines and split again at
+/// the new width. Lines that no longer fit on the screen move into the
+/// scrollback; when the resize opens up blank space at the bottom, lines are
+/// pulled back out of it. History lines that fit the new width as they are
+/// stay packed. Returns the new grid and the cursor position, which stays on
+/// the same logical character.
+pub(crate) fn reflow(
+    grid: Grid,
+    scrollback: &mut Scrollback,
+    cursor: (u16, u16),
+    cols: u16,
+    rows: u16,
+) -> (Grid, (u16, u16)) {
+    let old_cols = grid.cols as usize;
+    let old_rows = grid.rows as usize;
+    let history = scrollback.len();
+    let mut lines: Vec<Line> = scrollback.take_packed().into_iter().map(Line::Packed).collect();
+    let styles = grid.styles().clone();
+    lines.extend(grid.into_grid_rows().into_iter().map(Line::Cells));
+
+    // Drop blank rows below both the cursor and the last line with content.
+    let cursor_abs = history + cursor.1 as usize;
+    let last_content = lines
+        .iter()
+        .rposition(|line| line.wrapped() || line.content_len() > 0)
+        .unwrap_or(0);
+    let content_end = cursor_abs.max(last_content);
+    lines.truncate(content_end + 1);
+    let old_blank = old_rows - (content_end + 1 - history);
+
+    let mut out: Vec<Line> = Vec::with_capacity(lines.len());
+    let mut new_cursor = (0, 0);
+    let mut new_top = 0;
+    let mut logical: Vec<GridCell> = Vec::new();
+    let mut marks = LineMarks::default();
+    // Cell indices within the current logical line of the cursor and of the
+    // old top screen row.
+    let mut targets = [None, None];
+
+    let mut lines = lines.into_iter().enumerate().peekable();
+    while let Some((i, line)) = lines.next() {
+        let row = match line {
+            // A history line that is a whole logical line and fits the new
+            // width stays as it is.
+            Line::Packed(mut row)
+                if logical.is_empty() && !row.wrapped && row.content_len() <= cols as usize =>
+            {
+                row.cols = cols;
+                out.push(Line::Packed(row));
+                continue;
+            }
+            line => line.into_cells(),
+        };
+        if i == cursor_abs {
+            targets[0] = Some(logical.len() + cursor.0 as usize);
+        }
+        if i == history {
+            targets[1] = Some(logical.len());
+        }
+
+        let mut row_marks = row.marks;
+        // Where D arrived, counted from the start of the logical line.
+        row_marks.finished_col += logical.len() as u32;
+        marks.merge(row_marks);
+        let next = lines.peek().map(|(_, next)| next);
+        if row.wrapped && next.is_some() {
+            let mut take = old_cols.min(row.cells.len());
+            // A blank in the last column followed by a wide character at the
+            // start of the next row is padding left by the wrap.
+            let next_starts_wide = next.is_some_and(Line::starts_wide);
+            if take > 0 && next_starts_wide && row.cells[take - 1].is_empty() {
+                take -= 1;
+            }
+            logical.extend_from_slice(&row.cells[..take]);
+        } else {
+            logical.extend_from_slice(&row.cells[..row.content_len()]);
+            let first_row = out.len();
+            let [cursor_pos, top_pos] = wrap_line(&logical, targets, cols as usize, &mut out);
+            *out[first_row].marks_mut() = std::mem::take(&mut marks);
+            if let Some(pos) = cursor_pos {
+                new_cursor = pos;
+            }
+            if let Some((_, row)) = top_pos {
+                new_top = row;
+            }
+            logical.clear();
+            targets = [None, None];
+        }
+    }
+
+    if out.is_empty() {
+        out.push(Line::Cells(GridRow::default()));
+    }
+
+    // Keep the old top row at the top of the screen, but pull lines back
+    // from history to fill blank space the resize opened up at the bottom,
+    // and keep the cursor on screen.
+    let total = out.len();
+    let mut start = new_top.min(total - 1);
+    let new_blank = (rows as usize).saturating_sub(total - start);
+    if new_blank > old_blank {
+        start = start.saturating_sub(new_blank - old_blank);
+    }
+    if total - start > rows as usize {
+        start = (total - rows as usize).min(new_cursor.1);
+        out.truncate(start + rows as usize);
+    }
+
+    let screen = out.split_off(start).into_iter().map(Line::into_cells).collect();
+    for line in out {
+        scrollback.push_packed(line.into_packed());
+    }
+
+    let cursor_col = (new_cursor.0 as u16).min(cols - 1);
+    let cursor_row = (new_cursor.1 - start) as u16;
+    (
+        Grid::from_grid_rows(cols, rows, screen, styles),
+        (cursor_col, cursor_row),
+    )
+}
+
+/// Split one logical line into rows of `cols` cells, appending them to
+/// `out`. Returns the (col, absolute row) each target cell index ended up
+/// at. The first target is the cursor: rows are added as needed so that it
+/// lands on a real row even past the end of the line's content.
+fn wrap_line(
+    cells: &[GridCell],
+    targets: [Option<usize>; 2],
+    cols: usize,
+    out: &mut Vec<Line>,
+) -> [Option<(usize, usize)>; 2] {
+    let mut current: Vec<GridCell> = Vec::with_capacity(cols);
+    let mut found = [None, None];
+    let mut i = 0;
+
+    while i < cells.len() {
+        let cell = &cells[i];
+        let width = if cell.width == 2 && cols >= 2 { 2 } else { 1 };
+
+        if current.len() + width > cols {
+            // Pad out the row if a wide character does not fit.
+            current.resize(cols, GridCell::default());
+            push_row(out, &mut current, true);
+        }
+
+        for (target, pos) in targets.iter().zip(found.iter_mut()) {
+            if *target == Some(i) || (width == 2 && *target == Some(i + 1)) {
+                *pos = Some((current.len(), out.len()));
+            }
+        }
+
+        if width == 2 {
+            current.push(cell.clone());
+            // Keep the continuation cell paired with its wide character.
+            match cells.get(i + 1) {
+                Some(next) if next.width == 0 => {
+                    current.push(next.clone());
+                    i += 1;
+                }
+                _ => current.push(GridCell::new(' ', 0, cell.style)),
+            }
+        } else if cell.width != 1 {
+            // Orphaned continuation, or a wide character that cannot fit.
+            current.push(GridCell::default());
+        } else {
+            current.push(cell.clone());
+        }
+        i += 1;
+    }
+
+    // Targets past the end of the line's content.
+    for (target, pos) in targets.iter().zip(found.iter_mut()) {
+        if let Some(idx) = *target {
+            if idx >= cells.len() {
+                let offset = current.len() + (idx - cells.len());
+                *pos = Some((offset % cols, out.len() + offset / cols));
+            }
+        }
+    }
+    if let Some((_, row)) = found[0] {
+        while out.len() < row {
+            current.resize(cols, GridCell::default());
+            push_row(out, &mut current, true);
+        }
+    }
+
+    push_row(out, &mut current, false);
+    found
+}
+
+fn push_row(out: &mut Vec<Line>, cells: &mut Vec<GridCell>, wrapped: bool) {
+    out.push(Line::Cells(GridRow {
+        cells: std::mem::take(cells),
+        wrapped,
+        marks: LineMarks::default(),
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Terminal;
+
+    fn row_text(term: &Terminal, row: u16) -> String {
+        term.state.grid.row_text(row)
+    }
+
+    #[test]
+    fn test_widen_joins_wrapped_line() {
+        let mut term = Terminal::new(10, 4);
+        term.process_bytes(b"abcdefghijklmno");
+        assert!(term.state.grid.is_wrapped(0));
+        term.resize(20, 4);
+        assert_eq!(row_text(&term, 0), "abcdefghijklmno");
+        assert_eq!(row_text(&term, 1), "");
+        assert!(!term.state.grid.is_wrapped(0));
+        assert_eq!(term.cursor_pos(), (15, 0));
+    }
+
+    #[test]
+    fn test_narrow_rewraps_instead_of_truncating() {
+        let mut term = Terminal::new(20, 4);
+        term.process_bytes(b"hello world");
+        term.resize(5, 4);
+        assert_eq!(row_text(&term, 0), "hello");
+        assert_eq!(row_text(&term, 1), " worl");
+        assert_eq!(row_text(&term, 2), "d");
+        assert!(term.state.grid.is_wrapped(0));
+        assert!(term.state.grid.is_wrapped(1));
+        assert!(!term.state.grid.is_wrapped(2));
+        assert_eq!(term.cursor_pos(), (1, 2));
+
+        term.resize(20, 4);
+        assert_eq!(row_text(&term, 0), "hello world");
+        assert_eq!(term.cursor_pos(), (11, 0));
+    }
+
+    #[test]
+    fn test_hard_newlines_are_not_joined() {
+        let mut term = Terminal::new(10, 4);
+        term.process_bytes(b"abc\r\ndef");
+        term.resize(20, 4);
+        assert_eq!(row_text(&term, 0), "abc");
+        assert_eq!(row_text(&term, 1), "def");
+        assert_eq!(term.cursor_pos(), (3, 1));
+    }
+
+    #[test]
+    fn test_reflow_moves_lines_through_history() {
+        let mut term = Terminal::new(10, 4);
+        term.process_bytes(b"1\r\n2\r\n0123456789abcde");
+        assert_eq!(term.state.history_len(), 0);
+
+        // Narrowing adds rows; the top lines move into history.
+        term.resize(5, 4);
+        assert_eq!(term.state.history_len(), 2);
+        assert_eq!(row_text(&term, 0), "01234");
+        assert_eq!(row_text(&term, 2), "abcde");
+        assert_eq!(term.cursor_pos(), (0, 3));
+
+        // Widening again pulls them back onto the screen.
+        term.resize(10, 4);
+        assert_eq!(term.state.history_len(), 0);
+        assert_eq!(row_text(&term, 0), "1");
+        assert_eq!(row_text(&term, 1), "2");
+        assert_eq!(row_text(&term, 2), "0123456789");
+        assert_eq!(row_text(&term, 3), "abcde");
+        assert_eq!(term.cursor_pos(), (5, 3));
+    }
+
+    #[test]
+    fn test_resize_does_not_pull_history_onto_cleared_screen() {
+        let mut term = Terminal::new(10, 2);
+        term.process_bytes(b"a\r\nb\r\nc\x1b[2J\x1b[H$ ");
+        assert_eq!(term.state.history_len(), 1);
+        term.resize(8, 2);
+        assert_eq!(term.state.history_len(), 1);
+        assert_eq!(row_text(&term, 0), "$");
+        assert_eq!(term.cursor_pos(), (2, 0));
+    }
+
+    #[test]
+    fn test_cursor_past_content_is_kept() {
+        let mut term = Terminal::new(10, 4);
+        term.process_bytes(b"prompt$ ");
+        term.resize(4, 4);
+        assert_eq!(row_text(&term, 0), "prom");
+        assert_eq!(row_text(&term, 1), "pt$");
+        assert_eq!(term.cursor_pos(), (0, 2));
+    }
+
+    #[test]
+    fn test_cjk_wide_chars_split_at_new_width() {
+        let mut term = Terminal::new(6, 4);
+        term.process_bytes("あいう".as_bytes());
+        assert_eq!(row_text(&term, 0), "あいう");
+
+        // "う" no longer fits after "あい" plus one column of padding.
+        term.resize(5, 4);
+        let grid = &term.state.grid;
+        assert_eq!(grid.cell(0, 0).ch, 'あ');
+        assert_eq!(grid.cell(1, 0).width, 0);
+        assert_eq!(grid.cell(2, 0).ch, 'い');
+        assert_eq!(grid.cell(3, 0).width, 0);
+        assert_eq!(grid.cell(4, 0).ch, ' ');
+        assert_eq!(grid.cell(4, 0).width, 1);
+        assert!(grid.is_wrapped(0));
+        assert_eq!(grid.cell(0, 1).ch, 'う');
+        assert_eq!(grid.cell(1, 1).width, 0);
+
+        // Widening drops the padding and rejoins the line.
+        term.resize(6, 4);
+        assert_eq!(row_text(&term, 0), "あいう");
+        assert_eq!(row_text(&term, 1), "");
+        assert_eq!(term.cursor_pos(), (0, 1));
+    }
+
+    #[test]
+    fn test_cjk_wide_char_never_split_in_half() {
+        let mut term = Terminal::new(8, 4);
+        term.process_bytes("aあいう".as_bytes());
+        term.resize(4, 4);
+        assert_eq!(row_text(&term, 0), "aあ");
+        assert_eq!(row_text(&term, 1), "いう");
+        for row in 0..2 {
+            for col in 0..4 {
+                let cell = term.state.grid.cell(col, row);
+                if cell.width == 0 {
+                    assert_eq!(term.state.grid.cell(col - 1, row).width, 2);
+                }
+            }
+        }
+        assert_eq!(term.cursor_pos(), (0, 2));
+    }
+}
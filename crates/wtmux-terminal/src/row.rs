@@ -0,0 +1,223 @@
+use crate::cell::{Cell, GridCell};
+use crate::style::StyleTable;
+use std::ops::{Deref, DerefMut};
+
+/// Shell integration marks (OSC 133) recorded on a line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineMarks {
+    /// `A`: a prompt starts on this line.
+    pub prompt: bool,
+    /// `B`: the command line typed by the user starts on this line.
+    pub command: bool,
+    /// `C`: the command's output starts on this line.
+    pub output: bool,
+    /// `D`: a command finished on this line.
+    pub finished: bool,
+    /// Exit status reported with `D`, if any.
+    pub exit_status: Option<i32>,
+    /// Cells before the point where `D` arrived, counted from the start of
+    /// this line and on across its soft wraps.
+    pub finished_col: u32,
+}
+
+impl LineMarks {
+    pub fn is_empty(&self) -> bool {
+        *self == LineMarks::default()
+    }
+
+    /// Combine the marks of two rows that became one.
+    pub fn merge(&mut self, other: LineMarks) {
+        self.prompt |= other.prompt;
+        self.command |= other.command;
+        self.output |= other.output;
+        self.finished |= other.finished;
+        if other.finished {
+            self.finished_col = other.finished_col;
+        }
+        if other.exit_status.is_some() {
+            self.exit_status = other.exit_status;
+        }
+    }
+}
+
+/// A single line of cells plus per-line metadata.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Row {
+    pub cells: Vec<Cell>,
+    /// Whether the line soft-wrapped into the next one (as opposed to ending
+    /// with a real newline).
+    pub wrapped: bool,
+    pub marks: LineMarks,
+}
+
+impl Row {
+    /// Create a blank row of the given width.
+    pub fn new(cols: u16) -> Self {
+        Row {
+            cells: vec![Cell::default(); cols as usize],
+            wrapped: false,
+            marks: LineMarks::default(),
+        }
+    }
+
+    /// Reset every cell to blank and clear the line metadata.
+    pub fn clear(&mut self) {
+        for cell in &mut self.cells {
+            *cell = Cell::default();
+        }
+        self.wrapped = false;
+        self.marks = LineMarks::default();
+    }
+
+    /// The line's text with trailing spaces removed.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for cell in self.cells.iter().filter(|c| c.width > 0) {
+            cell.push_grapheme(&mut text);
+        }
+        text.truncate(text.trim_end().len());
+        text
+    }
+
+    /// Number of cells up to and including the last non-blank one.
+    pub fn content_len(&self) -> usize {
+        self.cells
+            .iter()
+            .rposition(|c| !c.is_empty() || c.width != 1)
+            .map_or(0, |pos| pos + 1)
+    }
+
+    /// If `col` holds either half of a wide character, blank both halves so
+    /// that overwriting or erasing one of them leaves no orphan behind.
+    pub fn split_wide_at(&mut self, col: usize) {
+        let start = match self.cells.get(col) {
+            Some(cell) if cell.width == 2 => col,
+            Some(cell) if cell.width == 0 && col > 0 && self.cells[col - 1].width == 2 => col - 1,
+            _ => return,
+        };
+        self.cells[start] = Cell::default();
+        if let Some(cell) = self.cells.get_mut(start + 1).filter(|c| c.width == 0) {
+            *cell = Cell::default();
+        }
+    }
+}
+
+impl From<Vec<Cell>> for Row {
+    fn from(cells: Vec<Cell>) -> Self {
+        Row {
+            cells,
+            wrapped: false,
+            marks: LineMarks::default(),
+        }
+    }
+}
+
+impl Deref for Row {
+    type Target = Vec<Cell>;
+
+    fn deref(&self) -> &Vec<Cell> {
+        &self.cells
+    }
+}
+
+impl DerefMut for Row {
+    fn deref_mut(&mut self) -> &mut Vec<Cell> {
+        &mut self.cells
+    }
+}
+
+/// A row as the grid stores it, with compact cells whose styles are kept in
+/// the terminal's style table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GridRow {
+    pub cells: Vec<GridCell>,
+    /// Whether the line soft-wrapped into the next one.
+    pub wrapped: bool,
+    pub marks: LineMarks,
+}
+
+impl GridRow {
+    /// Create a blank row of the given width.
+    pub fn new(cols: u16) -> Self {
+        GridRow {
+            cells: vec![GridCell::default(); cols as usize],
+            wrapped: false,
+            marks: LineMarks::default(),
+        }
+    }
+
+    /// Store `row`, adding the styles of its cells to `styles`.
+    pub fn from_row(row: &Row, styles: &mut StyleTable) -> Self {
+        GridRow {
+            cells: row
+                .cells
+                .iter()
+                .map(|cell| GridCell::from_cell(cell, styles))
+                .collect(),
+            wrapped: row.wrapped,
+            marks: row.marks,
+        }
+    }
+
+    /// The row with full cells, their styles looked up in `styles`.
+    pub fn to_row(&self, styles: &StyleTable) -> Row {
+        Row {
+            cells: self.cells.iter().map(|cell| cell.to_cell(styles)).collect(),
+            wrapped: self.wrapped,
+            marks: self.marks,
+        }
+    }
+
+    /// Reset every cell to blank and clear the line metadata.
+    pub fn clear(&mut self) {
+        self.cells.fill(GridCell::default());
+        self.wrapped = false;
+        self.marks = LineMarks::default();
+    }
+
+    /// The line's text with trailing spaces removed.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for cell in self.cells.iter().filter(|c| c.width > 0) {
+            cell.push_grapheme(&mut text);
+        }
+        text.truncate(text.trim_end().len());
+        text
+    }
+
+    /// Number of cells up to and including the last non-blank one.
+    pub fn content_len(&self) -> usize {
+        self.cells
+            .iter()
+            .rposition(|c| !c.is_empty() || c.width != 1)
+            .map_or(0, |pos| pos + 1)
+    }
+
+    /// If `col` holds either half of a wide character, blank both halves so
+    /// that overwriting or erasing one of them leaves no orphan behind.
+    pub fn split_wide_at(&mut self, col: usize) {
+        let start = match self.cells.get(col) {
+            Some(cell) if cell.width == 2 => col,
+            Some(cell) if cell.width == 0 && col > 0 && self.cells[col - 1].width == 2 => col - 1,
+            _ => return,
+        };
+        self.cells[start] = GridCell::default();
+        if let Some(cell) = self.cells.get_mut(start + 1).filter(|c| c.width == 0) {
+            *cell = GridCell::default();
+        }
+    }
+}
+
+impl Deref for GridRow {
+    type Target = Vec<GridCell>;
+
+    fn deref(&self) -> &Vec<GridCell> {
+        &self.cells
+    }
+}
+
+impl DerefMut for GridRow {
+    fn deref_mut(&mut self) -> &mut Vec<GridCell> {
+        &mut self.cells
+    }
+}
@@ -1,22 +1,50 @@
-use crate::cell::Cell;
+use crate::cell::GridCell;
+use crate::row::{GridRow, LineMarks, Row};
+use crate::style::SharedStyles;
 use std::collections::VecDeque;
 
 /// Ring buffer for scrollback history.
+///
+/// Lines are stored packed: trailing blanks are dropped, runs of plain
+/// ASCII characters are kept as bytes, and each run keeps the one style id
+/// its cells share in a style table shared with the rest of the terminal.
+/// Reading a line unpacks it into full cells.
 pub struct Scrollback {
-    lines: VecDeque<Vec<Cell>>,
+    lines: VecDeque<PackedRow>,
+    styles: SharedStyles,
     max_lines: usize,
 }
 
 impl Scrollback {
     pub fn new(max_lines: usize) -> Self {
+        Self::with_styles(max_lines, SharedStyles::new())
+    }
+
+    /// Create a scrollback whose lines keep their styles in `styles`.
+    pub(crate) fn with_styles(max_lines: usize, styles: SharedStyles) -> Self {
         Scrollback {
             lines: VecDeque::new(),
+            styles,
             max_lines,
         }
     }
 
     /// Push a line into the scrollback buffer.
-    pub fn push_line(&mut self, line: Vec<Cell>) {
+    pub fn push_line(&mut self, line: impl Into<Row>) {
+        let line = GridRow::from_row(&line.into(), &mut self.styles.write());
+        self.push_grid_line(&line);
+    }
+
+    /// Push a line stored with the scrollback's style table.
+    pub(crate) fn push_grid_line(&mut self, line: &GridRow) {
+        self.push_packed(PackedRow::pack(line));
+    }
+
+    /// Push a line that is packed already.
+    pub(crate) fn push_packed(&mut self, line: PackedRow) {
+        if self.max_lines == 0 {
+            return;
+        }
         if self.lines.len() >= self.max_lines {
             self.lines.pop_front();
         }
@@ -24,7 +52,24 @@ impl Scrollback {
     }
 
     /// Get a line from the scrollback (0 = most recent).
-    pub fn get_line(&self, offset: usize) -> Option<&Vec<Cell>> {
+    pub fn get_line(&self, offset: usize) -> Option<Row> {
+        let line = self.packed_line(offset)?.unpack();
+        Some(line.to_row(&self.styles.read()))
+    }
+
+    /// Shell integration marks of a line (0 = most recent), without
+    /// unpacking its cells.
+    pub fn get_marks(&self, offset: usize) -> Option<LineMarks> {
+        self.packed_line(offset).map(|line| line.marks)
+    }
+
+    /// Whether a line (0 = most recent) soft-wrapped into the next one,
+    /// without unpacking its cells.
+    pub fn get_wrapped(&self, offset: usize) -> Option<bool> {
+        self.packed_line(offset).map(|line| line.wrapped)
+    }
+
+    fn packed_line(&self, offset: usize) -> Option<&PackedRow> {
         if offset < self.lines.len() {
             self.lines.get(self.lines.len() - 1 - offset)
         } else {
@@ -32,6 +77,11 @@ impl Scrollback {
         }
     }
 
+    /// Maximum number of lines kept before the oldest are dropped.
+    pub fn max_lines(&self) -> usize {
+        self.max_lines
+    }
+
     /// Total number of lines in the scrollback.
     pub fn len(&self) -> usize {
         self.lines.len()
@@ -48,14 +98,175 @@ impl Scrollback {
     }
 
     /// Iterate over all lines (oldest first).
-    pub fn iter(&self) -> impl Iterator<Item = &Vec<Cell>> {
-        self.lines.iter()
+    pub fn iter(&self) -> impl Iterator<Item = Row> + '_ {
+        let styles = self.styles.read();
+        self.lines.iter().map(move |line| line.unpack().to_row(&styles))
+    }
+
+    /// Ids of the styles the stored lines use, with repeats.
+    pub(crate) fn style_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.lines
+            .iter()
+            .flat_map(|line| line.runs.iter().map(Run::style))
+    }
+
+    /// Remove and return all lines (oldest first), still packed.
+    pub(crate) fn take_packed(&mut self) -> Vec<PackedRow> {
+        std::mem::take(&mut self.lines).into()
+    }
+}
+
+/// A history line in packed form.
+#[derive(Debug)]
+pub(crate) struct PackedRow {
+    runs: Box<[Run]>,
+    /// Width of the line; cells past the last run are blank.
+    pub(crate) cols: u16,
+    pub(crate) wrapped: bool,
+    pub(crate) marks: LineMarks,
+}
+
+/// Cells of a packed line sharing one style.
+#[derive(Debug)]
+enum Run {
+    /// Width-1 ASCII characters without combining marks, one byte each.
+    Ascii { style: u32, text: Box<[u8]> },
+    /// A wide character and the continuation cell after it.
+    Wide {
+        style: u32,
+        ch: char,
+        combining: Option<Box<str>>,
+    },
+    /// A single cell of any other kind.
+    Cell {
+        style: u32,
+        ch: char,
+        width: u8,
+        combining: Option<Box<str>>,
+    },
+}
+
+impl Run {
+    fn style(&self) -> u32 {
+        match *self {
+            Run::Ascii { style, .. } | Run::Wide { style, .. } | Run::Cell { style, .. } => style,
+        }
+    }
+
+    /// Number of cells the run covers.
+    fn len(&self) -> usize {
+        match self {
+            Run::Ascii { text, .. } => text.len(),
+            Run::Wide { .. } => 2,
+            Run::Cell { .. } => 1,
+        }
+    }
+}
+
+impl PackedRow {
+    pub(crate) fn pack(row: &GridRow) -> Self {
+        let mut runs = Vec::new();
+        let mut ascii: Option<(u32, Vec<u8>)> = None;
+        let mut cells = row.cells[..row.content_len()].iter().peekable();
+        while let Some(cell) = cells.next() {
+            let style = cell.style;
+            if cell.ch.is_ascii() && cell.width == 1 && cell.combining().is_none() {
+                match &mut ascii {
+                    Some((run_style, text)) if *run_style == style => text.push(cell.ch as u8),
+                    _ => {
+                        if let Some((style, text)) = ascii.take() {
+                            runs.push(Run::Ascii { style, text: text.into() });
+                        }
+                        ascii = Some((style, vec![cell.ch as u8]));
+                    }
+                }
+                continue;
+            }
+            if let Some((style, text)) = ascii.take() {
+                runs.push(Run::Ascii { style, text: text.into() });
+            }
+            let combining = cell.combining().map(Box::from);
+            let continuation = GridCell::new(' ', 0, style);
+            if cell.width == 2 && cells.next_if_eq(&&continuation).is_some() {
+                runs.push(Run::Wide {
+                    style,
+                    ch: cell.ch,
+                    combining,
+                });
+                continue;
+            }
+            runs.push(Run::Cell {
+                style,
+                ch: cell.ch,
+                width: cell.width,
+                combining,
+            });
+        }
+        if let Some((style, text)) = ascii {
+            runs.push(Run::Ascii { style, text: text.into() });
+        }
+
+        PackedRow {
+            runs: runs.into(),
+            cols: row.cells.len() as u16,
+            wrapped: row.wrapped,
+            marks: row.marks,
+        }
+    }
+
+    pub(crate) fn unpack(&self) -> GridRow {
+        let mut cells = Vec::with_capacity(self.cols as usize);
+        for run in self.runs.iter() {
+            match run {
+                Run::Ascii { style, text } => {
+                    cells.extend(text.iter().map(|&byte| GridCell::new(byte as char, 1, *style)));
+                }
+                Run::Wide {
+                    style,
+                    ch,
+                    combining,
+                } => {
+                    let mut cell = GridCell::new(*ch, 2, *style);
+                    cell.set_combining(combining.clone());
+                    cells.push(cell);
+                    cells.push(GridCell::new(' ', 0, *style));
+                }
+                Run::Cell {
+                    style,
+                    ch,
+                    width,
+                    combining,
+                } => {
+                    let mut cell = GridCell::new(*ch, *width, *style);
+                    cell.set_combining(combining.clone());
+                    cells.push(cell);
+                }
+            }
+        }
+        cells.resize(self.cols as usize, GridCell::default());
+
+        GridRow {
+            cells,
+            wrapped: self.wrapped,
+            marks: self.marks,
+        }
+    }
+
+    /// Number of cells up to and including the last non-blank one.
+    pub(crate) fn content_len(&self) -> usize {
+        self.runs.iter().map(Run::len).sum()
+    }
+
+    /// Whether the line starts with a wide character.
+    pub(crate) fn starts_wide(&self) -> bool {
+        matches!(self.runs.first(), Some(Run::Wide { .. }))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cell::{AttrFlags, Cell, Color};
 
     #[test]
     fn test_scrollback_push_and_get() {
@@ -76,4 +287,35 @@ mod tests {
         assert_eq!(sb.get_line(0).unwrap()[0].ch, 'C');
         assert_eq!(sb.get_line(1).unwrap()[0].ch, 'B');
     }
+
+    #[test]
+    fn test_packed_lines_round_trip() {
+        let mut row = Row::new(12);
+        for (col, ch) in "ab 日 xyz".chars().enumerate() {
+            row[col] = Cell::new(ch);
+        }
+        row[3].width = 2;
+        row[4] = Cell { width: 0, ..Cell::default() };
+        row[5].fg = Color::Rgb(1, 2, 3);
+        row[6].attrs.flags = AttrFlags::BOLD;
+        row[7].push_combining('\u{301}');
+        row[8].hyperlink = 1;
+        row[10].bg = Color::Indexed(4);
+        row.wrapped = true;
+        row.marks.prompt = true;
+
+        let mut sb = Scrollback::new(10);
+        sb.push_line(row.clone());
+        sb.push_line(Row::new(12));
+        assert_eq!(sb.get_line(1), Some(row));
+        assert_eq!(sb.get_line(0), Some(Row::new(12)));
+        assert_eq!(sb.get_marks(1).map(|marks| marks.prompt), Some(true));
+    }
+
+    #[test]
+    fn test_scrollback_zero_limit() {
+        let mut sb = Scrollback::new(0);
+        sb.push_line(vec![Cell::new('A')]);
+        assert!(sb.is_empty());
+    }
 }
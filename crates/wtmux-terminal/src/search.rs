@@ -0,0 +1,290 @@
+use crate::parser::TerminalState;
+use regex::{Regex, RegexBuilder};
+use regex_syntax::ast::{self, Ast, ClassSetItem, LiteralKind, Visitor};
+
+/// A cell in the combined history + visible screen view (see
+/// [`TerminalState::line`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Point {
+    pub line: usize,
+    pub col: u16,
+}
+
+/// Where a match was found: the cells of its first and last characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub start: Point,
+    pub end: Point,
+}
+
+/// A compiled search over a terminal's history and screen.
+///
+/// Searches are smart-case: they ignore case unless the pattern contains an
+/// uppercase letter of its own (escapes like `\S` and class names like
+/// `\p{Lu}` don't count). Rows that soft-wrapped are searched as one line, so a
+/// match may start on one row and end on the next.
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    regex: Regex,
+}
+
+impl SearchQuery {
+    /// Compile a search for `pattern`, as a regular expression if `regex` is
+    /// set and as plain text otherwise.
+    pub fn new(pattern: &str, regex: bool) -> Result<Self, regex::Error> {
+        let (source, uppercase) = if regex {
+            (pattern.to_string(), has_uppercase_literal(pattern))
+        } else {
+            (regex::escape(pattern), pattern.chars().any(char::is_uppercase))
+        };
+        let regex = RegexBuilder::new(&source)
+            .case_insensitive(!uppercase)
+            .build()?;
+        Ok(SearchQuery { regex })
+    }
+
+    /// Find the next match starting after `from` (or, searching backward,
+    /// the closest one starting before it). With `wrap`, the search goes on
+    /// from the other end of the history and screen when it reaches one.
+    pub fn find(
+        &self,
+        state: &TerminalState,
+        from: Point,
+        forward: bool,
+        wrap: bool,
+    ) -> Option<SearchMatch> {
+        let total = state.total_lines();
+        if total == 0 {
+            return None;
+        }
+        let first = logical_start(state, from.line.min(total - 1));
+
+        if forward {
+            let mut line = first;
+            while line < total {
+                let text = LogicalLine::read(state, line);
+                if let Some(found) = self.matches(&text).find(|m| m.start > from) {
+                    return Some(found);
+                }
+                line = text.end + 1;
+            }
+            if wrap {
+                let mut line = 0;
+                while line <= first {
+                    let text = LogicalLine::read(state, line);
+                    if let Some(found) = self.matches(&text).next() {
+                        return Some(found);
+                    }
+                    line = text.end + 1;
+                }
+            }
+        } else {
+            let mut line = Some(first);
+            while let Some(start) = line {
+                let text = LogicalLine::read(state, start);
+                if let Some(found) = self.matches(&text).filter(|m| m.start < from).last() {
+                    return Some(found);
+                }
+                line = start.checked_sub(1).map(|end| logical_start(state, end));
+            }
+            if wrap {
+                let mut line = Some(logical_start(state, total - 1));
+                while let Some(start) = line.filter(|&start| start >= first) {
+                    let text = LogicalLine::read(state, start);
+                    if let Some(found) = self.matches(&text).last() {
+                        return Some(found);
+                    }
+                    line = start.checked_sub(1).map(|end| logical_start(state, end));
+                }
+            }
+        }
+        None
+    }
+
+    /// Non-empty matches in a logical line, first to last.
+    fn matches<'a>(&'a self, text: &'a LogicalLine) -> impl Iterator<Item = SearchMatch> + 'a {
+        self.regex
+            .find_iter(&text.text)
+            .filter(|m| !m.is_empty())
+            .map(|m| SearchMatch {
+                start: text.point_at(m.start()),
+                end: text.point_at(m.end() - 1),
+            })
+    }
+}
+
+/// Whether a regular expression spells out an uppercase letter.
+fn has_uppercase_literal(pattern: &str) -> bool {
+    ast::parse::Parser::new()
+        .parse(pattern)
+        .ok()
+        .and_then(|ast| ast::visit(&ast, UppercaseLiteral(false)).ok())
+        .unwrap_or(false)
+}
+
+/// Looks for uppercase letters among the literal characters of a regular
+/// expression, in and out of brackets.
+struct UppercaseLiteral(bool);
+
+impl UppercaseLiteral {
+    fn check(&mut self, literal: &ast::Literal) {
+        if literal.kind == LiteralKind::Verbatim && literal.c.is_uppercase() {
+            self.0 = true;
+        }
+    }
+}
+
+impl Visitor for UppercaseLiteral {
+    type Output = bool;
+    type Err = ();
+
+    fn finish(self) -> Result<bool, ()> {
+        Ok(self.0)
+    }
+
+    fn visit_pre(&mut self, ast: &Ast) -> Result<(), ()> {
+        if let Ast::Literal(literal) = ast {
+            self.check(literal);
+        }
+        Ok(())
+    }
+
+    fn visit_class_set_item_pre(&mut self, item: &ClassSetItem) -> Result<(), ()> {
+        match item {
+            ClassSetItem::Literal(literal) => self.check(literal),
+            ClassSetItem::Range(range) => {
+                self.check(&range.start);
+                self.check(&range.end);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// The first row of the logical line that `line` is part of.
+fn logical_start(state: &TerminalState, line: usize) -> usize {
+    let mut start = line;
+    while start > 0 && state.line_wrapped(start - 1) == Some(true) {
+        start -= 1;
+    }
+    start
+}
+
+/// The text of rows joined by soft wraps, with the cell each character
+/// came from.
+struct LogicalLine {
+    text: String,
+    /// Byte offset in `text` where each character starts, and its cell.
+    cells: Vec<(usize, Point)>,
+    /// Last row of the line.
+    end: usize,
+}
+
+impl LogicalLine {
+    fn read(state: &TerminalState, start: usize) -> Self {
+        let mut text = String::new();
+        let mut cells = Vec::new();
+        let mut line = start;
+        while let Some(row) = state.line(line) {
+            // Trailing blanks end the line unless it goes on in the next row.
+            let len = if row.wrapped {
+                row.cells.len()
+            } else {
+                row.content_len()
+            };
+            for (col, cell) in row.cells[..len].iter().enumerate() {
+                if cell.width > 0 {
+                    let col = col as u16;
+                    cells.push((text.len(), Point { line, col }));
+                    cell.push_grapheme(&mut text);
+                }
+            }
+            if !row.wrapped {
+                break;
+            }
+            line += 1;
+        }
+        LogicalLine {
+            text,
+            cells,
+            end: line.min(state.total_lines().saturating_sub(1)),
+        }
+    }
+
+    /// The cell of the character that byte `offset` of the text is part of.
+    fn point_at(&self, offset: usize) -> Point {
+        let index = self.cells.partition_point(|&(start, _)| start <= offset);
+        self.cells[index.saturating_sub(1)].1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    fn terminal_with(cols: u16, rows: u16, input: &str) -> Terminal {
+        let mut term = Terminal::new(cols, rows);
+        term.process_bytes(input.as_bytes());
+        term
+    }
+
+    fn at(line: usize, col: u16) -> Point {
+        Point { line, col }
+    }
+
+    #[test]
+    fn test_search_multibyte_cells() {
+        let term = terminal_with(20, 3, "é漢字 ok");
+        let query = SearchQuery::new("ok", false).unwrap();
+        let found = query.find(&term.state, at(0, 0), true, false).unwrap();
+        // é takes one cell and each CJK character two.
+        assert_eq!(found.start, at(0, 6));
+        assert_eq!(found.end, at(0, 7));
+    }
+
+    #[test]
+    fn test_search_smart_case_and_regex() {
+        let term = terminal_with(20, 3, "Error error\r\n");
+        let plain = SearchQuery::new("error", false).unwrap();
+        let found = plain.find(&term.state, at(0, 0), true, false);
+        assert_eq!(found.map(|m| m.start), Some(at(0, 6)));
+        // An uppercase letter makes the search case-sensitive.
+        let exact = SearchQuery::new("ERROR", false).unwrap();
+        assert_eq!(exact.find(&term.state, at(0, 0), true, true), None);
+        let literal = SearchQuery::new("e.r", false).unwrap();
+        assert_eq!(literal.find(&term.state, at(0, 0), true, true), None);
+        let regex = SearchQuery::new("e.r", true).unwrap();
+        let found = regex.find(&term.state, at(0, 0), true, false);
+        assert_eq!(found.map(|m| m.start), Some(at(0, 6)));
+        // Uppercase escapes and class names leave regex searches caseless.
+        let upper = terminal_with(20, 3, "ERROR");
+        for pattern in [r"\berror\S*", r"[[:upper:]]rror", r"\p{Lu}rror"] {
+            let query = SearchQuery::new(pattern, true).unwrap();
+            let found = query.find(&upper.state, at(0, 0), true, true);
+            assert_eq!(found.map(|m| m.start), Some(at(0, 0)), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_search_across_soft_wrap() {
+        let term = terminal_with(10, 3, "0123456needle");
+        let query = SearchQuery::new("needle", false).unwrap();
+        let found = query.find(&term.state, at(2, 0), false, false).unwrap();
+        assert_eq!(found.start, at(0, 7));
+        assert_eq!(found.end, at(1, 2));
+    }
+
+    #[test]
+    fn test_search_history_and_wrap() {
+        let term = terminal_with(10, 2, "match\r\nx\r\ny\r\nz");
+        assert_eq!(term.state.history_len(), 2);
+        let query = SearchQuery::new("match", false).unwrap();
+        let bottom = at(3, 0);
+        let found = query.find(&term.state, bottom, false, false).unwrap();
+        assert_eq!(found.start, at(0, 0));
+        assert_eq!(query.find(&term.state, bottom, true, false), None);
+        assert_eq!(query.find(&term.state, bottom, true, true), Some(found));
+    }
+}
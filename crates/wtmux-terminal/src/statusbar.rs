@@ -34,6 +34,8 @@ pub struct WindowStatus {
     pub index: usize,
     pub name: String,
     pub active: bool,
+    /// Show the `!` flag: a pane in the window rang the bell.
+    pub bell: bool,
 }
 
 impl StatusBar {
@@ -64,7 +66,13 @@ impl StatusBar {
         // Render window list
         for win in &ctx.windows {
             let label = format!("{}:{}", win.index, win.name);
-            let suffix = if win.active { "* " } else { " " };
+            let suffix = if win.active {
+                "* "
+            } else if win.bell {
+                "! "
+            } else {
+                " "
+            };
             let full = format!("{}{}", label, suffix);
 
             for ch in full.chars() {
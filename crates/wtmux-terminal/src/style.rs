@@ -0,0 +1,147 @@
+use crate::cell::{Attrs, Cell, Color};
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Everything about a cell except its text: colors, attributes and link.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub attrs: Attrs,
+    pub hyperlink: u32,
+}
+
+impl Style {
+    pub fn of(cell: &Cell) -> Self {
+        Style {
+            fg: cell.fg,
+            bg: cell.bg,
+            attrs: cell.attrs,
+            hyperlink: cell.hyperlink,
+        }
+    }
+
+    /// Give `cell` this style.
+    pub fn apply(&self, cell: &mut Cell) {
+        cell.fg = self.fg;
+        cell.bg = self.bg;
+        cell.attrs = self.attrs;
+        cell.hyperlink = self.hyperlink;
+    }
+}
+
+/// Smallest table that is swept for unused styles.
+const MIN_SWEEP_LEN: usize = 1024;
+
+/// Styles stored once per terminal, so that cells keep a small id instead of
+/// the whole style. Id 0 is the default style. Ids of styles no cell uses
+/// any more are freed by [`StyleTable::sweep`] and handed out again.
+#[derive(Debug)]
+pub struct StyleTable {
+    styles: Vec<Style>,
+    ids: HashMap<Style, u32>,
+    /// Ids freed by the last sweeps, to be reused first.
+    free: Vec<u32>,
+    /// Styles in use after the last sweep.
+    swept_len: usize,
+}
+
+impl Default for StyleTable {
+    fn default() -> Self {
+        StyleTable {
+            styles: vec![Style::default()],
+            ids: HashMap::from([(Style::default(), 0)]),
+            free: Vec::new(),
+            swept_len: 0,
+        }
+    }
+}
+
+impl StyleTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the id for a style, adding it to the table if it is new.
+    pub fn intern(&mut self, style: Style) -> u32 {
+        if let Some(&id) = self.ids.get(&style) {
+            return id;
+        }
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.styles[id as usize] = style;
+                id
+            }
+            None => {
+                self.styles.push(style);
+                self.styles.len() as u32 - 1
+            }
+        };
+        self.ids.insert(style, id);
+        id
+    }
+
+    /// Look up a style by id; unknown ids give the default style.
+    pub fn get(&self, id: u32) -> Style {
+        self.styles.get(id as usize).copied().unwrap_or_default()
+    }
+
+    /// Whether the table has doubled since the last sweep, so that sweeping
+    /// it again is worth going through every stored cell.
+    pub fn needs_sweep(&self) -> bool {
+        self.ids.len() >= (self.swept_len * 2).max(MIN_SWEEP_LEN)
+    }
+
+    /// Free every style except the default one and those with an id in
+    /// `used`.
+    pub fn sweep(&mut self, used: impl IntoIterator<Item = u32>) {
+        let mut keep = vec![false; self.styles.len()];
+        keep[0] = true;
+        for id in used {
+            if let Some(keep) = keep.get_mut(id as usize) {
+                *keep = true;
+            }
+        }
+        for (id, style) in self.styles.iter().enumerate() {
+            if !keep[id] && self.ids.get(style) == Some(&(id as u32)) {
+                self.ids.remove(style);
+                self.free.push(id as u32);
+            }
+        }
+        self.swept_len = self.ids.len();
+    }
+
+    /// The styles in use, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Style> {
+        self.ids.keys()
+    }
+
+    /// Number of styles in use, including the default one.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// A handle to a style table shared by a terminal's grids and scrollback, so
+/// that each of them can look up the styles of the cells it stores. Clones
+/// refer to the same table.
+#[derive(Debug, Clone, Default)]
+pub struct SharedStyles(Arc<RwLock<StyleTable>>);
+
+impl SharedStyles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, StyleTable> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, StyleTable> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
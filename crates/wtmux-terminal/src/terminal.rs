@@ -1,9 +1,15 @@
-use crate::cell::{Attrs, Color};
-use crate::parser::TerminalState;
+use crate::cell::{AttrFlags, Attrs, Cell, Color, UnderlineStyle};
+use crate::hyperlink;
+use crate::modes::TerminalModes;
+use crate::parser::{CursorStyle, TerminalState};
 
 /// High-level terminal that wraps VT parsing and grid management.
 pub struct Terminal {
     pub state: TerminalState,
+    /// Whether `render` and `render_region` may use underline styles
+    /// (`4:x`) and underline colors (SGR 58). When false they are drawn as
+    /// plain underlines.
+    pub styled_underlines: bool,
     vt_parser: vte::Parser,
 }
 
@@ -11,15 +17,45 @@ impl Terminal {
     pub fn new(cols: u16, rows: u16) -> Self {
         Terminal {
             state: TerminalState::new(cols, rows),
+            styled_underlines: true,
+            vt_parser: vte::Parser::new(),
+        }
+    }
+
+    /// Create a terminal that keeps at most `history_limit` scrollback lines.
+    pub fn with_history_limit(cols: u16, rows: u16, history_limit: usize) -> Self {
+        Terminal {
+            state: TerminalState::with_history_limit(cols, rows, history_limit),
+            styled_underlines: true,
             vt_parser: vte::Parser::new(),
         }
     }
 
     /// Process raw bytes from the PTY, updating the internal grid.
-    pub fn process_bytes(&mut self, bytes: &[u8]) {
-        for &byte in bytes {
+    pub fn process_bytes(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let processed = self.process_until_passthrough(bytes);
+            bytes = &bytes[processed..];
+        }
+    }
+
+    /// Process bytes up to the end of the next `DCS tmux;` sequence, so that
+    /// the screen it was sent over can be drawn before it is passed on.
+    /// Returns how many bytes were processed.
+    pub fn process_until_passthrough(&mut self, bytes: &[u8]) -> usize {
+        for (i, &byte) in bytes.iter().enumerate() {
+            if self.state.in_passthrough() {
+                if self.state.passthrough_byte(byte) {
+                    // Let the parser see the terminator too, ending its DCS.
+                    self.vt_parser.advance(&mut self.state, 0x1b);
+                    self.vt_parser.advance(&mut self.state, b'\\');
+                    return i + 1;
+                }
+                continue;
+            }
             self.vt_parser.advance(&mut self.state, byte);
         }
+        bytes.len()
     }
 
     /// Resize the terminal.
@@ -32,6 +68,26 @@ impl Terminal {
         (self.state.cursor.col, self.state.cursor.row)
     }
 
+    /// Whether the application wants the cursor shown (DECTCEM).
+    pub fn cursor_visible(&self) -> bool {
+        self.state.cursor.visible
+    }
+
+    /// Cursor shape set by the application (DECSCUSR).
+    pub fn cursor_style(&self) -> CursorStyle {
+        self.state.cursor_style
+    }
+
+    /// DEC private modes set by the application, used to encode input.
+    pub fn modes(&self) -> &TerminalModes {
+        self.state.modes()
+    }
+
+    /// Take the bytes queued in reply to queries, to be written to the PTY.
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        self.state.take_outgoing()
+    }
+
     /// Check if the terminal content has been modified.
     pub fn is_dirty(&self) -> bool {
         self.state.dirty
@@ -42,6 +98,17 @@ impl Terminal {
         self.state.dirty = false;
     }
 
+    /// Start a new damage epoch and return it. Pass it to `damaged_rows`
+    /// later to learn which screen rows changed in between.
+    pub fn begin_damage_epoch(&mut self) -> u64 {
+        self.state.grid.advance_damage_epoch()
+    }
+
+    /// Screen rows changed since the start of epoch `since`.
+    pub fn damaged_rows(&self, since: u64) -> Vec<u16> {
+        self.state.grid.damaged_rows(since).collect()
+    }
+
     /// Render the terminal grid to ANSI escape sequences.
     pub fn render(&self) -> Vec<u8> {
         let mut output = Vec::with_capacity(
@@ -56,6 +123,7 @@ impl Terminal {
         let mut prev_fg = Color::Default;
         let mut prev_bg = Color::Default;
         let mut prev_attrs = Attrs::default();
+        let mut prev_link = 0;
 
         for row in 0..self.state.grid.rows {
             if row > 0 {
@@ -71,34 +139,29 @@ impl Terminal {
                 }
 
                 // Emit SGR changes only when needed
-                let need_sgr = cell.fg != prev_fg
-                    || cell.bg != prev_bg
+                let fg = self.state.palette.resolve(cell.fg, true);
+                let bg = self.state.palette.resolve(cell.bg, false);
+                let need_sgr = fg != prev_fg
+                    || bg != prev_bg
                     || cell.attrs != prev_attrs;
 
                 if need_sgr {
-                    output.extend_from_slice(b"\x1b[0"); // Reset first
-
-                    // Foreground
-                    write_color(&mut output, cell.fg, true);
-                    // Background
-                    write_color(&mut output, cell.bg, false);
-                    // Attributes
-                    write_attrs(&mut output, cell.attrs);
-
-                    output.push(b'm');
-
-                    prev_fg = cell.fg;
-                    prev_bg = cell.bg;
+                    write_sgr(&mut output, fg, bg, cell.attrs, self.styled_underlines);
+                    prev_fg = fg;
+                    prev_bg = bg;
                     prev_attrs = cell.attrs;
                 }
 
+                self.write_hyperlink(&mut output, &cell, &mut prev_link);
+
                 // Write the character
-                let mut buf = [0u8; 4];
-                let s = cell.ch.encode_utf8(&mut buf);
-                output.extend_from_slice(s.as_bytes());
+                cell.write_grapheme(&mut output);
             }
         }
 
+        if prev_link != 0 {
+            output.extend_from_slice(hyperlink::CLOSE_SEQUENCE);
+        }
         // Reset attributes
         output.extend_from_slice(b"\x1b[0m");
 
@@ -121,19 +184,41 @@ impl Terminal {
 
     /// Render a rectangular sub-region of the grid to ANSI escape sequences.
     pub fn render_region(&self, x: u16, y: u16, width: u16, height: u16, dest_x: u16, dest_y: u16) -> Vec<u8> {
+        self.render_lines(
+            self.state.history_len() + y as usize,
+            x,
+            width,
+            height,
+            dest_x,
+            dest_y,
+        )
+    }
+
+    /// Render `height` lines of the combined history + screen view, starting
+    /// at absolute line `first_line` (see [`TerminalState::line`]).
+    pub fn render_lines(
+        &self,
+        first_line: usize,
+        x: u16,
+        width: u16,
+        height: u16,
+        dest_x: u16,
+        dest_y: u16,
+    ) -> Vec<u8> {
         let mut output = Vec::new();
 
         let mut prev_fg = Color::Default;
         let mut prev_bg = Color::Default;
         let mut prev_attrs = Attrs::default();
+        let mut prev_link = 0;
 
         for row_offset in 0..height {
-            let src_row = y + row_offset;
             let dst_row = dest_y + row_offset;
 
-            if src_row >= self.state.grid.rows {
-                break;
-            }
+            let line = match self.state.line(first_line + row_offset as usize) {
+                Some(line) => line,
+                None => break,
+            };
 
             // Move cursor to destination position
             output.extend_from_slice(
@@ -141,40 +226,115 @@ impl Terminal {
             );
 
             for col_offset in 0..width {
-                let src_col = x + col_offset;
-                if src_col >= self.state.grid.cols {
-                    break;
-                }
-
-                let cell = self.state.grid.cell(src_col, src_row);
-                if cell.width == 0 {
+                let src_col = (x + col_offset) as usize;
+                let cell = match line.get(src_col) {
+                    Some(cell) => cell,
+                    None => break,
+                };
+                // A wide character cut by either edge of the region is drawn
+                // as a blank so that it does not spill into a neighbor.
+                let cut = (cell.width == 0 && col_offset == 0)
+                    || (cell.width == 2 && col_offset + 1 == width);
+                let blank;
+                let cell = if cut {
+                    blank = Cell {
+                        ch: ' ',
+                        combining: None,
+                        width: 1,
+                        ..cell.clone()
+                    };
+                    &blank
+                } else if cell.width == 0 {
                     continue;
-                }
+                } else {
+                    cell
+                };
 
-                let need_sgr = cell.fg != prev_fg
-                    || cell.bg != prev_bg
+                let fg = self.state.palette.resolve(cell.fg, true);
+                let bg = self.state.palette.resolve(cell.bg, false);
+                let need_sgr = fg != prev_fg
+                    || bg != prev_bg
                     || cell.attrs != prev_attrs;
 
                 if need_sgr {
-                    output.extend_from_slice(b"\x1b[0");
-                    write_color(&mut output, cell.fg, true);
-                    write_color(&mut output, cell.bg, false);
-                    write_attrs(&mut output, cell.attrs);
-                    output.push(b'm');
-                    prev_fg = cell.fg;
-                    prev_bg = cell.bg;
+                    write_sgr(&mut output, fg, bg, cell.attrs, self.styled_underlines);
+                    prev_fg = fg;
+                    prev_bg = bg;
                     prev_attrs = cell.attrs;
                 }
 
-                let mut buf = [0u8; 4];
-                let s = cell.ch.encode_utf8(&mut buf);
-                output.extend_from_slice(s.as_bytes());
+                self.write_hyperlink(&mut output, cell, &mut prev_link);
+
+                cell.write_grapheme(&mut output);
+            }
+
+            // Close links at the edge of the region so they do not spill
+            // into whatever is drawn next to it.
+            if prev_link != 0 {
+                output.extend_from_slice(hyperlink::CLOSE_SEQUENCE);
+                prev_link = 0;
             }
         }
 
         output.extend_from_slice(b"\x1b[0m");
         output
     }
+
+    /// Emit OSC 8 when the link changes between `prev_link` and `cell`.
+    fn write_hyperlink(&self, output: &mut Vec<u8>, cell: &Cell, prev_link: &mut u32) {
+        if cell.hyperlink == *prev_link {
+            return;
+        }
+        match self.state.hyperlinks.get(cell.hyperlink) {
+            Some(link) => output.extend_from_slice(&link.open_sequence()),
+            None => output.extend_from_slice(hyperlink::CLOSE_SEQUENCE),
+        }
+        *prev_link = cell.hyperlink;
+    }
+
+    /// A cell as this terminal draws it: palette colors resolved, and
+    /// underline styles and colors reduced to plain underlines unless the
+    /// terminal it is drawn for shows `styled_underlines`.
+    pub fn drawn_cell(&self, cell: &Cell, styled_underlines: bool) -> Cell {
+        let mut attrs = cell.attrs;
+        if !styled_underlines {
+            if attrs.underline != UnderlineStyle::None {
+                attrs.underline = UnderlineStyle::Single;
+            }
+            attrs.underline_color = Color::Default;
+        }
+        Cell {
+            fg: self.state.palette.resolve(cell.fg, true),
+            bg: self.state.palette.resolve(cell.bg, false),
+            attrs,
+            ..cell.clone()
+        }
+    }
+
+    /// URI of the hyperlink at a cell of the combined history + screen view.
+    pub fn hyperlink_at(&self, col: u16, line: usize) -> Option<&str> {
+        let link = self.state.line(line)?.get(col as usize)?.hyperlink;
+        self.state
+            .hyperlinks
+            .get(link)
+            .map(|link| link.uri.as_str())
+    }
+}
+
+/// Write an SGR sequence that resets the style and then selects `fg`, `bg`
+/// and `attrs`.
+pub fn write_sgr(
+    output: &mut Vec<u8>,
+    fg: Color,
+    bg: Color,
+    attrs: Attrs,
+    styled_underlines: bool,
+) {
+    output.extend_from_slice(b"\x1b[0");
+    write_color(output, fg, true);
+    write_color(output, bg, false);
+    write_attrs(output, attrs, styled_underlines);
+    output.push(b'm');
 }
 
 fn write_color(output: &mut Vec<u8>, color: Color, is_fg: bool) {
@@ -199,33 +359,55 @@ fn write_color(output: &mut Vec<u8>, color: Color, is_fg: bool) {
     }
 }
 
-fn write_attrs(output: &mut Vec<u8>, attrs: Attrs) {
-    if attrs.bold {
+fn write_attrs(output: &mut Vec<u8>, attrs: Attrs, styled_underlines: bool) {
+    if attrs.flags.contains(AttrFlags::BOLD) {
         output.extend_from_slice(b";1");
     }
-    if attrs.italic {
+    if attrs.flags.contains(AttrFlags::DIM) {
+        output.extend_from_slice(b";2");
+    }
+    if attrs.flags.contains(AttrFlags::ITALIC) {
         output.extend_from_slice(b";3");
     }
-    if attrs.underline {
-        output.extend_from_slice(b";4");
+    match attrs.underline {
+        UnderlineStyle::None => {}
+        UnderlineStyle::Single => output.extend_from_slice(b";4"),
+        _ if !styled_underlines => output.extend_from_slice(b";4"),
+        UnderlineStyle::Double => output.extend_from_slice(b";4:2"),
+        UnderlineStyle::Curly => output.extend_from_slice(b";4:3"),
+        UnderlineStyle::Dotted => output.extend_from_slice(b";4:4"),
+        UnderlineStyle::Dashed => output.extend_from_slice(b";4:5"),
     }
-    if attrs.blink {
+    if attrs.flags.contains(AttrFlags::BLINK) {
         output.extend_from_slice(b";5");
     }
-    if attrs.reverse {
+    if attrs.flags.contains(AttrFlags::REVERSE) {
         output.extend_from_slice(b";7");
     }
-    if attrs.hidden {
+    if attrs.flags.contains(AttrFlags::HIDDEN) {
         output.extend_from_slice(b";8");
     }
-    if attrs.strikethrough {
+    if attrs.flags.contains(AttrFlags::STRIKETHROUGH) {
         output.extend_from_slice(b";9");
     }
+    if attrs.flags.contains(AttrFlags::OVERLINE) {
+        output.extend_from_slice(b";53");
+    }
+    if styled_underlines {
+        match attrs.underline_color {
+            Color::Default => {}
+            Color::Indexed(n) => output.extend_from_slice(format!(";58:5:{}", n).as_bytes()),
+            Color::Rgb(r, g, b) => {
+                output.extend_from_slice(format!(";58:2::{}:{}:{}", r, g, b).as_bytes())
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dcs::Passthrough;
 
     #[test]
     fn test_process_simple_text() {
@@ -265,4 +447,829 @@ mod tests {
         term.process_bytes(b"\x1b[31mRed");
         assert_eq!(term.state.grid.cell(0, 0).fg, Color::Indexed(1));
     }
+
+    #[test]
+    fn test_scrolled_lines_go_to_history() {
+        let mut term = Terminal::new(10, 3);
+        term.process_bytes(b"one\r\ntwo\r\nthree\r\nfour");
+        assert_eq!(term.state.history_len(), 1);
+        assert_eq!(term.state.total_lines(), 4);
+        assert_eq!(term.state.line(0).unwrap()[0].ch, 'o');
+        assert_eq!(term.state.line(1).unwrap()[0].ch, 't');
+        assert_eq!(term.state.line(3).unwrap()[0].ch, 'f');
+        assert!(term.state.line(4).is_none());
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let mut term = Terminal::with_history_limit(10, 2, 3);
+        for i in 0..10 {
+            term.process_bytes(format!("{}\r\n", i).as_bytes());
+        }
+        assert_eq!(term.state.history_len(), 3);
+        assert_eq!(term.state.line(0).unwrap()[0].ch, '6');
+    }
+
+    #[test]
+    fn test_styles_of_dropped_history_are_freed() {
+        let mut term = Terminal::with_history_limit(10, 2, 100);
+        for i in 0..10_000 {
+            let (r, g) = (i % 256, i / 256);
+            term.process_bytes(format!("\x1b[38;2;{};{};0mx\x1b[m\r\n", r, g).as_bytes());
+        }
+        let styles = term.state.styles.read().len();
+        assert!(styles <= 2048, "{}", styles);
+        // The styles left in the history still read back; the last line is
+        // still on the screen.
+        let cell = &term.state.line(99).unwrap()[0];
+        assert_eq!(cell.fg, Color::Rgb((9_998 % 256) as u8, (9_998 / 256) as u8, 0));
+    }
+
+    #[test]
+    fn test_links_of_dropped_history_are_freed() {
+        let mut term = Terminal::with_history_limit(20, 2, 10);
+        for i in 0..5_000 {
+            let line = format!("\x1b]8;;https://example.com/{}\x1b\\x\x1b]8;;\x1b\\\r\n", i);
+            term.process_bytes(line.as_bytes());
+        }
+        assert!(term.state.hyperlinks.len() <= 512, "{}", term.state.hyperlinks.len());
+        assert_eq!(term.hyperlink_at(0, 9), Some("https://example.com/4998"));
+    }
+
+    #[test]
+    fn test_no_history_from_partial_region_or_alt_screen() {
+        let mut term = Terminal::new(10, 4);
+        // Scroll region covering only rows 2-3
+        term.process_bytes(b"\x1b[2;3r\x1b[3;1Ha\r\nb\r\nc");
+        assert_eq!(term.state.history_len(), 0);
+
+        let mut term = Terminal::new(10, 2);
+        term.process_bytes(b"\x1b[?1049ha\r\nb\r\nc\r\n");
+        assert_eq!(term.state.history_len(), 0);
+    }
+
+    #[test]
+    fn test_clear_scrollback() {
+        let mut term = Terminal::new(10, 2);
+        term.process_bytes(b"a\r\nb\r\nc");
+        assert_eq!(term.state.history_len(), 1);
+        term.process_bytes(b"\x1b[3J");
+        assert_eq!(term.state.history_len(), 0);
+        assert_eq!(term.state.grid.cell(0, 1).ch, 'c');
+    }
+
+    #[test]
+    fn test_private_modes_tracked() {
+        use crate::modes::{MouseEncoding, MouseTracking};
+
+        let mut term = Terminal::new(80, 24);
+        assert!(term.modes().autowrap);
+        term.process_bytes(b"\x1b[?1h\x1b[?2004h\x1b[?1004;1007h\x1b[?7l");
+        assert!(term.modes().app_cursor_keys);
+        assert!(term.modes().bracketed_paste);
+        assert!(term.modes().focus_reporting);
+        assert!(term.modes().alternate_scroll);
+        assert!(!term.modes().autowrap);
+
+        term.process_bytes(b"\x1b[?1002h\x1b[?1006h");
+        assert_eq!(term.modes().mouse_tracking, MouseTracking::Drag);
+        assert_eq!(term.modes().mouse_encoding, MouseEncoding::Sgr);
+        term.process_bytes(b"\x1b[?1002l\x1b[?1006l\x1b[?1l");
+        assert_eq!(term.modes().mouse_tracking, MouseTracking::Off);
+        assert_eq!(term.modes().mouse_encoding, MouseEncoding::Default);
+        assert!(!term.modes().app_cursor_keys);
+
+        term.process_bytes(b"\x1b=");
+        assert!(term.modes().app_keypad);
+        term.process_bytes(b"\x1b>");
+        assert!(!term.modes().app_keypad);
+        term.process_bytes(b"\x1b[?66h");
+        assert!(term.modes().app_keypad);
+    }
+
+    #[test]
+    fn test_origin_mode() {
+        let mut term = Terminal::new(80, 24);
+        term.process_bytes(b"\x1b[5;10r\x1b[?6h");
+        assert_eq!(term.cursor_pos(), (0, 4));
+        term.process_bytes(b"\x1b[2;3H");
+        assert_eq!(term.cursor_pos(), (2, 5));
+        // Clamped to the bottom of the region.
+        term.process_bytes(b"\x1b[20;1H");
+        assert_eq!(term.cursor_pos(), (0, 9));
+        term.process_bytes(b"\x1b[?6l");
+        assert_eq!(term.cursor_pos(), (0, 0));
+    }
+
+    #[test]
+    fn test_status_and_cursor_reports() {
+        let mut term = Terminal::new(80, 24);
+        term.process_bytes(b"\x1b[5n");
+        assert_eq!(term.take_outgoing(), b"\x1b[0n");
+        assert!(term.take_outgoing().is_empty());
+
+        term.process_bytes(b"\x1b[5;10H\x1b[6n\x1b[?6n");
+        assert_eq!(term.take_outgoing(), b"\x1b[5;10R\x1b[?5;10R");
+
+        // Reported relative to the scroll region in origin mode.
+        term.process_bytes(b"\x1b[3;20r\x1b[?6h\x1b[2;4H\x1b[6n");
+        assert_eq!(term.take_outgoing(), b"\x1b[2;4R");
+    }
+
+    #[test]
+    fn test_device_attributes_and_version() {
+        let mut term = Terminal::new(80, 24);
+        term.process_bytes(b"\x1b[c\x1b[0c");
+        assert_eq!(term.take_outgoing(), b"\x1b[?62;22c\x1b[?62;22c");
+        term.process_bytes(b"\x1b[>c");
+        assert_eq!(term.take_outgoing(), b"\x1b[>84;0;0c");
+        term.process_bytes(b"\x1b[>q");
+        assert_eq!(
+            term.take_outgoing(),
+            format!("\x1bP>|wtmux {}\x1b\\", env!("CARGO_PKG_VERSION")).as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_request_mode() {
+        let mut term = Terminal::new(80, 24);
+        term.process_bytes(b"\x1b[?2004h\x1b[?2004$p\x1b[?1$p\x1b[?25$p\x1b[?9999$p\x1b[4$p");
+        assert_eq!(
+            term.take_outgoing(),
+            b"\x1b[?2004;1$y\x1b[?1;2$y\x1b[?25;1$y\x1b[?9999;0$y\x1b[4;0$y"
+        );
+    }
+
+    #[test]
+    fn test_dec_line_drawing() {
+        let mut term = Terminal::new(80, 24);
+        term.process_bytes(b"\x1b(0lqqk\x1b(B lqk");
+        assert_eq!(term.state.grid.row_text(0), "┌──┐ lqk");
+    }
+
+    #[test]
+    fn test_shift_in_out_and_single_shift() {
+        let mut term = Terminal::new(80, 24);
+        // G1 is line drawing, selected with SO and back to G0 with SI.
+        term.process_bytes(b"\x1b)0x\x0ex\x0fx");
+        assert_eq!(term.state.grid.row_text(0), "x│x");
+
+        // SS2 applies G2 to one character only.
+        let mut term = Terminal::new(80, 24);
+        term.process_bytes(b"\x1b*0\x1bNqq");
+        assert_eq!(term.state.grid.row_text(0), "─q");
+    }
+
+    #[test]
+    fn test_save_restore_charsets() {
+        let mut term = Terminal::new(80, 24);
+        term.process_bytes(b"\x1b(0\x1b7\x1b(Bab\x1b8\x1b[3Gq");
+        assert_eq!(term.state.grid.row_text(0), "ab─");
+    }
+
+    #[test]
+    fn test_default_tab_stops() {
+        let mut term = Terminal::new(20, 4);
+        term.process_bytes(b"ab\t");
+        assert_eq!(term.cursor_pos(), (8, 0));
+        term.process_bytes(b"\t\t\t");
+        // No stop left: tab stops at the last column.
+        assert_eq!(term.cursor_pos(), (19, 0));
+    }
+
+    #[test]
+    fn test_set_and_clear_tab_stops() {
+        let mut term = Terminal::new(40, 4);
+        // Clear all, then set stops at columns 3 and 10.
+        term.process_bytes(b"\x1b[3g\x1b[4G\x1bH\x1b[11G\x1bH\r");
+        term.process_bytes(b"\t");
+        assert_eq!(term.cursor_pos(), (3, 0));
+        term.process_bytes(b"\t");
+        assert_eq!(term.cursor_pos(), (10, 0));
+
+        // Clear the stop under the cursor only.
+        term.process_bytes(b"\x1b[g\r\t\t");
+        assert_eq!(term.cursor_pos(), (39, 0));
+    }
+
+    #[test]
+    fn test_forward_and_backward_tabulation() {
+        let mut term = Terminal::new(40, 4);
+        term.process_bytes(b"\x1b[2I");
+        assert_eq!(term.cursor_pos(), (16, 0));
+        term.process_bytes(b"\x1b[Z");
+        assert_eq!(term.cursor_pos(), (8, 0));
+        term.process_bytes(b"\x1b[5Z");
+        assert_eq!(term.cursor_pos(), (0, 0));
+    }
+
+    #[test]
+    fn test_tab_stops_on_resize() {
+        let mut term = Terminal::new(10, 4);
+        term.process_bytes(b"\x1b[3g\x1b[3G\x1bH\r");
+        term.resize(20, 4);
+        // The custom stop survives; the new columns get default stops.
+        term.process_bytes(b"\t");
+        assert_eq!(term.cursor_pos(), (2, 0));
+        term.process_bytes(b"\t");
+        assert_eq!(term.cursor_pos(), (16, 0));
+    }
+
+    #[test]
+    fn test_hyperlinks_stored_in_cells() {
+        let mut term = Terminal::new(80, 24);
+        term.process_bytes(b"see \x1b]8;id=x;https://example.com/a;b\x1b\\docs\x1b]8;;\x1b\\ now");
+        let grid = &term.state.grid;
+        assert_eq!(grid.cell(3, 0).hyperlink, 0);
+        assert_ne!(grid.cell(4, 0).hyperlink, 0);
+        assert_eq!(grid.cell(7, 0).hyperlink, grid.cell(4, 0).hyperlink);
+        assert_eq!(grid.cell(8, 0).hyperlink, 0);
+        assert_eq!(term.hyperlink_at(5, 0), Some("https://example.com/a;b"));
+        assert_eq!(term.hyperlink_at(9, 0), None);
+
+        // The same link again reuses its table entry.
+        term.process_bytes(b"\x1b]8;id=x;https://example.com/a;b\x1b\\!");
+        assert_eq!(term.state.hyperlinks.len(), 1);
+    }
+
+    #[test]
+    fn test_render_region_reemits_hyperlinks() {
+        let mut term = Terminal::new(20, 2);
+        term.process_bytes(b"a\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\b");
+        let out = String::from_utf8(term.render_region(0, 0, 20, 1, 0, 0)).unwrap();
+        assert!(out.contains("a\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\b"));
+
+        // A link cut by the region edge is closed at the edge.
+        let out = String::from_utf8(term.render_region(0, 0, 3, 1, 0, 0)).unwrap();
+        assert!(out.ends_with("\x1b]8;;http://x\x1b\\li\x1b]8;;\x1b\\\x1b[0m"));
+    }
+
+    #[test]
+    fn test_osc52_clipboard_requests() {
+        use crate::clipboard::ClipboardRequest;
+
+        let mut term = Terminal::new(80, 24);
+        term.process_bytes(b"\x1b]52;c;aGVsbG8gd29ybGQ=\x07\x1b]52;;?\x1b\\\x1b]52;c;!!\x07");
+        assert_eq!(
+            term.state.take_clipboard_requests(),
+            vec![
+                ClipboardRequest::Set {
+                    selection: "c".to_string(),
+                    text: "hello world".to_string(),
+                },
+                ClipboardRequest::Query {
+                    selection: "s0".to_string(),
+                },
+            ]
+        );
+        assert!(term.state.take_clipboard_requests().is_empty());
+    }
+
+    #[test]
+    fn test_color_queries() {
+        let mut term = Terminal::new(80, 24);
+        term.process_bytes(b"\x1b]11;?\x07");
+        assert_eq!(term.take_outgoing(), b"\x1b]11;rgb:0000/0000/0000\x07");
+        term.process_bytes(b"\x1b]10;?;?\x1b\\");
+        assert_eq!(
+            term.take_outgoing(),
+            b"\x1b]10;rgb:e5e5/e5e5/e5e5\x1b\\\x1b]11;rgb:0000/0000/0000\x1b\\"
+        );
+        term.process_bytes(b"\x1b]4;1;?\x07");
+        assert_eq!(term.take_outgoing(), b"\x1b]4;1;rgb:cdcd/0000/0000\x07");
+    }
+
+    #[test]
+    fn test_palette_overrides() {
+        let mut term = Terminal::new(80, 24);
+        term.process_bytes(b"\x1b]4;1;rgb:12/34/56;2;#abcdef\x07\x1b]11;#fdf6e3\x07");
+        term.process_bytes(b"\x1b]4;1;?\x07\x1b]11;?\x07");
+        assert_eq!(
+            term.take_outgoing(),
+            b"\x1b]4;1;rgb:1212/3434/5656\x07\x1b]11;rgb:fdfd/f6f6/e3e3\x07"
+        );
+
+        // Rendering uses the overridden values.
+        term.process_bytes(b"\x1b[31mx");
+        let out = String::from_utf8(term.render_region(0, 0, 1, 1, 0, 0)).unwrap();
+        assert!(out.contains("\x1b[0;38;2;18;52;86;48;2;253;246;227mx"));
+
+        // Resets restore the defaults.
+        term.process_bytes(b"\x1b]104;1\x07\x1b]111\x07\x1b]4;1;?;2;?\x07\x1b]11;?\x07");
+        assert_eq!(
+            term.take_outgoing(),
+            b"\x1b]4;1;rgb:cdcd/0000/0000\x07\x1b]4;2;rgb:abab/cdcd/efef\x07\x1b]11;rgb:0000/0000/0000\x07"
+        );
+        let out = String::from_utf8(term.render_region(0, 0, 1, 1, 0, 0)).unwrap();
+        assert!(out.contains("\x1b[0;31mx"));
+    }
+
+    #[test]
+    fn test_osc7_current_dir() {
+        let mut term = Terminal::new(80, 24);
+        assert_eq!(term.state.current_dir, None);
+        term.process_bytes(b"\x1b]7;file://host/home/me/my%20dir\x07");
+        assert_eq!(term.state.current_dir.as_deref(), Some("/home/me/my dir"));
+        term.process_bytes(b"\x1b]7;file://DESKTOP/C:/Users/me\x1b\\");
+        assert_eq!(term.state.current_dir.as_deref(), Some("C:/Users/me"));
+        // Not a file URI: ignored.
+        term.process_bytes(b"\x1b]7;http://host/x\x07");
+        assert_eq!(term.state.current_dir.as_deref(), Some("C:/Users/me"));
+    }
+
+    /// Output of a shell with OSC 133 integration running `cmd`, which
+    /// prints `output` and exits with `status`.
+    fn shell_command(cmd: &str, output: &str, status: i32) -> Vec<u8> {
+        format!(
+            "\x1b]133;A\x07$ \x1b]133;B\x07{}\r\n\x1b]133;C\x07{}\x1b]133;D;{}\x07",
+            cmd, output, status
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_prompt_marks_recorded_in_history() {
+        let mut term = Terminal::new(20, 4);
+        term.process_bytes(&shell_command("ls", "a\r\nb\r\n", 0));
+        term.process_bytes(&shell_command("false", "", 1));
+        term.process_bytes(b"\x1b]133;A\x07$ ");
+        // Lines: $ ls / a / b / $ false / $
+        assert_eq!(term.state.history_len(), 1);
+        let marks = |line: usize| term.state.line(line).unwrap().marks;
+        assert!(marks(0).prompt && marks(0).command);
+        assert!(marks(1).output);
+        assert!(marks(3).prompt && marks(3).finished);
+        assert_eq!(marks(3).exit_status, Some(0));
+        assert_eq!(marks(4).exit_status, Some(1));
+
+        assert_eq!(term.state.previous_prompt(4), Some(3));
+        assert_eq!(term.state.previous_prompt(3), Some(0));
+        assert_eq!(term.state.previous_prompt(0), None);
+        assert_eq!(term.state.next_prompt(0), Some(3));
+        assert_eq!(term.state.next_prompt(4), None);
+    }
+
+    #[test]
+    fn test_last_command_output() {
+        let mut term = Terminal::new(10, 10);
+        assert_eq!(term.state.last_command_output(), None);
+        term.process_bytes(&shell_command("ls", "one\r\n0123456789abc\r\n", 0));
+        assert_eq!(
+            term.state.last_command_output().as_deref(),
+            Some("one\n0123456789abc")
+        );
+
+        // A command that is still running does not count.
+        term.process_bytes(b"\x1b]133;A\x07$ \x1b]133;B\x07make\r\n\x1b]133;C\x07building");
+        assert_eq!(
+            term.state.last_command_output().as_deref(),
+            Some("one\n0123456789abc")
+        );
+    }
+
+    #[test]
+    fn test_last_command_output_ends_where_d_arrived() {
+        let mut term = Terminal::new(10, 10);
+        // Output without a final newline: D arrives after it on its line.
+        term.process_bytes(&shell_command("cat", "a\r\nb", 0));
+        assert_eq!(term.state.last_command_output().as_deref(), Some("a\nb"));
+        term.process_bytes(b"\r\n");
+        term.process_bytes(&shell_command("printf", "foo", 0));
+        assert_eq!(term.state.last_command_output().as_deref(), Some("foo"));
+
+        // Across a soft wrap, and after the line is rewrapped.
+        term.process_bytes(b"\r\n");
+        term.process_bytes(&shell_command("echo", "0123456789abc", 0));
+        assert_eq!(term.state.last_command_output().as_deref(), Some("0123456789abc"));
+        term.resize(20, 10);
+        assert_eq!(term.state.last_command_output().as_deref(), Some("0123456789abc"));
+    }
+
+    #[test]
+    fn test_last_command_output_needs_its_own_c_mark() {
+        let mut term = Terminal::new(10, 10);
+        term.process_bytes(&shell_command("ls", "one\r\n", 0));
+        // A command that finished without reporting where its output began.
+        term.process_bytes(b"\x1b]133;A\x07$ \x1b]133;B\x07cd\r\n\x1b]133;D;0\x07");
+        assert_eq!(term.state.last_command_output(), None);
+    }
+
+    #[test]
+    fn test_prompt_marks_survive_reflow() {
+        let mut term = Terminal::new(10, 6);
+        term.process_bytes(&shell_command("echo", "0123456789abc\r\n", 0));
+        term.resize(5, 6);
+        term.resize(20, 6);
+        assert_eq!(
+            term.state.last_command_output().as_deref(),
+            Some("0123456789abc")
+        );
+    }
+
+    #[test]
+    fn test_sgr_extended_attributes() {
+        let mut term = Terminal::new(10, 2);
+        term.process_bytes(b"\x1b[2;53;21ma\x1b[22;55;24mb");
+        let a = term.state.grid.cell(0, 0).attrs;
+        assert!(a.flags.contains(AttrFlags::DIM | AttrFlags::OVERLINE));
+        assert_eq!(a.underline, UnderlineStyle::Double);
+        assert_eq!(term.state.grid.cell(1, 0).attrs, Attrs::default());
+
+        // Colon subparameters: 4:3 is a curly underline, not underline + italic.
+        term.process_bytes(b"\x1b[4:3;58:2::255:0:10mc\x1b[4:0;59md");
+        let c = term.state.grid.cell(2, 0).attrs;
+        assert_eq!(c.underline, UnderlineStyle::Curly);
+        assert!(!c.flags.contains(AttrFlags::ITALIC));
+        assert_eq!(c.underline_color, Color::Rgb(255, 0, 10));
+        assert_eq!(term.state.grid.cell(3, 0).attrs, Attrs::default());
+
+        // Semicolon and colon forms of the other colors.
+        term.process_bytes(b"\x1b[58;5;196;38:5:1;48:2:1:2:3me");
+        let e = term.state.grid.cell(4, 0);
+        assert_eq!(e.attrs.underline_color, Color::Indexed(196));
+        assert_eq!(e.fg, Color::Indexed(1));
+        assert_eq!(e.bg, Color::Rgb(1, 2, 3));
+        assert_eq!(e.attrs.underline, UnderlineStyle::None);
+    }
+
+    #[test]
+    fn test_render_extended_attributes() {
+        let mut term = Terminal::new(4, 1);
+        term.process_bytes(b"\x1b[2;53;4:3;58:5:9mx");
+        let out = String::from_utf8(term.render_region(0, 0, 1, 1, 0, 0)).unwrap();
+        assert!(out.contains("\x1b[0;2;4:3;53;58:5:9mx"));
+
+        // Clients without styled underlines get a plain one.
+        term.styled_underlines = false;
+        let out = String::from_utf8(term.render_region(0, 0, 1, 1, 0, 0)).unwrap();
+        assert!(out.contains("\x1b[0;2;4;53mx"));
+    }
+
+    #[test]
+    fn test_grapheme_clusters() {
+        // e + combining acute, family ZWJ sequence, US flag, thumbs up + skin tone
+        const TEXT: &str = concat!(
+            "e\u{301}x",
+            "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}",
+            "\u{1f1fa}\u{1f1f8}",
+            "\u{1f44d}\u{1f3fb}|",
+        );
+        let mut term = Terminal::new(20, 2);
+        term.process_bytes(TEXT.as_bytes());
+        assert_eq!(term.state.grid.cell(0, 0).ch, 'e');
+        assert_eq!(term.state.grid.cell(0, 0).combining.as_deref(), Some("\u{301}"));
+        assert_eq!(term.state.grid.cell(1, 0).ch, 'x');
+        assert_eq!(term.state.grid.cell(2, 0).width, 2);
+        assert_eq!(term.state.grid.cell(4, 0).width, 2);
+        assert_eq!(term.state.grid.cell(6, 0).width, 2);
+        assert_eq!(term.state.grid.cell(8, 0).ch, '|');
+        assert_eq!(term.cursor_pos(), (9, 0));
+        assert_eq!(term.state.grid.row_text(0), TEXT);
+
+        let out = String::from_utf8(term.render_region(0, 0, 4, 1, 0, 0)).unwrap();
+        assert!(out.contains("e\u{301}x\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}"));
+    }
+
+    #[test]
+    fn test_variation_selectors_change_width() {
+        let mut term = Terminal::new(10, 2);
+        // VS16 turns a narrow heart into a wide emoji.
+        term.process_bytes("\u{2764}\u{fe0f}a".as_bytes());
+        assert_eq!(term.state.grid.cell(0, 0).width, 2);
+        assert_eq!(term.state.grid.cell(1, 0).width, 0);
+        assert_eq!(term.state.grid.cell(2, 0).ch, 'a');
+
+        // VS15 turns a wide watch into narrow text.
+        term.process_bytes("\r\n\u{231a}\u{fe0e}b".as_bytes());
+        assert_eq!(term.state.grid.cell(0, 1).width, 1);
+        assert_eq!(term.state.grid.cell(1, 1).ch, 'b');
+    }
+
+    #[test]
+    fn test_combining_marks_are_capped() {
+        let mut term = Terminal::new(10, 2);
+        let mut text = String::from("a");
+        text.extend(std::iter::repeat_n('\u{301}', 20_000));
+        text.push('b');
+        term.process_bytes(text.as_bytes());
+        let combining = term.state.grid.cell(0, 0).combining.clone().unwrap();
+        assert_eq!(combining.chars().count(), 16);
+        assert_eq!(term.state.grid.cell(1, 0).ch, 'b');
+
+        // A third regional indicator starts a new flag.
+        term.process_bytes("\r\n\u{1f1fa}\u{1f1f8}\u{1f1eb}".as_bytes());
+        assert_eq!(term.state.grid.cell(0, 1).combining.as_deref(), Some("\u{1f1f8}"));
+        assert_eq!(term.state.grid.cell(2, 1).ch, '\u{1f1eb}');
+    }
+
+    #[test]
+    fn test_combining_mark_after_wrap() {
+        let mut term = Terminal::new(3, 3);
+        term.process_bytes("abc\u{301}d".as_bytes());
+        assert_eq!(term.state.grid.cell(2, 0).combining.as_deref(), Some("\u{301}"));
+        assert_eq!(term.state.grid.cell(0, 1).ch, 'd');
+
+        // With nothing to attach to, a combining mark is dropped.
+        term.process_bytes("\r\n\u{301}z".as_bytes());
+        assert_eq!(term.state.grid.cell(0, 2).ch, 'z');
+    }
+
+    #[test]
+    fn test_wide_char_wraps_at_last_column() {
+        let mut term = Terminal::new(5, 3);
+        term.process_bytes("abcd\u{4e2d}".as_bytes());
+        assert_eq!(term.state.grid.cell(4, 0).ch, ' ');
+        assert_eq!(term.state.grid.cell(4, 0).width, 1);
+        assert!(term.state.grid.is_wrapped(0));
+        assert_eq!(term.state.grid.cell(0, 1).ch, '\u{4e2d}');
+        assert_eq!(term.state.grid.cell(1, 1).width, 0);
+        assert_eq!(term.cursor_pos(), (2, 1));
+    }
+
+    #[test]
+    fn test_overwriting_half_of_wide_char() {
+        let mut term = Terminal::new(10, 2);
+        // Overwrite the right half, then the left half of another.
+        term.process_bytes("\u{4e2d}\u{6587}\x1b[2Gx\x1b[3Gy".as_bytes());
+        assert_eq!(term.state.grid.row_text(0), " xy");
+        for col in 0..4 {
+            assert_eq!(term.state.grid.cell(col, 0).width, 1);
+        }
+
+        // A wide character landing on the right half of another.
+        term.process_bytes("\r\n\u{4e2d}\u{6587}\x1b[2G\u{5b57}".as_bytes());
+        assert_eq!(term.state.grid.row_text(1), " \u{5b57}");
+        assert_eq!(term.state.grid.cell(3, 1).width, 1);
+    }
+
+    #[test]
+    fn test_erase_and_shift_split_wide_chars() {
+        let wide = "a\u{4e2d}b\u{6587}c";
+        let run = |seq: &str| {
+            let mut term = Terminal::new(8, 1);
+            term.process_bytes(wide.as_bytes());
+            term.process_bytes(seq.as_bytes());
+            let widths: Vec<u8> = term.state.grid.row(0).iter().map(|c| c.width).collect();
+            (term.state.grid.row_text(0), widths)
+        };
+
+        // ECH over the right half of 中.
+        assert_eq!(run("\x1b[3G\x1b[X").0, "a  b\u{6587}c");
+        // ECH with a count running far past the last column.
+        assert_eq!(run("\x1b[5G\x1b[65535X").0, "a\u{4e2d}b");
+        // EL from the right half of 文.
+        assert_eq!(run("\x1b[6G\x1b[K").0, "a\u{4e2d}b");
+        // EL to the left half of 中.
+        assert_eq!(run("\x1b[2G\x1b[1K").0, "   b\u{6587}c");
+        // DCH starting on the right half of 中 and ending inside 文.
+        let (text, widths) = run("\x1b[3G\x1b[3P");
+        assert_eq!(text, "a  c");
+        assert!(widths.iter().all(|&w| w == 1));
+        // ICH pushing the right half of 文 off the last column.
+        let (text, widths) = run("\x1b[1G\x1b[3@");
+        assert_eq!(text, "   a\u{4e2d}b");
+        assert_eq!(widths[7], 1);
+    }
+
+    #[test]
+    fn test_render_region_cuts_wide_chars() {
+        let mut term = Terminal::new(7, 1);
+        term.process_bytes("\u{4e2d}\u{6587}\u{5b57}".as_bytes());
+        // Columns 1..4 start on the right half of 中 and end on the left
+        // half of 字.
+        let out = String::from_utf8(term.render_region(1, 0, 4, 1, 0, 0)).unwrap();
+        assert!(out.ends_with(" \u{6587} \x1b[0m"));
+    }
+
+    #[test]
+    fn test_full_width_line_then_crlf_has_no_blank_line() {
+        let mut term = Terminal::new(5, 4);
+        term.process_bytes(b"abcde\r\nfghij\r\n$ ");
+        assert_eq!(term.state.grid.row_text(0), "abcde");
+        assert_eq!(term.state.grid.row_text(1), "fghij");
+        assert_eq!(term.state.grid.row_text(2), "$");
+        assert_eq!(term.cursor_pos(), (2, 2));
+        assert!(!term.state.grid.is_wrapped(0));
+        assert_eq!(term.state.history_len(), 0);
+    }
+
+    #[test]
+    fn test_deferred_wrap() {
+        let mut term = Terminal::new(5, 3);
+        term.process_bytes(b"abcde");
+        // The cursor waits in the last column until the next character.
+        assert_eq!(term.cursor_pos(), (4, 0));
+        assert!(term.state.cursor.pending_wrap);
+
+        // SGR keeps the pending wrap; the next character wraps.
+        term.process_bytes(b"\x1b[1mf");
+        assert!(term.state.grid.is_wrapped(0));
+        assert_eq!(term.state.grid.row_text(1), "f");
+        assert_eq!(term.cursor_pos(), (1, 1));
+
+        // Cursor movement cancels it.
+        term.process_bytes(b"\x1b[1;1Hvwxyz\x1b[1;5Hq");
+        assert_eq!(term.state.grid.row_text(0), "vwxyq");
+        assert_eq!(term.cursor_pos(), (4, 0));
+        term.process_bytes(b"\rr");
+        assert_eq!(term.state.grid.row_text(0), "rwxyq");
+    }
+
+    #[test]
+    fn test_full_width_prompt_at_bottom_scrolls_once() {
+        let mut term = Terminal::new(4, 2);
+        term.process_bytes(b"$ ls\r\nabcd\r\n$ ");
+        assert_eq!(term.state.history_len(), 1);
+        assert_eq!(term.state.grid.row_text(0), "abcd");
+        assert_eq!(term.state.grid.row_text(1), "$");
+    }
+
+    #[test]
+    fn test_decawm_off_overwrites_last_column() {
+        let mut term = Terminal::new(5, 2);
+        term.process_bytes(b"\x1b[?7labcdefg");
+        assert_eq!(term.state.grid.row_text(0), "abcdg");
+        assert_eq!(term.cursor_pos(), (4, 0));
+        assert!(!term.state.grid.is_wrapped(0));
+
+        // A wide character is kept on the line.
+        term.process_bytes("\u{4e2d}".as_bytes());
+        assert_eq!(term.state.grid.row_text(0), "abc\u{4e2d}");
+
+        term.process_bytes(b"\x1b[?7h\x1b[2;1Hvwxyz1");
+        assert_eq!(term.state.history_len(), 1);
+        assert_eq!(term.state.grid.row_text(0), "vwxyz");
+        assert_eq!(term.state.grid.row_text(1), "1");
+    }
+
+    #[test]
+    fn test_decsc_saves_pending_wrap() {
+        let mut term = Terminal::new(5, 3);
+        term.process_bytes(b"abcde\x1b7\x1b[3;1H\x1b8x");
+        assert_eq!(term.state.grid.row_text(0), "abcde");
+        assert_eq!(term.state.grid.row_text(1), "x");
+    }
+
+    #[test]
+    fn test_synchronized_output_mode() {
+        let mut term = Terminal::new(10, 2);
+        term.process_bytes(b"\x1b[?2026$p");
+        assert_eq!(term.take_outgoing(), b"\x1b[?2026;2$y");
+
+        term.process_bytes(b"\x1b[?2026hframe");
+        assert!(term.modes().synchronized_output);
+        term.process_bytes(b"\x1b[?2026$p");
+        assert_eq!(term.take_outgoing(), b"\x1b[?2026;1$y");
+
+        term.process_bytes(b"\x1b[?2026l");
+        assert!(!term.modes().synchronized_output);
+    }
+
+    #[test]
+    fn test_cursor_style_and_visibility() {
+        let mut term = Terminal::new(10, 2);
+        assert_eq!(term.cursor_style(), CursorStyle::Default);
+        term.process_bytes(b"\x1b[5 q");
+        assert_eq!(term.cursor_style(), CursorStyle::BlinkingBar);
+        term.process_bytes(b"\x1b[9 q\x1b[2 q");
+        assert_eq!(term.cursor_style(), CursorStyle::SteadyBlock);
+        term.process_bytes(b"\x1b[ q");
+        assert_eq!(term.cursor_style(), CursorStyle::Default);
+
+        // Visibility is not saved with the screen.
+        term.process_bytes(b"\x1b[?25l\x1b[?1049h");
+        assert!(!term.cursor_visible());
+        term.process_bytes(b"\x1b[?25h\x1b[?1049l");
+        assert!(term.cursor_visible());
+    }
+
+    #[test]
+    fn test_bell() {
+        let mut term = Terminal::new(10, 2);
+        // BEL ending an OSC string is not a bell.
+        term.process_bytes(b"\x1b]0;title\x07ab");
+        assert!(!term.state.take_bell());
+        term.process_bytes(b"done\x07");
+        assert!(term.state.take_bell());
+        assert!(!term.state.take_bell());
+        assert_eq!(term.state.grid.row_text(0), "abdone");
+    }
+
+    #[test]
+    fn test_kitty_keyboard_flags() {
+        let mut term = Terminal::new(10, 2);
+        term.process_bytes(b"\x1b[?u");
+        assert_eq!(term.take_outgoing(), b"\x1b[?0u");
+
+        term.process_bytes(b"\x1b[>1u\x1b[>3u\x1b[?u");
+        assert_eq!(term.take_outgoing(), b"\x1b[?3u");
+        term.process_bytes(b"\x1b[=4;2u");
+        assert_eq!(term.state.keyboard_flags(), 7);
+
+        // The alternate screen has its own stack.
+        term.process_bytes(b"\x1b[?1049h");
+        assert_eq!(term.state.keyboard_flags(), 0);
+        term.process_bytes(b"\x1b[>8u\x1b[?1049l");
+        assert_eq!(term.state.keyboard_flags(), 7);
+
+        term.process_bytes(b"\x1b[<u");
+        assert_eq!(term.state.keyboard_flags(), 1);
+        term.process_bytes(b"\x1b[<5u");
+        assert_eq!(term.state.keyboard_flags(), 0);
+
+        // Without a private marker, CSI u still restores the cursor.
+        term.process_bytes(b"\x1b[2;3H\x1b[s\x1b[H\x1b[u");
+        assert_eq!(term.cursor_pos(), (2, 1));
+    }
+
+    #[test]
+    fn test_tmux_passthrough() {
+        let mut term = Terminal::new(10, 3);
+        term.process_bytes(b"ab\x1bPtmux;\x1b\x1b]2;inner\x07\x1b\x1b]9;hi\x1b\x1b\\\x1b\\cd");
+        // The wrapped sequences are not applied to the pane itself.
+        assert_eq!(term.state.title, "");
+        assert_eq!(term.state.grid.row_text(0), "abcd");
+        assert_eq!(
+            term.state.take_passthrough(),
+            vec![Passthrough {
+                data: b"\x1b]2;inner\x07\x1b]9;hi\x1b\\".to_vec(),
+                col: 2,
+                row: 0,
+            }]
+        );
+        assert!(term.state.take_passthrough().is_empty());
+
+        // Other DCS strings are consumed without output.
+        term.process_bytes(b"\x1bP$qm\x1b\\e");
+        assert_eq!(term.state.grid.row_text(0), "abcde");
+        assert!(term.state.take_passthrough().is_empty());
+    }
+
+    #[test]
+    fn test_process_until_passthrough() {
+        let mut term = Terminal::new(10, 3);
+        let input = b"ab\x1bPtmux;\x1b\x1b]9;1\x07\x1b\\cd\x1bPtmux;x\x1b\\ef";
+        let first = term.process_until_passthrough(input);
+        assert_eq!(&input[first..first + 2], b"cd");
+        assert_eq!(term.state.grid.row_text(0), "ab");
+        assert_eq!(term.state.take_passthrough().len(), 1);
+
+        let second = first + term.process_until_passthrough(&input[first..]);
+        assert_eq!(&input[second..], b"ef");
+        assert_eq!(term.state.grid.row_text(0), "abcd");
+        assert_eq!(term.state.take_passthrough()[0].col, 4);
+
+        assert_eq!(term.process_until_passthrough(&input[second..]), 2);
+        assert_eq!(term.state.grid.row_text(0), "abcdef");
+    }
+
+    #[test]
+    fn test_damage_tracking() {
+        let mut term = Terminal::new(20, 5);
+        term.process_bytes(b"one\r\ntwo\r\nthree");
+        let epoch = term.begin_damage_epoch();
+        assert!(term.damaged_rows(epoch).is_empty());
+
+        // Printing changes only the row written to; moving the cursor
+        // changes nothing.
+        term.process_bytes(b"\x1b[1;1Hx\x1b[5;3H");
+        assert_eq!(term.damaged_rows(epoch), vec![0]);
+
+        let epoch = term.begin_damage_epoch();
+        term.process_bytes(b"\x1b[2;1H\x1b[K");
+        assert_eq!(term.damaged_rows(epoch), vec![1]);
+
+        // Scrolling changes the rows of the scroll region.
+        let epoch = term.begin_damage_epoch();
+        term.process_bytes(b"\x1b[2;4r\x1b[4;1H\n\x1b[r");
+        assert_eq!(term.damaged_rows(epoch), vec![1, 2, 3]);
+
+        // Switching screens or colors changes every row.
+        let epoch = term.begin_damage_epoch();
+        term.process_bytes(b"\x1b[?1049h");
+        assert_eq!(term.damaged_rows(epoch).len(), 5);
+        let epoch = term.begin_damage_epoch();
+        term.process_bytes(b"\x1b]11;#102030\x07");
+        assert_eq!(term.damaged_rows(epoch).len(), 5);
+    }
+
+    #[test]
+    fn test_damaged_rows_render_less() {
+        let mut term = Terminal::new(200, 60);
+        for row in 0..60 {
+            let line = format!("\x1b[{};1Hline {} {}", row + 1, row, "x".repeat(180));
+            term.process_bytes(line.as_bytes());
+        }
+        let epoch = term.begin_damage_epoch();
+        term.process_bytes(b"\x1b[30;1Hchanged");
+
+        let damaged = term.damaged_rows(epoch);
+        assert_eq!(damaged, vec![29]);
+        let full = term.render_region(0, 0, 200, 60, 0, 0);
+        let partial: Vec<u8> = damaged
+            .into_iter()
+            .flat_map(|row| term.render_region(0, row, 200, 1, 0, row))
+            .collect();
+        assert!(partial.len() * 50 < full.len());
+    }
 }